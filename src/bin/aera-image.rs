@@ -0,0 +1,239 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::process;
+use aera::r_code::atom;
+use aera::r_code::code;
+use aera::r_code::image::LEGACY_FORMAT_VERSION;
+use aera::r_code::SysObject;
+use aera::r_comp::Image;
+
+const USAGE: &str = "\
+Usage: aera-image <command> <image file> [options]
+Commands:
+  info <image>                         Show the segment sizes, object count per class and the
+                                       opcode table.
+  dump <image> [--oid N] [--class C]   Trace each object, optionally only the object with OID N
+                                       or the objects of class C.
+  validate <image>                     Check the structure of every object and reference.
+  refs <image> <oid>                   Show the objects which reference the object with the OID.
+  migrate <image> <defs image> <output>
+                                       Change the opcodes of the image to those of the classes in
+                                       the definition segment of defs image, and write output.
+  link <output> <image>...             Link the images into one output image.";
+
+fn usage_error(message: &str) -> ! {
+    eprintln!("{}\n{}", message, USAGE);
+    process::exit(2);
+}
+
+fn parse_oid(value: &str) -> u32 {
+    value.parse().unwrap_or_else(|_| usage_error(&format!("Invalid OID: {}", value)))
+}
+
+/**
+ * Return the name of the class of the object from its head opcode, or "unknown".
+ */
+fn class_name(image: &Image, object: &SysObject) -> String {
+    let classes = &image.definition_segment_.classes_by_opcodes_;
+    object.code_.first()
+        .and_then(|head| classes.get(head.asOpcode() as usize))
+        .map(|class| class.str_opcode_.clone())
+        .unwrap_or_else(|| String::from("unknown"))
+}
+
+/**
+ * Return the OID followed by the object name if it has one, like "42 (hand)".
+ */
+fn oid_label(image: &Image, oid: u32) -> String {
+    match image.object_names_.get_name(oid) {
+        Some(name) => format!("{} ({})", oid, name),
+        None => format!("{}", oid),
+    }
+}
+
+fn info(image: &Image, raw_image: &aera::r_code::Image) {
+    if raw_image.format_version() == LEGACY_FORMAT_VERSION {
+        println!("Format: legacy (no header)");
+    }
+    else {
+        println!("Format version: {}", raw_image.format_version());
+        println!("Opcode table hash: {:#010x}", raw_image.opcode_table_hash());
+    }
+    println!("Timestamp: {}us", raw_image.timestamp());
+    println!("Segment sizes (words):");
+    println!("  definition: {}", raw_image.def_size());
+    println!("  object map: {}", raw_image.map_size());
+    println!("  code:       {}", raw_image.code_size());
+    println!("  relocation: {}", raw_image.reloc_size());
+    println!("  names:      {}", raw_image.names_size());
+    println!("Objects: {}", image.get_object_count());
+
+    let mut counts: BTreeMap<String, usize> = BTreeMap::new();
+    for object in &image.code_segment_.objects_ {
+        *counts.entry(class_name(image, object)).or_insert(0) += 1;
+    }
+    for (name, count) in &counts {
+        println!("  {}: {}", name, count);
+    }
+
+    println!("Opcodes:");
+    for (opcode, class) in image.definition_segment_.classes_by_opcodes_.iter().enumerate() {
+        println!("  {}\t{}", opcode, class.str_opcode_);
+    }
+}
+
+fn dump(image: &Image, args: &[String]) {
+    let mut oid_filter = None;
+    let mut class_filter = None;
+    let mut i = 0;
+    while i < args.len() {
+        match (args[i].as_str(), args.get(i + 1)) {
+            ("--oid", Some(value)) => oid_filter = Some(parse_oid(value)),
+            ("--class", Some(value)) => class_filter = Some(value.clone()),
+            _ => usage_error(&format!("Invalid dump option: {}", args[i])),
+        }
+        i += 2;
+    }
+
+    atom::set_opcode_names(&image.definition_segment_.get_opcode_names());
+    let objects = image.get_objects().unwrap_or_else(|error| {
+        eprintln!("{}", error);
+        process::exit(1);
+    });
+
+    for (object, sys_object) in objects.iter().zip(image.code_segment_.objects_.iter()) {
+        if oid_filter.is_some_and(|oid| oid != sys_object.oid_) ||
+           class_filter.as_ref().is_some_and(|name| *name != class_name(image, sys_object)) {
+            continue;
+        }
+        let mut out = String::new();
+        code::trace_out(&*object.borrow(), &mut out, &image.object_names_);
+        print!("{}", out);
+    }
+}
+
+fn validate(image: &Image) -> bool {
+    let problems = image.validate();
+    for problem in &problems {
+        println!("{}", problem);
+    }
+    if problems.is_empty() {
+        println!("OK: {} objects", image.get_object_count());
+    }
+    else {
+        println!("{} problems", problems.len());
+    }
+    problems.is_empty()
+}
+
+fn refs(image: &Image, oid: u32) {
+    let objects = &image.code_segment_.objects_;
+    let target = match objects.iter().position(|object| object.oid_ == oid) {
+        Some(target) => target,
+        None => {
+            eprintln!("No object with OID {}", oid);
+            process::exit(1);
+        }
+    };
+
+    println!("Objects referencing {}:", oid_label(image, oid));
+    for object in objects {
+        for (i, reference) in object.references_.iter().enumerate() {
+            if *reference as usize == target {
+                println!("  {} {} reference {}", oid_label(image, object.oid_),
+                         class_name(image, object), i);
+            }
+        }
+        for (v, view) in object.views_.iter().enumerate() {
+            for (i, reference) in view.references_.iter().enumerate() {
+                if *reference as usize == target {
+                    println!("  {} {} view {} reference {}", oid_label(image, object.oid_),
+                             class_name(image, object), v, i);
+                }
+            }
+        }
+    }
+}
+
+/**
+ * Migrate the image to the definition segment of the image at definitions_path and write the
+ * result to the output file.
+ */
+fn migrate(mut image: Image, definitions_path: &str, output: &str) {
+    let definitions = Image::read_file(definitions_path).unwrap_or_else(|error| {
+        eprintln!("{}: {}", definitions_path, error);
+        process::exit(1);
+    });
+    if let Err(error) = image.migrate(&definitions.definition_segment_) {
+        eprintln!("{}", error);
+        process::exit(1);
+    }
+    if let Err(error) = image.serialize().write_file(output) {
+        eprintln!("{}: {}", output, error);
+        process::exit(1);
+    }
+    println!("Migrated {} objects into {}", image.get_object_count(), output);
+}
+
+/**
+ * Read each image, link them in order and write the result to the output file.
+ */
+fn link(output: &str, image_paths: &[String]) {
+    let images: Vec<Image> = image_paths.iter().map(|path| {
+        Image::read_file(path).unwrap_or_else(|error| {
+            eprintln!("{}: {}", path, error);
+            process::exit(1);
+        })
+    }).collect();
+    let mut linked = aera::r_comp::link(&images).unwrap_or_else(|error| {
+        eprintln!("{}", error);
+        process::exit(1);
+    });
+    if let Err(error) = linked.serialize().write_file(output) {
+        eprintln!("{}: {}", output, error);
+        process::exit(1);
+    }
+    println!("Linked {} objects into {}", linked.get_object_count(), output);
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() < 3 {
+        usage_error("Missing command or image file");
+    }
+    let command = args[1].as_str();
+    if command == "link" {
+        if args.len() < 4 {
+            usage_error("Missing image files to link");
+        }
+        link(&args[2], &args[3..]);
+        return;
+    }
+    let raw_image = aera::r_code::Image::read_file(&args[2]).unwrap_or_else(|error| {
+        eprintln!("{}: {}", args[2], error);
+        process::exit(1);
+    });
+    let image = Image::load(&raw_image).unwrap_or_else(|error| {
+        eprintln!("{}: {}", args[2], error);
+        process::exit(1);
+    });
+
+    match command {
+        "info" => info(&image, &raw_image),
+        "dump" => dump(&image, &args[3..]),
+        "validate" => {
+            if !validate(&image) {
+                process::exit(1);
+            }
+        },
+        "refs" => match args.get(3) {
+            Some(oid) => refs(&image, parse_oid(oid)),
+            None => usage_error("Missing OID for refs"),
+        },
+        "migrate" => match (args.get(3), args.get(4)) {
+            (Some(definitions_path), Some(output)) => migrate(image, definitions_path, output),
+            _ => usage_error("Missing definitions image or output for migrate"),
+        },
+        _ => usage_error(&format!("Unknown command: {}", command)),
+    }
+}
@@ -1,7 +1,44 @@
+use std::convert::TryFrom;
+use std::fmt;
 use std::ops::{Add, Sub};
+use std::str::FromStr;
 use super::UTimestamp;
 
-#[derive(Default, PartialEq, PartialOrd)]
+/**
+ * A ParseTimeError is returned when a duration or timestamp string can't be parsed.
+ */
+#[derive(Clone, Debug, PartialEq)]
+pub enum ParseTimeError {
+    // The string is not in a form like 150us, 20ms, 3s or -1s:200ms:0us.
+    Invalid(String),
+    // The value does not fit in 64-bit microseconds.
+    Overflow(String),
+}
+
+impl fmt::Display for ParseTimeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseTimeError::Invalid(text) => write!(f, "Invalid time {:?}", text),
+            ParseTimeError::Overflow(text) => write!(f, "The time {:?} is out of range", text),
+        }
+    }
+}
+
+impl std::error::Error for ParseTimeError {}
+
+/**
+ * Return the number of microseconds in one of the unit, or None if it is not a time unit.
+ */
+fn unit_microseconds(unit: &str) -> Option<i128> {
+    match unit {
+        "s" => Some(1000000),
+        "ms" => Some(1000),
+        "us" => Some(1),
+        _ => None,
+    }
+}
+
+#[derive(Default, Clone, Copy, Debug, PartialEq, PartialOrd)]
 pub struct UDuration {
     useconds_: i64,
 }
@@ -32,6 +69,49 @@ impl UDuration {
     }
 }
 
+/**
+ * Parse a duration in the form printed by Utils::to_string_us like 150us, 20ms or 3s, or in
+ * the form printed by Utils::to_string_s_ms_us like 1s:200ms:0us, with an optional minus sign.
+ * The parts of a combination like 1s:20ms have decreasing units, and each part after the first
+ * is less than 1000.
+ */
+impl FromStr for UDuration {
+    type Err = ParseTimeError;
+
+    fn from_str(text: &str) -> Result<Self, ParseTimeError> {
+        let invalid = || ParseTimeError::Invalid(text.to_string());
+        let (is_negative, unsigned_text) = match text.strip_prefix('-') {
+            Some(unsigned_text) => (true, unsigned_text),
+            None => (false, text),
+        };
+
+        let mut total: i128 = 0;
+        let mut previous_unit: Option<i128> = None;
+        for part in unsigned_text.split(':') {
+            let digits_end = part.find(|c: char| !c.is_ascii_digit()).ok_or_else(invalid)?;
+            let (digits, unit) = part.split_at(digits_end);
+            if digits.is_empty() {
+                return Err(invalid());
+            }
+            let unit = unit_microseconds(unit).ok_or_else(invalid)?;
+            // Too many digits for any i64 is an overflow, not an invalid form.
+            let value = digits.parse::<i128>()
+                .map_err(|_| ParseTimeError::Overflow(text.to_string()))?;
+            if let Some(previous_unit) = previous_unit {
+                if unit >= previous_unit || value >= 1000 {
+                    return Err(invalid());
+                }
+            }
+            previous_unit = Some(unit);
+            total = value.checked_mul(unit).and_then(|value| total.checked_add(value))
+                .ok_or_else(|| ParseTimeError::Overflow(text.to_string()))?;
+        }
+
+        let total = if is_negative { -total } else { total };
+        i64::try_from(total).map(Self::new).map_err(|_| ParseTimeError::Overflow(text.to_string()))
+    }
+}
+
 impl Add<UDuration> for UDuration {
     type Output = UDuration;
 
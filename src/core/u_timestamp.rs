@@ -1,8 +1,8 @@
 use std::ops::{Add, Sub};
 use super::UDuration;
-use super::u_duration::microseconds;
+use super::u_duration::{microseconds, ParseTimeError};
 
-#[derive(Default, PartialEq, PartialOrd)]
+#[derive(Default, Clone, Copy, Debug, PartialEq, PartialOrd)]
 pub struct UTimestamp {
     useconds_: i64,
 }
@@ -19,6 +19,22 @@ impl UTimestamp {
     pub fn time_since_epoch(&self) -> UDuration {
         microseconds(self.useconds_)
     }
+
+    /**
+     * Parse a timestamp relative to the time reference in the form printed by
+     * Utils::to_string_s_ms_us like -1s:200ms:0us, or any duration form of UDuration::from_str.
+     * \param text The string to parse.
+     * \param time_reference The time which is added to the parsed duration, usually the session
+     * start time.
+     * \return The timestamp, or a ParseTimeError if the string is invalid or the timestamp is out
+     * of range.
+     */
+    pub fn parse_relative(text: &str, time_reference: UTimestamp)
+      -> Result<UTimestamp, ParseTimeError> {
+        let duration: UDuration = text.parse()?;
+        time_reference.useconds_.checked_add(duration.as_microseconds()).map(Self::new)
+            .ok_or_else(|| ParseTimeError::Overflow(text.to_string()))
+    }
 }
 
 impl Add<UDuration> for UTimestamp {
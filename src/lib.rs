@@ -1,2 +1,3 @@
 pub mod core;
 pub mod r_code;
+pub mod r_comp;
@@ -70,7 +70,9 @@ pub const MODEL: u8 = 0xCE;
 pub const NULL_PROGRAM: u8 = 0xCF;
 pub const DURATION : u8 = 0xD0;
 
+// The layout is the same as u32 so that the words of a mapped image can be used as atoms.
 #[derive(Copy, Clone)]
+#[repr(transparent)]
 pub struct Atom {
     pub atom_: u32,
 }
@@ -236,6 +238,7 @@ impl Atom {
         Self::new(0xA2FFFFFF)
     }
 
+    #[allow(clippy::manual_is_multiple_of)]
     pub fn String(character_count: u8) -> Self {
         let mut blocks: u8 = character_count / 4;
         if character_count % 4 != 0 {
@@ -341,7 +344,7 @@ impl Atom {
     }
 
     pub fn asBoolean(&self) -> bool {
-        if self.atom_ & 0x000000FF != 0 { true } else { false }
+        self.atom_ & 0x000000FF != 0
     }
 
     pub fn isBooleanTrue(&self) -> bool { self.getDescriptor() == BOOLEAN_ && self.asBoolean() }
@@ -416,7 +419,7 @@ impl Atom {
 
     // applicable to NULL_PROGRAM.
     pub fn takesPastInputs(&self) -> bool {
-        if self.atom_ & 0x00000001 != 0 { true } else { false }
+        self.atom_ & 0x00000001 != 0
     }
 
     // asRawPointer is not used. See RawPointer above.
@@ -572,15 +575,12 @@ impl Atom {
                     context.string_data_ -= 1;
                     let mut s = String::new();
                     let content = self.atom_.to_le_bytes();
-                    for i in 0..4 {
-                        let have_more = context.char_count_ > 0;
-                        context.char_count_ -= 1;
-                        if have_more {
-                            s.push(content[i] as char);
-                        }
-                        else {
+                    for c in content.iter() {
+                        if context.char_count_ == 0 {
                             break;
                         }
+                        context.char_count_ -= 1;
+                        s.push(*c as char);
                     }
                     write!(out, "{}", s).unwrap();
                 } else if self.isFloat() {
@@ -606,10 +606,7 @@ impl Default for Atom {
  * \return True for success, false if the opcode names have already been set.
  */
  pub fn set_opcode_names(opcode_names: &HashMap<u16, String>) -> bool {
-    match OPCODE_NAMES.set(opcode_names.clone()) {
-        Ok(_) => true,
-        Err(_) => false,
-    }
+    OPCODE_NAMES.set(opcode_names.clone()).is_ok()
 }
 
 fn get_opcode_name(opcode: u16) -> String {
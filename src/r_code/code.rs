@@ -1,18 +1,21 @@
-use std::rc::Rc;
+use std::rc::{Rc, Weak};
 use std::cell::RefCell;
 use std::fmt::Write;
 use super::atom;
 use super::atom::Atom;
+use super::object_names::ObjectNames;
+use super::sys_object::{SysObject, SysView};
+use super::view::View;
 
 //const NULL_STORAGE_INDEX: isize = -1;
 //const CODE_MARKERS_INITIAL_SIZE: usize = 8;
 
 pub trait Code {
-    #[cfg(with_detail_oid)]
-    // Compile with: RUSTFLAGS='--cfg with_detail_oid' cargo build
+    #[cfg(feature = "with_detail_oid")]
+    // Compile with: cargo build --features with_detail_oid
     fn get_detail_oid(&self) -> u64;
 
-    #[cfg(with_detail_oid)]
+    #[cfg(feature = "with_detail_oid")]
     /**
      * Set this object's detail OID and also set the static last_detail_oid
      * so that the next detail OID will be higher than this one.
@@ -23,68 +26,452 @@ pub trait Code {
 /* TODO: Implement
     int32 storage_index_; // -1: not stored; >= 0: index of the object in a vector-based container.
 
-    void load(SysObject *source) {
-  
-      for (uint16 i = 0; i < source->code_.size(); ++i)
-        code(i) = source->code_[i];
-      set_oid(source->oid_);
-    }
-    template<class V> _View *build_view(SysView *source) {
-  
-      return new V(source, this);
-    }
-
     void set_strorage_index(int32 i) { storage_index_ = i; }
     bool is_registered() const { return storage_index_ > null_storage_index; }
     int32 get_storage_index() const { return storage_index_; }
 */
 
+    /**
+     * Set the code and OID of this object from the source. The references of source are indexes
+     * in an image, so the caller must resolve them and call set_reference.
+     * \param source The persisted object.
+     */
+    fn load(&mut self, source: &SysObject) {
+        for (i, a) in source.code_.iter().enumerate() {
+            self.set_code(i as u16, *a);
+        }
+        self.set_oid(source.oid_);
+    }
+
     fn get_oid(&self) -> u32;
     fn set_oid(&mut self, oid: u32);
 
     fn code(&self, i: u16) -> Atom;
+    /**
+     * Set code(i) to the atom, resizing the code if needed. This panics if is_compact(), so
+     * code which may get a compact object should call try_set_code.
+     */
     fn set_code(&mut self, i: u16, a: Atom);
     fn code_size(&self) -> u16;
+    /**
+     * Resize the code. This panics if is_compact().
+     */
     fn resize_code(&mut self, new_size: u16);
+
+    /**
+     * Call set_code unless the code of this object can't change.
+     * \return True if the code was set, false if is_compact() so that the code is not changed.
+     */
+    fn try_set_code(&mut self, i: u16, a: Atom) -> bool {
+        if self.is_compact() {
+            return false;
+        }
+        self.set_code(i, a);
+        true
+    }
+    /**
+     * Set reference i to the object. If i is references_size(), this appends the reference if
+     * the implementation can grow its references.
+     */
     fn set_reference(&mut self, i: u16, object: &Rc<RefCell<dyn Code>>);
     fn get_reference(&self, i: u16) -> Rc<RefCell<dyn Code>>;
+
+    /**
+     * Return reference i, or None if i is not less than references_size() or the reference is
+     * not resolved yet, as after CompactObject::load.
+     */
+    fn try_get_reference(&self, i: u16) -> Option<Rc<RefCell<dyn Code>>> {
+        if i < self.references_size() { Some(self.get_reference(i)) } else { None }
+    }
+
     fn references_size(&self) -> u16;
     fn clear_references(&mut self);
-/* TODO: Implement
-  virtual void set_references(std::vector<P<Code> > &new_references) = 0;
 
-  virtual bool is_compact() const { return false; }
-  virtual bool is_invalidated() { return false; }
-  virtual bool invalidate() { return false; }
+    /**
+     * Return the markers which reference this object and which are still alive.
+     * The C++ version uses acq_markers and rel_markers to lock markers_. Here the RefCell
+     * which holds the object serves as the lock.
+     */
+    fn markers(&self) -> Vec<Rc<RefCell<dyn Code>>>;
+
+    /**
+     * Add the marker to the markers of this object. This holds a weak reference so that the
+     * marker is automatically removed when it is deleted. Usually this is called by
+     * set_reference (the module function) when a marker's reference is set.
+     * \param marker The marker. If it is already a marker of this object, do nothing.
+     */
+    fn add_marker(&mut self, marker: &Rc<RefCell<dyn Code>>);
+
+    /**
+     * Remove the marker from the markers of this object.
+     * \param marker The marker. If it is not a marker of this object, do nothing.
+     */
+    fn remove_marker(&mut self, marker: &Rc<RefCell<dyn Code>>);
+
+    /**
+     * Create a View of this object from the persisted source. The caller must resolve the
+     * references of the view and add it with add_view.
+     * \param source The persisted view.
+     * \return The new View.
+     */
+    fn build_view(&self, source: &SysView) -> View {
+        View::from_sys_view(source)
+    }
+
+    /**
+     * Add the view to the views of this object, indexed by its host group. This replaces an
+     * existing view with the same host.
+     * \param view The view, which must have its host reference assigned.
+     * \return True for success, false if the view doesn't have a host.
+     */
+    fn add_view(&mut self, view: View) -> bool;
+
+    /**
+     * Return the view of this object in the group, or None if the object is not in the group.
+     * The C++ version uses acq_views and rel_views to lock views_. Here the RefCell which holds
+     * the object serves as the lock.
+     */
+    fn get_view(&self, group: &Rc<RefCell<dyn Code>>) -> Option<&View>;
+
+    fn get_view_mut(&mut self, group: &Rc<RefCell<dyn Code>>) -> Option<&mut View>;
+
+    /**
+     * Remove and return the view of this object in the group.
+     */
+    fn remove_view(&mut self, group: &Rc<RefCell<dyn Code>>) -> Option<View>;
+
+    /**
+     * Return all the views of this object, in no particular order.
+     */
+    fn views(&self) -> Vec<&View>;
+
+    fn clear_views(&mut self);
+    /**
+     * Return true if this object has been invalidated.
+     */
+    fn is_invalidated(&self) -> bool;
 
-  r_code::list<Code *> markers_;
-  std::unordered_set<_View *, _View::Hash, _View::Equal> views_; // indexed by groups.
+    /**
+     * Mark this object as invalidated and remove all its views, so that it is no longer in any
+     * group. This does not cascade to the markers of this object. To invalidate an object held in
+     * an Rc, use invalidate (the module function) which also invalidates the markers and notifies
+     * the invalidation observers.
+     * \return True if this object was already invalidated, false if it is newly invalidated.
+     */
+    fn invalidate(&mut self) -> bool;
 
-  virtual _View *build_view(SysView *source) = 0;
+    /**
+     * Return the propagation of saliency threshold, which is the last member of the object, or 1
+     * if the last member is not a float.
+     */
+    fn get_psln_thr(&self) -> f32 {
+        if self.code_size() == 0 {
+            return 1.0;
+        }
+        let a = self.code(self.code_size() - 1);
+        if a.isFloat() { a.asFloat() } else { 1.0 }
+    }
 
-  virtual void acq_views() {}
-  virtual void rel_views() {}
-  virtual void acq_markers() {}
-  virtual void rel_markers() {}
+    /**
+     * Add value to the member, as done by the _mod executive command. Only the propagation of
+     * saliency threshold (the last member) can be modified, and the result is clamped to [0, 1].
+     * To notify the modification observers, use mod_member (the module function).
+     * (The C++ name is mod, which is a keyword in Rust.)
+     * \param member_index The index of the member in the code.
+     * \param value The value to add.
+     * \return The new value, or None if the member can't be modified or is_compact().
+     */
+    fn mod_member(&mut self, member_index: u16, value: f32) -> Option<f32> {
+        if self.is_compact() || self.code_size() == 0 || member_index != self.code_size() - 1 ||
+           !self.code(member_index).isFloat() {
+            return None;
+        }
+        let new_value = clamp_unit(self.code(member_index).asFloat() + value);
+        self.set_code(member_index, Atom::Float(new_value));
+        Some(new_value)
+    }
+
+    /**
+     * Set the member to value, as done by the _set executive command. Only the propagation of
+     * saliency threshold (the last member) can be set, and the value is clamped to [0, 1].
+     * To notify the modification observers, use set_member (the module function).
+     * \param member_index The index of the member in the code.
+     * \param value The new value.
+     * \return The new value, or None if the member can't be set or is_compact().
+     */
+    fn set_member(&mut self, member_index: u16, value: f32) -> Option<f32> {
+        if self.is_compact() || self.code_size() == 0 || member_index != self.code_size() - 1 ||
+           !self.code(member_index).isFloat() {
+            return None;
+        }
+        let new_value = clamp_unit(value);
+        self.set_code(member_index, Atom::Float(new_value));
+        Some(new_value)
+    }
 
-  virtual float32 get_psln_thr() { return 1; }
+    /**
+     * Return true if this object's code and references never change after creation. (See
+     * CompactObject.)
+     */
+    fn is_compact(&self) -> bool {
+        false
+    }
+/* TODO: Implement
+  virtual void set_references(std::vector<P<Code> > &new_references) = 0;
 
   Code() : storage_index_(null_storage_index) { markers_.reserve(CodeMarkersInitialSize); }
   virtual ~Code() {}
 
-  virtual void mod(uint16 member_index, float32 value) {};
-  virtual void set(uint16 member_index, float32 value) {};
-  virtual _View *get_view(Code *group, bool lock) { return NULL; }
   virtual void add_reference(Code *object) const {} // called only on local objects.
-  void remove_marker(Code *m) {
-
-    acq_markers();
-    markers_.remove(m);
-    rel_markers();
-  }
 */
 }
 
+/**
+ * Return an address which identifies the object, independent of the dyn Code vtable.
+ */
+pub fn object_address(object: &Rc<RefCell<dyn Code>>) -> usize {
+    Rc::as_ptr(object) as *const () as usize
+}
+
+/**
+ * Return true if a and b are the same object.
+ */
+pub fn same_object(a: &Rc<RefCell<dyn Code>>, b: &Rc<RefCell<dyn Code>>) -> bool {
+    object_address(a) == object_address(b)
+}
+
+/**
+ * Add the marker to the weak markers list of a Code implementation, if it is not already there.
+ * Also remove deleted markers.
+ */
+pub(crate) fn add_weak_marker(
+  markers: &mut Vec<Weak<RefCell<dyn Code>>>, marker: &Rc<RefCell<dyn Code>>) {
+    markers.retain(|m| m.strong_count() > 0);
+    let address = object_address(marker);
+    if !markers.iter().any(|m| m.as_ptr() as *const () as usize == address) {
+        markers.push(Rc::downgrade(marker));
+    }
+}
+
+/**
+ * Remove the marker and deleted markers from the weak markers list of a Code implementation.
+ */
+pub(crate) fn remove_weak_marker(
+  markers: &mut Vec<Weak<RefCell<dyn Code>>>, marker: &Rc<RefCell<dyn Code>>) {
+    let address = object_address(marker);
+    markers.retain(|m| m.strong_count() > 0 && m.as_ptr() as *const () as usize != address);
+}
+
+/**
+ * Return true if code(0) of the object is a MARKER atom.
+ */
+pub fn is_marker(object: &dyn Code) -> bool {
+    object.code_size() > 0 && object.code(0).getDescriptor() == atom::MARKER
+}
+
+/**
+ * Set reference i of the object. If the object is a marker, also remove it from the markers of
+ * the object previously at reference i and add it to the markers of the new reference. Use this
+ * instead of Code::set_reference so that the markers of the referenced objects are maintained.
+ * \param object The object whose reference is set.
+ * \param i The index in the references of object.
+ * \param reference The referenced object.
+ */
+pub fn set_reference(object: &Rc<RefCell<dyn Code>>, i: u16, reference: &Rc<RefCell<dyn Code>>) {
+    let object_is_marker = is_marker(&*object.borrow());
+    let previous = if object_is_marker { object.borrow().try_get_reference(i) } else { None };
+    if let Some(previous) = previous {
+        if !same_object(&previous, object) {
+            previous.borrow_mut().remove_marker(object);
+        }
+    }
+
+    object.borrow_mut().set_reference(i, reference);
+    // A marker which references itself is not added to its own markers, which would need a
+    // second borrow.
+    if object_is_marker && !same_object(reference, object) {
+        reference.borrow_mut().add_marker(object);
+    }
+}
+
+/**
+ * Return the markers of the object whose code(0) has the given opcode, for example all the mk.val
+ * markers of a fact. This skips invalidated markers.
+ * \param object The marked object.
+ * \param opcode The opcode of the markers' class.
+ * \return The live, valid markers with the opcode.
+ */
+pub fn markers_of(object: &Rc<RefCell<dyn Code>>, opcode: u16) -> Vec<Rc<RefCell<dyn Code>>> {
+    object.borrow().markers().into_iter()
+        .filter(|marker| {
+            let marker_ref = marker.borrow();
+            !marker_ref.is_invalidated() && marker_ref.code(0).asOpcode() == opcode
+        })
+        .collect()
+}
+
+/**
+ * An invalidation observer is called with each object which is newly invalidated by invalidate.
+ */
+pub type InvalidationObserver = Rc<dyn Fn(&Rc<RefCell<dyn Code>>)>;
+
+thread_local! {
+    static INVALIDATION_OBSERVERS: RefCell<Vec<InvalidationObserver>> = RefCell::new(vec![]);
+}
+
+/**
+ * Add an observer to be called each time invalidate newly invalidates an object, including the
+ * markers invalidated by the cascade. When the observer is called, the object is not borrowed.
+ * Observers are kept per thread since objects are not shared between threads.
+ * \param observer The observer. It may check the object's OID to recognize an object which the
+ * host injected.
+ */
+pub fn add_invalidation_observer(observer: InvalidationObserver) {
+    INVALIDATION_OBSERVERS.with(|observers| observers.borrow_mut().push(observer));
+}
+
+/**
+ * Remove all the invalidation observers of this thread.
+ */
+pub fn clear_invalidation_observers() {
+    INVALIDATION_OBSERVERS.with(|observers| observers.borrow_mut().clear());
+}
+
+/**
+ * Invalidate the object (see Code::invalidate) if it isn't already invalidated. If the object is
+ * a marker, remove it from the markers of the objects it references. Then invalidate the markers
+ * of the object, so that for example the mk.val markers of an invalidated fact are also
+ * invalidated. Finally, call the invalidation observers for the object.
+ * \param object The object to invalidate.
+ * \return True if the object was already invalidated, false if it is newly invalidated.
+ */
+pub fn invalidate(object: &Rc<RefCell<dyn Code>>) -> bool {
+    if object.borrow_mut().invalidate() {
+        return true;
+    }
+
+    if is_marker(&*object.borrow()) {
+        let references: Vec<Rc<RefCell<dyn Code>>> = {
+            let object_ref = object.borrow();
+            (0..object_ref.references_size()).map(|i| object_ref.get_reference(i)).collect()
+        };
+        for reference in references.iter().filter(|r| !same_object(r, object)) {
+            reference.borrow_mut().remove_marker(object);
+        }
+    }
+
+    let markers = object.borrow().markers();
+    for marker in markers.iter().filter(|m| !same_object(m, object)) {
+        invalidate(marker);
+    }
+
+    // Copy the observers so that an observer can add observers or invalidate other objects.
+    let observers = INVALIDATION_OBSERVERS.with(|observers| observers.borrow().clone());
+    for observer in observers {
+        observer(object);
+    }
+
+    false
+}
+
+/**
+ * Return the value clamped to [0, 1], which is the range of saliency, activation and thresholds.
+ */
+pub fn clamp_unit(value: f32) -> f32 {
+    value.clamp(0.0, 1.0)
+}
+
+/**
+ * A modification observer is called each time mod_member, set_member, mod_view_member or
+ * set_view_member changes a member. The arguments are the object, the host group if the member is
+ * in the object's view in that group (otherwise None), the member index and the new value.
+ */
+pub type ModificationObserver =
+    Rc<dyn Fn(&Rc<RefCell<dyn Code>>, Option<&Rc<RefCell<dyn Code>>>, u16, f32)>;
+
+thread_local! {
+    static MODIFICATION_OBSERVERS: RefCell<Vec<ModificationObserver>> = RefCell::new(vec![]);
+}
+
+/**
+ * Add an observer to be called after each member modification, for example to re-evaluate the
+ * thresholds which depend on it. When the observer is called, the object is not borrowed.
+ * \param observer The observer.
+ */
+pub fn add_modification_observer(observer: ModificationObserver) {
+    MODIFICATION_OBSERVERS.with(|observers| observers.borrow_mut().push(observer));
+}
+
+/**
+ * Remove all the modification observers of this thread.
+ */
+pub fn clear_modification_observers() {
+    MODIFICATION_OBSERVERS.with(|observers| observers.borrow_mut().clear());
+}
+
+fn notify_modification(
+  object: &Rc<RefCell<dyn Code>>, group: Option<&Rc<RefCell<dyn Code>>>, member_index: u16,
+  new_value: Option<f32>) -> Option<f32> {
+    if let Some(value) = new_value {
+        // Copy the observers so that an observer can modify other members.
+        let observers = MODIFICATION_OBSERVERS.with(|observers| observers.borrow().clone());
+        for observer in observers {
+            observer(object, group, member_index, value);
+        }
+    }
+    new_value
+}
+
+/**
+ * Call Code::mod_member on the object and notify the modification observers.
+ * \return The new value, or None if the member can't be modified or the object is compact.
+ */
+pub fn mod_member(object: &Rc<RefCell<dyn Code>>, member_index: u16, value: f32) -> Option<f32> {
+    if object.borrow().is_compact() {
+        return None;
+    }
+    let new_value = object.borrow_mut().mod_member(member_index, value);
+    notify_modification(object, None, member_index, new_value)
+}
+
+/**
+ * Call Code::set_member on the object and notify the modification observers.
+ * \return The new value, or None if the member can't be set or the object is compact.
+ */
+pub fn set_member(object: &Rc<RefCell<dyn Code>>, member_index: u16, value: f32) -> Option<f32> {
+    if object.borrow().is_compact() {
+        return None;
+    }
+    let new_value = object.borrow_mut().set_member(member_index, value);
+    notify_modification(object, None, member_index, new_value)
+}
+
+/**
+ * Call View::mod_member on the object's view in the group and notify the modification observers.
+ * \return The new value, or None if the object has no view in the group or the member can't be
+ * modified.
+ */
+pub fn mod_view_member(
+  object: &Rc<RefCell<dyn Code>>, group: &Rc<RefCell<dyn Code>>, member_index: u16,
+  value: f32) -> Option<f32> {
+    let new_value = object.borrow_mut().get_view_mut(group)
+        .and_then(|view| view.mod_member(member_index, value));
+    notify_modification(object, Some(group), member_index, new_value)
+}
+
+/**
+ * Call View::set_member on the object's view in the group and notify the modification observers.
+ * \return The new value, or None if the object has no view in the group or the member can't be
+ * set.
+ */
+pub fn set_view_member(
+  object: &Rc<RefCell<dyn Code>>, group: &Rc<RefCell<dyn Code>>, member_index: u16,
+  value: f32) -> Option<f32> {
+    let new_value = object.borrow_mut().get_view_mut(group)
+        .and_then(|view| view.set_member(member_index, value));
+    notify_modification(object, Some(group), member_index, new_value)
+}
+
 pub trait CodeTrace {
     /**
      * Print the trace of code(i) to the out stream, using the given TraceContext.
@@ -92,7 +479,8 @@ pub trait CodeTrace {
     fn trace_at(&self, i: u16, out: &mut impl Write, context: &mut atom::TraceContext);
 
     /**
-     * Print the trace of this Code to the out stream.
+     * Print the trace of this Code to the out stream. To print the names of referenced objects,
+     * call code::trace_out with the object names.
      */
     fn trace_out(&self, out: &mut impl Write);
 
@@ -114,14 +502,24 @@ pub trait CodeTrace {
     }
 }
 
-pub fn trace_at(code: &impl Code, i: u16, out: &mut impl Write, context: &mut atom::TraceContext) {
+/**
+ * Print the trace of code(i) to the out stream. An R_PTR is followed by the OID of the referenced
+ * object, and its name if it has one in object_names, like -> 42 (hand).
+ */
+pub fn trace_at(
+  code: &(impl Code + ?Sized), i: u16, out: &mut impl Write, context: &mut atom::TraceContext,
+  object_names: &ObjectNames) {
     let a = code.code(i);
     a.trace(context, out);
     if a.getDescriptor() == atom::R_PTR {
         if a.asIndex() < code.references_size() {
-            write!(out, " -> {}", code.get_reference(a.asIndex()).borrow().get_oid()).unwrap();
-            #[cfg(with_detail_oid)]
+            let oid = code.get_reference(a.asIndex()).borrow().get_oid();
+            write!(out, " -> {}", oid).unwrap();
+            #[cfg(feature = "with_detail_oid")]
             write!(out, "({})", code.get_reference(a.asIndex()).borrow().get_detail_oid()).unwrap();
+            if let Some(name) = object_names.get_name(oid) {
+                write!(out, " ({})", name).unwrap();
+            }
         }
         else {
             write!(out, " (unassigned) ").unwrap();
@@ -129,16 +527,23 @@ pub fn trace_at(code: &impl Code, i: u16, out: &mut impl Write, context: &mut at
     }
 }
 
-pub fn trace_out(code: &impl Code, out: &mut impl Write) {
-    write!(out, "--------\n").unwrap();
+/**
+ * Print the trace of each atom of the code as in trace_at, then the OID.
+ */
+pub fn trace_out(
+  code: &(impl Code + ?Sized), out: &mut impl Write, object_names: &ObjectNames) {
+    writeln!(out, "--------").unwrap();
     let mut context = atom::TraceContext::default();
     for i in 0..code.code_size() {
         write!(out, "{}\t", i).unwrap();
-        trace_at(code, i, out, &mut context);
-        write!(out, "\n").unwrap();
+        trace_at(code, i, out, &mut context, object_names);
+        writeln!(out).unwrap();
     }
     write!(out, "OID: {}", code.get_oid()).unwrap();
-    #[cfg(with_detail_oid)]
+    #[cfg(feature = "with_detail_oid")]
     write!(out, "({})", code.get_detail_oid()).unwrap();
-    write!(out, "\n").unwrap();
+    if code.is_invalidated() {
+        write!(out, " (invalidated)").unwrap();
+    }
+    writeln!(out).unwrap();
 }
@@ -0,0 +1,134 @@
+use std::rc::{Rc, Weak};
+use std::cell::RefCell;
+use std::collections::HashSet;
+use super::code::Code;
+use super::code::object_address;
+
+/**
+ * CollectionReport lists what a call to Collector::collect found and freed.
+ */
+#[derive(Default)]
+pub struct CollectionReport {
+    // The number of registered objects still alive when the collection started.
+    pub live_count_: usize,
+    // The number of live objects reachable from the root set.
+    pub reachable_count_: usize,
+    // The OIDs of the unreachable objects whose references were cleared.
+    pub freed_oids_: Vec<u32>,
+}
+
+impl CollectionReport {
+    pub fn freed_count(&self) -> usize {
+        self.freed_oids_.len()
+    }
+}
+
+/**
+ * Collector tracks the objects of a memory and frees those which can no longer be reached from a
+ * root set. References between objects are strong Rc references, so objects which reference each
+ * other in a cycle (for example a marker and the fact it points to) are never dropped. The
+ * collector breaks these cycles by calling clear_references on every unreachable object, after
+ * which the Rc counts drop to zero and the objects are deallocated.
+ * The collector only holds weak references, so registering an object does not keep it alive.
+ */
+#[derive(Default)]
+pub struct Collector {
+    objects_: Vec<Weak<RefCell<dyn Code>>>,
+}
+
+impl Collector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /**
+     * Track the object so that a later collect can free it if it is unreachable.
+     * \param object The object to track.
+     */
+    pub fn register(&mut self, object: &Rc<RefCell<dyn Code>>) {
+        self.objects_.push(Rc::downgrade(object));
+    }
+
+    /**
+     * Return the number of tracked objects which are still alive.
+     */
+    pub fn live_count(&self) -> usize {
+        self.objects_.iter().filter(|object| object.strong_count() > 0).count()
+    }
+
+    /**
+     * Find the tracked objects which are reachable from the roots by following references.
+     * \param roots The root set, for example the objects held in groups.
+     * \return The set of object addresses (see object_address) of the reachable objects,
+     * including objects reachable from the roots which were not registered.
+     */
+    pub fn mark(roots: &[Rc<RefCell<dyn Code>>]) -> HashSet<usize> {
+        let mut reachable = HashSet::new();
+        let mut to_visit: Vec<Rc<RefCell<dyn Code>>> = roots.iter().map(Rc::clone).collect();
+
+        while let Some(object) = to_visit.pop() {
+            if !reachable.insert(object_address(&object)) {
+                // Already visited.
+                continue;
+            }
+
+            let object_ref = object.borrow();
+            for i in 0..object_ref.references_size() {
+                let reference = object_ref.get_reference(i);
+                if !reachable.contains(&object_address(&reference)) {
+                    to_visit.push(reference);
+                }
+            }
+            // The host group and origin of each view are also reachable.
+            for view in object_ref.views() {
+                for reference in [view.get_reference(0), view.get_reference(1)].iter().flatten() {
+                    if !reachable.contains(&object_address(reference)) {
+                        to_visit.push(Rc::clone(reference));
+                    }
+                }
+            }
+        }
+
+        reachable
+    }
+
+    /**
+     * Clear the references and views of every tracked object which is not reachable from the
+     * roots, which breaks reference cycles so that the unreachable objects are deallocated once
+     * the caller releases its own references. Also stop tracking objects which have already been
+     * dropped.
+     * \param roots The root set, for example the objects held in groups.
+     * \return A CollectionReport with the OIDs of the freed objects.
+     */
+    pub fn collect(&mut self, roots: &[Rc<RefCell<dyn Code>>]) -> CollectionReport {
+        let reachable = Self::mark(roots);
+        let mut report = CollectionReport::default();
+
+        // Upgrade all the unreachable objects first so that clearing references of one object
+        // can't drop another before we visit it.
+        let mut unreachable = vec![];
+        let mut still_tracked = vec![];
+        for weak in self.objects_.drain(..) {
+            if let Some(object) = weak.upgrade() {
+                report.live_count_ += 1;
+                if reachable.contains(&object_address(&object)) {
+                    report.reachable_count_ += 1;
+                    still_tracked.push(weak);
+                }
+                else {
+                    unreachable.push(object);
+                }
+            }
+        }
+        self.objects_ = still_tracked;
+
+        for object in &unreachable {
+            let mut object_ref = object.borrow_mut();
+            report.freed_oids_.push(object_ref.get_oid());
+            object_ref.clear_references();
+            object_ref.clear_views();
+        }
+
+        report
+    }
+}
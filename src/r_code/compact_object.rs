@@ -0,0 +1,252 @@
+use std::rc::{Rc, Weak};
+use std::cell::RefCell;
+use std::fmt::Write;
+#[cfg(feature = "with_detail_oid")]
+use std::sync::atomic::Ordering;
+use super::atom;
+use super::atom::Atom;
+use super::code::Code;
+use super::code::CodeTrace;
+use super::code::object_address;
+use super::object_names::ObjectNames;
+use super::sys_object::SysObject;
+#[cfg(feature = "with_detail_oid")]
+use super::local_object::LAST_DETAIL_OID;
+use super::view::View;
+
+// None for a reference which load left to be resolved.
+type Reference = Option<Rc<RefCell<dyn Code>>>;
+
+/**
+ * A CompactObject is a Code whose code and references never change after creation, such as the
+ * facts and markers produced during a run. The code is a shared boxed slice, so cloning a
+ * CompactObject or creating one from the code of another doesn't copy the atoms. The references
+ * are a boxed slice whose entries can be set, and which only grows when set_reference adds one
+ * at the end, as when references are resolved after load. The markers, views and invalidation
+ * can still change since they are managed by the executive.
+ * Calling set_code or resize_code panics. load replaces the whole code instead.
+ */
+pub struct CompactObject {
+    oid_: u32,
+    code_: Rc<[Atom]>,
+    references_: Box<[Reference]>,
+    markers_: Vec<Weak<RefCell<dyn Code>>>,
+    // There are usually few views, so a Vec takes less memory than a map indexed by host.
+    views_: Vec<View>,
+    invalidated_: bool,
+    #[cfg(feature = "with_detail_oid")]
+    detail_oid_: u64,
+}
+
+impl CompactObject {
+    /**
+     * Create a CompactObject with the code and references.
+     * \param oid The OID.
+     * \param code The code, which can be shared with other CompactObjects. A Vec<Atom> converts
+     * into Rc<[Atom]> with into().
+     * \param references The references, which can't change size after creation.
+     */
+    pub fn new(oid: u32, code: Rc<[Atom]>, references: Vec<Rc<RefCell<dyn Code>>>) -> Self {
+        CompactObject { oid_: oid, code_: code,
+            references_: references.into_iter().map(Some).collect(),
+            markers_: vec![], views_: vec![], invalidated_: false,
+            #[cfg(feature = "with_detail_oid")]
+            detail_oid_: LAST_DETAIL_OID.fetch_add(1, Ordering::SeqCst),
+        }
+    }
+
+    /**
+     * Create a CompactObject with a copy of the OID, code and references of the source. This
+     * does not copy the markers, views or invalidation.
+     * \param source The object to copy, such as a LocalObject which is finished being built.
+     */
+    pub fn from_code(source: &dyn Code) -> Self {
+        let code: Vec<Atom> = (0..source.code_size()).map(|i| source.code(i)).collect();
+        let references = (0..source.references_size()).map(|i| source.get_reference(i)).collect();
+        Self::new(source.get_oid(), code.into(), references)
+    }
+
+    /**
+     * Return the shared code, for example to create another CompactObject with the same code.
+     */
+    pub fn shared_code(&self) -> Rc<[Atom]> {
+        Rc::clone(&self.code_)
+    }
+
+    fn remove_view_at(&mut self, host_address: usize) -> Option<View> {
+        let position = self.views_.iter()
+            .position(|view| view_host_address(view) == Some(host_address))?;
+        Some(self.views_.swap_remove(position))
+    }
+}
+
+fn view_host_address(view: &View) -> Option<usize> {
+    view.get_host().map(|host| object_address(&host))
+}
+
+impl Clone for CompactObject {
+    /**
+     * Return a new object with the same OID which shares the code and has the same references.
+     * The markers, views and invalidation belong to the original object and are not cloned.
+     */
+    fn clone(&self) -> Self {
+        let mut object = Self::new(self.oid_, self.shared_code(), vec![]);
+        object.references_ = self.references_.clone();
+        object
+    }
+}
+
+impl Code for CompactObject {
+    /**
+     * Replace the code and OID with those of the source, in new shared code. The references are
+     * replaced by as many unresolved references as the source has, so the caller must resolve
+     * each reference of source and call set_reference.
+     * \param source The persisted object.
+     */
+    fn load(&mut self, source: &SysObject) {
+        self.code_ = source.code_.clone().into();
+        self.references_ = vec![None; source.references_.len()].into_boxed_slice();
+        self.oid_ = source.oid_;
+    }
+
+    #[cfg(feature = "with_detail_oid")]
+    fn get_detail_oid(&self) -> u64 {
+        self.detail_oid_
+    }
+
+    #[cfg(feature = "with_detail_oid")]
+    fn set_detail_oid(&mut self, detail_oid: u64) {
+        self.detail_oid_ = detail_oid;
+        // Make sure the next assigned detail OID is higher.
+        LAST_DETAIL_OID.store(detail_oid + 1, Ordering::Relaxed);
+    }
+
+    fn get_oid(&self) -> u32 {
+        self.oid_
+    }
+
+    fn set_oid(&mut self, oid: u32) {
+        self.oid_ = oid;
+    }
+
+    fn code(&self, i: u16) -> Atom {
+        self.code_[i as usize]
+    }
+
+    fn set_code(&mut self, i: u16, _a: Atom) {
+        panic!("CompactObject {}: Can't set code({}) of a compact object", self.oid_, i);
+    }
+
+    fn code_size(&self) -> u16 {
+        self.code_.len() as u16
+    }
+
+    fn resize_code(&mut self, new_size: u16) {
+        panic!("CompactObject {}: Can't resize the code of a compact object to {}",
+               self.oid_, new_size);
+    }
+
+    /**
+     * Set reference i to the object. The references of a compact object can't grow, so i must be
+     * less than references_size().
+     */
+    fn set_reference(&mut self, i: u16, object: &Rc<RefCell<dyn Code>>) {
+        let size = self.references_size();
+        match self.references_.get_mut(i as usize) {
+            Some(reference) => *reference = Some(Rc::clone(object)),
+            None => panic!("CompactObject {}: Can't set reference {} of a compact object with {} \
+                            references", self.oid_, i, size),
+        }
+    }
+
+    fn get_reference(&self, i: u16) -> Rc<RefCell<dyn Code>> {
+        match &self.references_[i as usize] {
+            Some(reference) => Rc::clone(reference),
+            None => panic!("CompactObject {}: Reference {} is not resolved", self.oid_, i),
+        }
+    }
+
+    fn try_get_reference(&self, i: u16) -> Option<Rc<RefCell<dyn Code>>> {
+        self.references_.get(i as usize)?.clone()
+    }
+
+    fn references_size(&self) -> u16 {
+        self.references_.len() as u16
+    }
+
+    fn clear_references(&mut self) {
+        self.references_ = Box::new([]);
+    }
+
+    fn markers(&self) -> Vec<Rc<RefCell<dyn Code>>> {
+        self.markers_.iter().filter_map(Weak::upgrade).collect()
+    }
+
+    fn add_marker(&mut self, marker: &Rc<RefCell<dyn Code>>) {
+        super::code::add_weak_marker(&mut self.markers_, marker);
+    }
+
+    fn remove_marker(&mut self, marker: &Rc<RefCell<dyn Code>>) {
+        super::code::remove_weak_marker(&mut self.markers_, marker);
+    }
+
+    fn add_view(&mut self, view: View) -> bool {
+        let host = match view.get_host() {
+            Some(host) => object_address(&host),
+            None => return false,
+        };
+        self.remove_view_at(host);
+        self.views_.push(view);
+        true
+    }
+
+    fn get_view(&self, group: &Rc<RefCell<dyn Code>>) -> Option<&View> {
+        let address = object_address(group);
+        self.views_.iter().find(|view| view_host_address(view) == Some(address))
+    }
+
+    fn get_view_mut(&mut self, group: &Rc<RefCell<dyn Code>>) -> Option<&mut View> {
+        let address = object_address(group);
+        self.views_.iter_mut().find(|view| view_host_address(view) == Some(address))
+    }
+
+    fn remove_view(&mut self, group: &Rc<RefCell<dyn Code>>) -> Option<View> {
+        self.remove_view_at(object_address(group))
+    }
+
+    fn views(&self) -> Vec<&View> {
+        self.views_.iter().collect()
+    }
+
+    fn clear_views(&mut self) {
+        self.views_.clear();
+    }
+
+    fn is_invalidated(&self) -> bool {
+        self.invalidated_
+    }
+
+    fn invalidate(&mut self) -> bool {
+        if self.invalidated_ {
+            return true;
+        }
+
+        self.invalidated_ = true;
+        self.views_.clear();
+        false
+    }
+
+    fn is_compact(&self) -> bool {
+        true
+    }
+}
+
+impl CodeTrace for CompactObject {
+    fn trace_at(&self, i: u16, out: &mut impl Write, context: &mut atom::TraceContext) {
+        super::code::trace_at(self, i, out, context, &ObjectNames::new());
+    }
+
+    fn trace_out(&self, out: &mut impl Write) {
+        super::code::trace_out(self, out, &ObjectNames::new());
+    }
+}
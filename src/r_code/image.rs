@@ -0,0 +1,682 @@
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::io::{BufReader, BufWriter, Read};
+use std::path::Path;
+
+#[derive(Debug)]
+pub enum ImageError {
+    Io(io::Error),
+    // The data ended while reading the named item.
+    Truncated(String),
+    // The data has an invalid value, described by the message.
+    Invalid(String),
+    // A reference index of an object (or of one of its views) is not an object in the image.
+    DanglingReference { object_index: usize, oid: u32, reference_index: u32 },
+    // Classes, operators or device functions used by the image which are not in the definition
+    // segment that it is migrated to, each described like "class fact used by OID 3, 5".
+    MissingDefinitions(Vec<String>),
+    // The image header has a format version, byte order or atom width which this reader doesn't
+    // support, described by the message.
+    UnsupportedFormat(String),
+    // The checksum of a segment doesn't match its data. For the code segment, this has the
+    // index and OID of the first corrupt object if it could be found.
+    ChecksumMismatch { segment: String, object_index: Option<usize>, oid: Option<u32> },
+    // A size in the image is larger than the limit, which protects against allocating memory
+    // for a corrupt size.
+    SizeLimit { item: String, size: usize, limit: usize },
+    // The error happened at the byte offset. A reader which gets this error from reading a
+    // slice of its data adds the offset of the slice with offset_by, so that the offset of an
+    // error from reading an image is from the start of the file.
+    AtOffset { byte_offset: usize, error: Box<ImageError> },
+}
+
+impl fmt::Display for ImageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ImageError::Io(error) => write!(f, "I/O error: {}", error),
+            ImageError::Truncated(item) => write!(f, "Image data ended while reading {}", item),
+            ImageError::Invalid(message) => write!(f, "Invalid image: {}", message),
+            ImageError::DanglingReference { object_index, oid, reference_index } => write!(
+                f, "Object {} (OID {}) references object {} which is not in the image",
+                object_index, oid, reference_index),
+            ImageError::MissingDefinitions(descriptions) => write!(
+                f, "Not in the new definition segment: {}", descriptions.join("; ")),
+            ImageError::UnsupportedFormat(message) => write!(
+                f, "Unsupported image format: {}", message),
+            ImageError::ChecksumMismatch { segment, object_index, oid } => {
+                write!(f, "The checksum of the {} segment doesn't match", segment)?;
+                match (object_index, oid) {
+                    (Some(object_index), Some(oid)) => write!(
+                        f, ": object {} (OID {}) is corrupt", object_index, oid),
+                    _ => Ok(()),
+                }
+            },
+            ImageError::SizeLimit { item, size, limit } => write!(
+                f, "The {} size {} is larger than the limit {}", item, size, limit),
+            ImageError::AtOffset { byte_offset, error } => write!(
+                f, "{} at byte offset {}", error, byte_offset),
+        }
+    }
+}
+
+impl ImageError {
+    /**
+     * Return the error at the word offset: if this is AtOffset, add the offset to its byte
+     * offset, otherwise return AtOffset with this error. An Io error is returned unchanged.
+     * \param word_offset The offset in words, for example of the slice given to the reader which
+     * returned this error.
+     */
+    pub fn offset_by(self, word_offset: usize) -> Self {
+        match self {
+            ImageError::Io(_) => self,
+            ImageError::AtOffset { byte_offset, error } =>
+                ImageError::AtOffset { byte_offset: byte_offset + 4 * word_offset, error },
+            _ => ImageError::AtOffset { byte_offset: 4 * word_offset, error: Box::new(self) },
+        }
+    }
+
+    /**
+     * Return the byte offset of the error, or None if it has none.
+     */
+    pub fn byte_offset(&self) -> Option<usize> {
+        match self {
+            ImageError::AtOffset { byte_offset, .. } => Some(*byte_offset),
+            _ => None,
+        }
+    }
+
+    /**
+     * Return the error without its offset.
+     */
+    pub fn without_offset(&self) -> &ImageError {
+        match self {
+            ImageError::AtOffset { error, .. } => error.without_offset(),
+            _ => self,
+        }
+    }
+}
+
+impl std::error::Error for ImageError {}
+
+impl From<io::Error> for ImageError {
+    fn from(error: io::Error) -> Self {
+        ImageError::Io(error)
+    }
+}
+
+/**
+ * Return the words data[offset..offset + size], or an ImageError::Truncated for the item at the
+ * offset if the data is too short. Since the size is checked against the data before anything
+ * is allocated, a corrupt size can't cause a large allocation.
+ */
+pub fn get_words<'a>(
+  data: &'a [u32], offset: usize, size: usize, item: &str) -> Result<&'a [u32], ImageError> {
+    match offset.checked_add(size) {
+        Some(end) if end <= data.len() => Ok(&data[offset..end]),
+        _ => Err(ImageError::Truncated(item.to_string()).offset_by(offset)),
+    }
+}
+
+/**
+ * Return the number of words used by write_string to store the string: the length, followed by
+ * the characters packed four per word.
+ */
+pub fn get_string_size(s: &str) -> usize {
+    1 + s.len().div_ceil(4)
+}
+
+/**
+ * Write the string to data as the length followed by the characters packed four per word with
+ * the first character in the low byte, as done by r_code::Write in the C++ version.
+ * \param data The destination, which must have at least get_string_size(s) words.
+ * \param s The string.
+ */
+pub fn write_string(data: &mut [u32], s: &str) {
+    let bytes = s.as_bytes();
+    data[0] = bytes.len() as u32;
+    for (i, chunk) in bytes.chunks(4).enumerate() {
+        let mut word = [0u8; 4];
+        word[..chunk.len()].copy_from_slice(chunk);
+        data[1 + i] = u32::from_le_bytes(word);
+    }
+}
+
+/**
+ * Read a string written by write_string.
+ * \param data The source data.
+ * \param offset The offset in data of the string length.
+ * \return The string and the number of words it used, or an ImageError if the data is truncated.
+ */
+pub fn read_string(data: &[u32], offset: usize) -> Result<(String, usize), ImageError> {
+    let length = get_words(data, offset, 1, "string length")?[0] as usize;
+    let words = get_words(data, offset + 1, length.div_ceil(4), "string characters")?;
+    let mut bytes: Vec<u8> = words.iter().flat_map(|word| word.to_le_bytes()).collect();
+    bytes.truncate(length);
+    Ok((String::from_utf8_lossy(&bytes).into_owned(), 1 + words.len()))
+}
+
+const fn crc32_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB88320 } else { crc >> 1 };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+static CRC32_TABLE: [u32; 256] = crc32_table();
+
+/**
+ * Return the CRC-32 of the bytes.
+ */
+pub fn checksum_bytes(bytes: impl IntoIterator<Item = u8>) -> u32 {
+    !bytes.into_iter().fold(!0u32, |crc, byte| {
+        CRC32_TABLE[((crc ^ byte as u32) & 0xFF) as usize] ^ (crc >> 8)
+    })
+}
+
+/**
+ * Return the CRC-32 of the words stored as little-endian bytes, as used for the checksums in the
+ * image header.
+ */
+pub fn checksum(words: &[u32]) -> u32 {
+    checksum_bytes(words.iter().flat_map(|word| word.to_le_bytes()))
+}
+
+// The first word of an image with a header, the bytes "AERA". A legacy image starts with the low
+// word of its timestamp, which is this value once in 2^32 microseconds. In practice the legacy
+// images of the C++ version have a timestamp of 0 or are compiled at another time.
+pub const IMAGE_MAGIC: u32 = 0x41524541;
+// The format version of a legacy C++ image, which has no header.
+pub const LEGACY_FORMAT_VERSION: u32 = 0;
+// The format version written by this version of the code.
+pub const FORMAT_VERSION: u32 = 1;
+// Written as a word to check that the reader uses the same byte order as the writer.
+const BYTE_ORDER_MARK: u32 = 0x01020304;
+// The size of an atom in bytes.
+const ATOM_WIDTH: u32 = 4;
+// The magic, version, byte order mark, atom width, opcode table hash, the timestamp as two words,
+// then five segment sizes and five segment checksums. The object checksums follow.
+pub(crate) const HEADER_SIZE: usize = 17;
+// The default limit on the size of an image read from a stream, which is 1 GiB.
+pub const DEFAULT_MAX_IMAGE_WORDS: usize = 1 << 28;
+const SEGMENT_NAMES: [&str; 5] = ["definition", "object map", "code", "relocation", "names"];
+
+/**
+ * ImageHeader is the decoded header of an image which is not legacy.
+ */
+pub(crate) struct ImageHeader {
+    pub opcode_table_hash_: u32,
+    pub timestamp_: u64,
+    // The definition, object map, code, relocation and names segment sizes in words.
+    pub sizes_: [u32; 5],
+    pub checksums_: [u32; 5],
+}
+
+impl ImageHeader {
+    /**
+     * Decode the HEADER_SIZE words of the header, which start with IMAGE_MAGIC.
+     * \return The ImageHeader, or ImageError::UnsupportedFormat if this reader doesn't support
+     * the format version, byte order or atom width.
+     */
+    pub fn read(words: &[u32]) -> Result<Self, ImageError> {
+        let words = get_words(words, 0, HEADER_SIZE, "image header")?;
+        if words[1] == LEGACY_FORMAT_VERSION || words[1] > FORMAT_VERSION {
+            return Err(ImageError::UnsupportedFormat(format!(
+                "Format version {} is not supported. The supported version is {}", words[1],
+                FORMAT_VERSION)).offset_by(1));
+        }
+        if words[2] != BYTE_ORDER_MARK {
+            return Err(ImageError::UnsupportedFormat(format!(
+                "The byte order mark is {:#010x} instead of {:#010x}", words[2],
+                BYTE_ORDER_MARK)).offset_by(2));
+        }
+        if words[3] != ATOM_WIDTH {
+            return Err(ImageError::UnsupportedFormat(format!(
+                "The atom width is {} bytes instead of {}", words[3], ATOM_WIDTH)).offset_by(3));
+        }
+
+        let mut header = ImageHeader {
+            opcode_table_hash_: words[4], timestamp_: get_timestamp(&words[5..7]), sizes_: [0; 5],
+            checksums_: [0; 5] };
+        header.sizes_.copy_from_slice(&words[7..12]);
+        header.checksums_.copy_from_slice(&words[12..17]);
+        Ok(header)
+    }
+
+    /**
+     * Check the checksum of the segment.
+     * \param segment The index of the segment: 0 for the definition segment, up to 4 for the
+     * names segment.
+     * \param words The words of the segment.
+     * \return Ok, or ImageError::ChecksumMismatch without an object index.
+     */
+    pub fn verify_segment(&self, segment: usize, words: &[u32]) -> Result<(), ImageError> {
+        if checksum(words) == self.checksums_[segment] {
+            Ok(())
+        }
+        else {
+            Err(ImageError::ChecksumMismatch {
+                segment: SEGMENT_NAMES[segment].to_string(), object_index: None, oid: None })
+        }
+    }
+
+    /**
+     * Check the checksum of each segment of the data, which has the segments in order. If the
+     * code segment doesn't match, use the object checksums to find the corrupt object.
+     * \param object_checksums The object checksums from the header.
+     * \return Ok, or ImageError::ChecksumMismatch for the first segment which doesn't match.
+     */
+    pub fn verify(&self, data: &[u32], object_checksums: &[u32]) -> Result<(), ImageError> {
+        let mut start = 0;
+        for (segment, size) in self.sizes_.iter().enumerate() {
+            let end = start + *size as usize;
+            let result = self.verify_segment(segment, &data[start..end]);
+            if result.is_err() && segment == 2 {
+                let (def_size, map_size, code_size) =
+                  (self.sizes_[0], self.sizes_[1], self.sizes_[2]);
+                let actual = get_object_checksums(data, def_size, map_size, code_size);
+                if let Some(index) = (0..actual.len())
+                    .find(|i| object_checksums.get(*i) != Some(&actual[*i])) {
+                    return Err(ImageError::ChecksumMismatch {
+                        segment: SEGMENT_NAMES[segment].to_string(), object_index: Some(index),
+                        oid: get_object_words(data, def_size, map_size, code_size, index)
+                            .first().copied() });
+                }
+            }
+            result?;
+            start = end;
+        }
+        Ok(())
+    }
+}
+
+/**
+ * Return the timestamp from its two words, the low word first as written by the C++ version.
+ */
+pub(crate) fn get_timestamp(words: &[u32]) -> u64 {
+    words[0] as u64 | (words[1] as u64) << 32
+}
+
+/**
+ * Return the words of the object at the index as given by the object map: from its offset to the
+ * offset of the next object or the end of the code segment. If the offsets are not in the code
+ * segment, return an empty slice.
+ * \param data The data, which starts with the definition segment, object map and code segment.
+ */
+pub(crate) fn get_object_words(
+  data: &[u32], def_size: u32, map_size: u32, code_size: u32, index: usize) -> &[u32] {
+    let map_start = def_size as usize;
+    let code_start = map_start + map_size as usize;
+    let code_end = code_start + code_size as usize;
+    let offset = |i: usize| data[map_start + i] as usize;
+    let start = offset(index);
+    let end = if index + 1 < map_size as usize { offset(index + 1) } else { code_end };
+    if start < code_start || start > end || end > code_end {
+        return &[];
+    }
+    &data[start..end]
+}
+
+/**
+ * Return the checksum of each object in the code segment, as stored in the image header.
+ */
+pub(crate) fn get_object_checksums(
+  data: &[u32], def_size: u32, map_size: u32, code_size: u32) -> Vec<u32> {
+    (0..map_size as usize)
+        .map(|i| checksum(get_object_words(data, def_size, map_size, code_size, i))).collect()
+}
+
+/**
+ * Image holds the raw words of an image file: the definition segment, the object map, the code
+ * segment, the relocation segment and the object names segment. Each size is in words. The words
+ * are little-endian. To decode the segments, see r_comp::Image.
+ * An image starts with a header: IMAGE_MAGIC, the format version, a byte order mark, the atom
+ * width, the hash of the opcode table (see DefinitionSegment::get_opcode_table_hash), the
+ * timestamp, the five segment sizes, the checksum of each segment and the checksum of each
+ * object. Reading fails if the format is not supported or a checksum doesn't match.
+ * A legacy image as written by the C++ r_code::Image has no header and starts with the 64-bit
+ * timestamp as two words and the first four segment sizes, followed by the data. The object
+ * names segment is optional. If it is not empty, the file continues after the data with its
+ * size and its words. A legacy image is written back in the legacy format.
+ * The legacy layout follows the C++ source. Reading an image written by the C++ version is not
+ * verified yet, since the only legacy fixture (tests/fixtures/legacy.image) was written by hand.
+ */
+pub struct Image {
+    format_version_: u32,
+    opcode_table_hash_: u32,
+    // The time at which the image was compiled in microseconds, as set by the C++ compiler.
+    timestamp_: u64,
+    def_size_: u32,
+    map_size_: u32,
+    code_size_: u32,
+    reloc_size_: u32,
+    names_size_: u32,
+    data_: Vec<u32>,
+}
+
+impl Default for Image {
+    fn default() -> Self {
+        Image::new(0, 0, 0, 0, 0)
+    }
+}
+
+impl Image {
+    /**
+     * Create an Image with the segment sizes and zero-filled data, in the current format.
+     */
+    pub fn new(
+      def_size: u32, map_size: u32, code_size: u32, reloc_size: u32, names_size: u32) -> Self {
+        let size = def_size as usize + map_size as usize + code_size as usize +
+          reloc_size as usize + names_size as usize;
+        Image { format_version_: FORMAT_VERSION, opcode_table_hash_: 0, timestamp_: 0,
+                def_size_: def_size, map_size_: map_size, code_size_: code_size,
+                reloc_size_: reloc_size, names_size_: names_size, data_: vec![0; size] }
+    }
+
+    /**
+     * Read an Image from the stream, with a header or in the legacy format. The data is at most
+     * DEFAULT_MAX_IMAGE_WORDS.
+     * \param stream The source, which is read to the end of the image data.
+     * \return The Image, or an ImageError if the stream can't be read or is truncated, the format
+     * is not supported, a size is larger than the limit or a checksum doesn't match.
+     */
+    pub fn read(stream: &mut impl Read) -> Result<Self, ImageError> {
+        Self::read_with_limit(stream, DEFAULT_MAX_IMAGE_WORDS)
+    }
+
+    /**
+     * Read an Image from the stream as in read, with a limit on the size.
+     * \param stream The source.
+     * \param max_words The maximum number of words in the image file, including the header.
+     * Memory is only allocated for the words which are actually read, so a corrupt size in a
+     * truncated image doesn't allocate memory for the size.
+     * \return The Image, or an ImageError as in read.
+     */
+    pub fn read_with_limit(stream: &mut impl Read, max_words: usize) -> Result<Self, ImageError> {
+        let mut reader = WordReader { stream, offset_: 0, max_words_: max_words };
+        let first_word = reader.read_word("image timestamp")?;
+        if first_word == IMAGE_MAGIC {
+            return Self::read_with_header(&mut reader);
+        }
+
+        let timestamp = get_timestamp(&[first_word, reader.read_word("image timestamp")?]);
+        let sizes = reader.read_words(4, "segment sizes")?;
+        let mut image = Image {
+            format_version_: LEGACY_FORMAT_VERSION, timestamp_: timestamp, ..Image::default() };
+        image.set_sizes(&sizes, 0);
+        let data_size = sizes.iter().map(|size| *size as usize).sum();
+        image.data_ = reader.read_words(data_size, "image data")?;
+
+        // Check for the optional names segment.
+        let mut bytes = [0u8; 4];
+        let mut count = 0;
+        while count < 4 {
+            match reader.stream.read(&mut bytes[count..])? {
+                0 => break,
+                n => count += n,
+            }
+        }
+        if count > 0 {
+            if count < 4 {
+                return Err(ImageError::Truncated("names segment size".to_string())
+                    .offset_by(reader.offset_));
+            }
+            reader.offset_ += 1;
+            image.names_size_ = u32::from_le_bytes(bytes);
+            let names = reader.read_words(image.names_size_ as usize, "names segment")?;
+            image.data_.extend(names);
+        }
+        Ok(image)
+    }
+
+    /**
+     * Read the rest of an image after IMAGE_MAGIC.
+     */
+    fn read_with_header(reader: &mut WordReader<impl Read>) -> Result<Self, ImageError> {
+        let mut header_words = vec![IMAGE_MAGIC];
+        header_words.extend(reader.read_words(HEADER_SIZE - 1, "image header")?);
+        let header = ImageHeader::read(&header_words)?;
+        let sizes = header.sizes_;
+        let object_checksums = reader.read_words(sizes[1] as usize, "object checksums")?;
+
+        let mut image = Image {
+            format_version_: header_words[1], opcode_table_hash_: header.opcode_table_hash_,
+            timestamp_: header.timestamp_, ..Image::default() };
+        image.set_sizes(&sizes[..4], sizes[4]);
+        let data_size = sizes.iter().map(|size| *size as usize).sum();
+        image.data_ = reader.read_words(data_size, "image data")?;
+        header.verify(&image.data_, &object_checksums)?;
+        Ok(image)
+    }
+
+    fn set_sizes(&mut self, sizes: &[u32], names_size: u32) {
+        self.def_size_ = sizes[0];
+        self.map_size_ = sizes[1];
+        self.code_size_ = sizes[2];
+        self.reloc_size_ = sizes[3];
+        self.names_size_ = names_size;
+    }
+
+    /**
+     * Read an Image from the file. The limit on the size is the file size.
+     */
+    pub fn read_file(path: impl AsRef<Path>) -> Result<Self, ImageError> {
+        let file = File::open(path)?;
+        let max_words = file.metadata()?.len() as usize / 4;
+        Self::read_with_limit(&mut BufReader::new(file), max_words)
+    }
+
+    /**
+     * Return the offset of the segment in the image file in words.
+     * \param segment The index of the segment: 0 for the definition segment, up to 4 for the
+     * names segment.
+     */
+    pub fn get_segment_offset(&self, segment: usize) -> usize {
+        let sizes = [self.def_size_, self.map_size_, self.code_size_, self.reloc_size_];
+        let (data_offset, names_size_word) = if self.format_version_ == LEGACY_FORMAT_VERSION {
+            (6, 1)
+        }
+        else {
+            (HEADER_SIZE + self.map_size_ as usize, 0)
+        };
+        let offset = data_offset + sizes[..segment.min(4)].iter()
+            .map(|size| *size as usize).sum::<usize>();
+        if segment == 4 { offset + names_size_word } else { offset }
+    }
+
+    /**
+     * Write the Image to the stream in the format read by read. If the format version is
+     * LEGACY_FORMAT_VERSION, this writes a legacy image without a header.
+     */
+    pub fn write(&self, stream: &mut impl io::Write) -> io::Result<()> {
+        let mut write_words = |words: &[u32]| -> io::Result<()> {
+            for word in words {
+                stream.write_all(&word.to_le_bytes())?;
+            }
+            Ok(())
+        };
+
+        if self.format_version_ == LEGACY_FORMAT_VERSION {
+            let (data, names) = self.data_.split_at(self.data_.len() - self.names_size_ as usize);
+            write_words(&self.get_timestamp_words())?;
+            write_words(&[self.def_size_, self.map_size_, self.code_size_, self.reloc_size_])?;
+            write_words(data)?;
+            if self.names_size_ > 0 {
+                write_words(&[self.names_size_])?;
+                write_words(names)?;
+            }
+            return Ok(());
+        }
+
+        let sizes = [self.def_size_, self.map_size_, self.code_size_, self.reloc_size_,
+                     self.names_size_];
+        write_words(&[IMAGE_MAGIC, self.format_version_, BYTE_ORDER_MARK, ATOM_WIDTH,
+                      self.opcode_table_hash_])?;
+        write_words(&self.get_timestamp_words())?;
+        write_words(&sizes)?;
+        let mut start = 0;
+        for size in &sizes {
+            write_words(&[checksum(&self.data_[start..start + *size as usize])])?;
+            start += *size as usize;
+        }
+        write_words(&get_object_checksums(
+            &self.data_, self.def_size_, self.map_size_, self.code_size_))?;
+        write_words(&self.data_)
+    }
+
+    /**
+     * Write the Image to the file, replacing it if it exists.
+     */
+    pub fn write_file(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let mut stream = BufWriter::new(File::create(path)?);
+        self.write(&mut stream)?;
+        io::Write::flush(&mut stream)
+    }
+
+    /**
+     * Return the format version, which is LEGACY_FORMAT_VERSION for an image without a header.
+     */
+    pub fn format_version(&self) -> u32 { self.format_version_ }
+
+    /**
+     * Set the format version used by write, such as LEGACY_FORMAT_VERSION to write an image for
+     * the C++ version, or FORMAT_VERSION.
+     */
+    pub fn set_format_version(&mut self, format_version: u32) {
+        self.format_version_ = format_version;
+    }
+
+    /**
+     * Return the hash of the opcode table in the header, which is 0 for a legacy image.
+     */
+    pub fn opcode_table_hash(&self) -> u32 { self.opcode_table_hash_ }
+
+    pub fn set_opcode_table_hash(&mut self, opcode_table_hash: u32) {
+        self.opcode_table_hash_ = opcode_table_hash;
+    }
+
+    /**
+     * Return the time at which the image was compiled in microseconds, or 0 if it is not set.
+     */
+    pub fn timestamp(&self) -> u64 { self.timestamp_ }
+
+    pub fn set_timestamp(&mut self, timestamp: u64) {
+        self.timestamp_ = timestamp;
+    }
+
+    fn get_timestamp_words(&self) -> [u32; 2] {
+        [self.timestamp_ as u32, (self.timestamp_ >> 32) as u32]
+    }
+
+    pub fn def_size(&self) -> u32 { self.def_size_ }
+
+    pub fn map_size(&self) -> u32 { self.map_size_ }
+
+    pub fn code_size(&self) -> u32 { self.code_size_ }
+
+    pub fn reloc_size(&self) -> u32 { self.reloc_size_ }
+
+    pub fn names_size(&self) -> u32 { self.names_size_ }
+
+    /**
+     * Return the size of the data in words.
+     */
+    pub fn get_size(&self) -> usize {
+        self.data_.len()
+    }
+
+    pub fn data(&self) -> &[u32] {
+        &self.data_
+    }
+
+    pub fn data_mut(&mut self) -> &mut [u32] {
+        &mut self.data_
+    }
+
+    pub fn get_object_count(&self) -> u32 {
+        self.map_size_
+    }
+
+    pub fn get_def_segment(&self) -> &[u32] {
+        &self.data_[..self.def_size_ as usize]
+    }
+
+    pub fn get_map_segment(&self) -> &[u32] {
+        let start = self.def_size_ as usize;
+        &self.data_[start..start + self.map_size_ as usize]
+    }
+
+    pub fn get_code_segment(&self) -> &[u32] {
+        let start = self.def_size_ as usize + self.map_size_ as usize;
+        &self.data_[start..start + self.code_size_ as usize]
+    }
+
+    pub fn get_reloc_segment(&self) -> &[u32] {
+        let start = self.def_size_ as usize + self.map_size_ as usize + self.code_size_ as usize;
+        &self.data_[start..start + self.reloc_size_ as usize]
+    }
+
+    pub fn get_names_segment(&self) -> &[u32] {
+        &self.data_[self.data_.len() - self.names_size_ as usize..]
+    }
+}
+
+/**
+ * WordReader reads little-endian words from a stream and keeps the offset for errors.
+ */
+struct WordReader<'a, R: Read> {
+    stream: &'a mut R,
+    // The offset in words of the next word in the stream.
+    offset_: usize,
+    max_words_: usize,
+}
+
+impl<'a, R: Read> WordReader<'a, R> {
+    fn read_word(&mut self, item: &str) -> Result<u32, ImageError> {
+        Ok(self.read_words(1, item)?[0])
+    }
+
+    /**
+     * Read count words. The Vec grows as the words are read, so that a corrupt count in a
+     * truncated stream only allocates memory for the words in the stream.
+     * \return The words, or ImageError::SizeLimit if the words would go past max_words_, or
+     * ImageError::Truncated at the offset of the item if the stream ends.
+     */
+    fn read_words(&mut self, count: usize, item: &str) -> Result<Vec<u32>, ImageError> {
+        if self.offset_.saturating_add(count) > self.max_words_ {
+            return Err(ImageError::SizeLimit {
+                item: item.to_string(), size: count,
+                limit: self.max_words_.saturating_sub(self.offset_) }.offset_by(self.offset_));
+        }
+
+        const CHUNK_WORDS: usize = 1 << 16;
+        let mut words = vec![];
+        let mut bytes = vec![];
+        while words.len() < count {
+            let chunk_words = (count - words.len()).min(CHUNK_WORDS);
+            bytes.resize(4 * chunk_words, 0);
+            self.stream.read_exact(&mut bytes).map_err(|error| {
+                if error.kind() == io::ErrorKind::UnexpectedEof {
+                    ImageError::Truncated(item.to_string()).offset_by(self.offset_)
+                }
+                else {
+                    ImageError::Io(error)
+                }
+            })?;
+            words.extend(bytes.chunks(4).map(|word| {
+                u32::from_le_bytes([word[0], word[1], word[2], word[3]])
+            }));
+        }
+        self.offset_ += count;
+        Ok(words)
+    }
+}
@@ -1,2 +1,24 @@
+use super::image::ImageError;
+
+/**
+ * An ImageObject is stored in the code segment of an image as a flat sequence of u32 words.
+ */
 pub trait ImageObject {
+    /**
+     * Set this object from the words at the start of data.
+     * \param data The words, which may continue past this object.
+     * \return The number of words read, or an ImageError if data is truncated or invalid.
+     */
+    fn read(&mut self, data: &[u32]) -> Result<usize, ImageError>;
+
+    /**
+     * Write this object to the start of data in the format read by read.
+     * \param data The destination, which must have at least get_size() words.
+     */
+    fn write(&self, data: &mut [u32]);
+
+    /**
+     * Return the number of words used to store this object.
+     */
+    fn get_size(&self) -> usize;
 }
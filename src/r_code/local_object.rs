@@ -1,42 +1,53 @@
-use std::rc::Rc;
+use std::rc::{Rc, Weak};
 use std::cell::RefCell;
+use std::collections::HashMap;
 use std::fmt::Write;
-#[cfg(with_detail_oid)]
+#[cfg(feature = "with_detail_oid")]
 use std::sync::atomic::{AtomicU64, Ordering};
 use super::atom;
 use super::atom::Atom;
 use super::code::Code;
 use super::code::CodeTrace;
+use super::code::object_address;
+use super::object_names::ObjectNames;
+use super::view::View;
 
 
-#[cfg(with_detail_oid)]
+#[cfg(feature = "with_detail_oid")]
 // Start with a non-zero value so that it doesn't appear to track object OIDs.
-static LAST_DETAIL_OID: AtomicU64 = AtomicU64::new(11);
+pub(crate) static LAST_DETAIL_OID: AtomicU64 = AtomicU64::new(11);
 
 pub struct LocalObject {
     oid_: u32,
     code_: Vec<Atom>,
     references_: Vec<Rc<RefCell<dyn Code>>>,
-    #[cfg(with_detail_oid)]
+    markers_: Vec<Weak<RefCell<dyn Code>>>,
+    // Indexed by the object_address of the host group.
+    views_: HashMap<usize, View>,
+    invalidated_: bool,
+    #[cfg(feature = "with_detail_oid")]
     detail_oid_: u64,
 }
 
+// Not derivable when detail_oid_ is present.
+#[allow(clippy::derivable_impls)]
 impl Default for LocalObject {
     fn default() -> Self {
         LocalObject { oid_: 0, code_: Vec::default(), references_: Vec::default(),
-            #[cfg(with_detail_oid)]
+            markers_: Vec::default(), views_: HashMap::default(), invalidated_: false,
+            #[cfg(feature = "with_detail_oid")]
             detail_oid_: LAST_DETAIL_OID.fetch_add(1, Ordering::SeqCst),
         }
     }
 }
 
 impl Code for LocalObject {
-    #[cfg(with_detail_oid)]
+    #[cfg(feature = "with_detail_oid")]
     fn get_detail_oid(&self) -> u64 {
         self.detail_oid_
     }
 
-    #[cfg(with_detail_oid)]
+    #[cfg(feature = "with_detail_oid")]
     fn set_detail_oid(&mut self, detail_oid: u64) {
         self.detail_oid_ = detail_oid;
         // Make sure the next assigned detail OID is higher.
@@ -71,7 +82,12 @@ impl Code for LocalObject {
     }
 
     fn set_reference(&mut self, i: u16, object: &Rc<RefCell<dyn Code>>) {
-        self.references_[i as usize] = Rc::clone(&object);
+        if i == self.references_size() {
+            self.references_.push(Rc::clone(object));
+        }
+        else {
+            self.references_[i as usize] = Rc::clone(object);
+        }
     }
 
     fn get_reference(&self, i: u16) -> Rc<RefCell<dyn Code>> {
@@ -85,14 +101,70 @@ impl Code for LocalObject {
     fn clear_references(&mut self) {
         self.references_.clear();
     }
+
+    fn markers(&self) -> Vec<Rc<RefCell<dyn Code>>> {
+        self.markers_.iter().filter_map(Weak::upgrade).collect()
+    }
+
+    fn add_marker(&mut self, marker: &Rc<RefCell<dyn Code>>) {
+        super::code::add_weak_marker(&mut self.markers_, marker);
+    }
+
+    fn remove_marker(&mut self, marker: &Rc<RefCell<dyn Code>>) {
+        super::code::remove_weak_marker(&mut self.markers_, marker);
+    }
+
+    fn add_view(&mut self, view: View) -> bool {
+        match view.get_host() {
+            Some(host) => {
+                self.views_.insert(object_address(&host), view);
+                true
+            },
+            None => false,
+        }
+    }
+
+    fn get_view(&self, group: &Rc<RefCell<dyn Code>>) -> Option<&View> {
+        self.views_.get(&object_address(group))
+    }
+
+    fn get_view_mut(&mut self, group: &Rc<RefCell<dyn Code>>) -> Option<&mut View> {
+        self.views_.get_mut(&object_address(group))
+    }
+
+    fn remove_view(&mut self, group: &Rc<RefCell<dyn Code>>) -> Option<View> {
+        self.views_.remove(&object_address(group))
+    }
+
+    fn views(&self) -> Vec<&View> {
+        self.views_.values().collect()
+    }
+
+    fn clear_views(&mut self) {
+        self.views_.clear();
+    }
+
+    fn is_invalidated(&self) -> bool {
+        self.invalidated_
+    }
+
+    fn invalidate(&mut self) -> bool {
+        if self.invalidated_ {
+            return true;
+        }
+
+        self.invalidated_ = true;
+        self.views_.clear();
+        false
+    }
 }
 
 impl CodeTrace for LocalObject {
     fn trace_at(&self, i: u16, out: &mut impl Write, context: &mut atom::TraceContext) {
-        super::code::trace_at(self, i, out, context);
+        super::code::trace_at(self, i, out, context, &ObjectNames::new());
     }
 
     fn trace_out(&self, out: &mut impl Write) {
-        super::code::trace_out(self, out);
+        super::code::trace_out(self, out, &ObjectNames::new());
     }
 }
@@ -0,0 +1,510 @@
+use std::rc::{Rc, Weak};
+use std::cell::RefCell;
+use std::fs::File;
+use std::path::Path;
+use super::atom::Atom;
+use super::code::Code;
+use super::image::{
+  checksum, get_object_words, get_timestamp, get_words, ImageError, ImageHeader, HEADER_SIZE,
+  IMAGE_MAGIC, LEGACY_FORMAT_VERSION};
+use super::image_object::ImageObject;
+use super::mapped_object::MappedObject;
+use super::object_registry::UNDEFINED_OID;
+use super::sys_object::SysView;
+
+// The file is mapped on unix. The words in the file are little-endian, so they are only used in
+// place on a little-endian host. The offset argument of mmap is declared as i64, which is the
+// size of off_t on 64-bit platforms.
+#[cfg(all(unix, target_endian = "little", target_pointer_width = "64"))]
+mod mmap {
+    use std::fs::File;
+    use std::io;
+    use std::os::raw::{c_int, c_void};
+    use std::os::unix::io::AsRawFd;
+
+    const PROT_READ: c_int = 1;
+    const MAP_PRIVATE: c_int = 2;
+
+    extern "C" {
+        fn mmap(
+          address: *mut c_void, length: usize, protection: c_int, flags: c_int, fd: c_int,
+          offset: i64) -> *mut c_void;
+        fn munmap(address: *mut c_void, length: usize) -> c_int;
+    }
+
+    /**
+     * A read-only private mapping of a whole file, unmapped when dropped.
+     */
+    pub struct Mapping {
+        address_: *mut c_void,
+        length_: usize,
+    }
+
+    impl Mapping {
+        /**
+         * Map the file, which must not be empty.
+         * \return The Mapping, or an io::Error if the file can't be mapped or the mapping is
+         * not a whole number of aligned words.
+         */
+        pub fn new(file: &File, length: usize) -> io::Result<Self> {
+            // Safety: This creates a new mapping and doesn't touch existing memory.
+            let address = unsafe {
+                mmap(std::ptr::null_mut(), length, PROT_READ, MAP_PRIVATE, file.as_raw_fd(), 0)
+            };
+            if address as isize == -1 {
+                return Err(io::Error::last_os_error());
+            }
+            let mapping = Mapping { address_: address, length_: length };
+            if mapping.words().is_none() {
+                return Err(io::Error::new(io::ErrorKind::InvalidData,
+                  "The mapped image is not a whole number of aligned words"));
+            }
+            Ok(mapping)
+        }
+
+        /**
+         * Return the mapped words, or None if the length is not a multiple of the word size or
+         * the address is not aligned for u32. (A mapping is page-aligned.)
+         */
+        pub fn words(&self) -> Option<&[u32]> {
+            // Safety: The mapping is readable for length_ bytes until it is dropped.
+            let bytes = unsafe {
+                std::slice::from_raw_parts(self.address_ as *const u8, self.length_)
+            };
+            // Safety: Any four bytes are a valid u32. align_to returns the unaligned bytes at
+            // the start and end, which must be empty.
+            match unsafe { bytes.align_to::<u32>() } {
+                ([], words, []) => Some(words),
+                _ => None,
+            }
+        }
+    }
+
+    impl Drop for Mapping {
+        fn drop(&mut self) {
+            // Safety: The words borrowed from the mapping can't outlive it.
+            unsafe { munmap(self.address_, self.length_); }
+        }
+    }
+}
+
+/**
+ * The words of the image file, which are mapped if the platform supports it and the words are
+ * little-endian like the host, or else read into memory.
+ */
+enum Words {
+    #[cfg(all(unix, target_endian = "little", target_pointer_width = "64"))]
+    Mapped(mmap::Mapping),
+    Read(Vec<u32>),
+}
+
+impl Words {
+    fn get(&self) -> &[u32] {
+        match self {
+            #[cfg(all(unix, target_endian = "little", target_pointer_width = "64"))]
+            // Mapping::new checked that the words are aligned.
+            Words::Mapped(mapping) => mapping.words().unwrap_or(&[]),
+            Words::Read(words) => words,
+        }
+    }
+}
+
+// The object for each index in the object map, if it was created and is still held.
+type ObjectCache = Vec<Option<Weak<RefCell<MappedObject>>>>;
+
+fn get_cached(objects: &RefCell<ObjectCache>, index: usize)
+  -> Option<Rc<RefCell<MappedObject>>> {
+    objects.borrow().get(index)?.as_ref().and_then(Weak::upgrade)
+}
+
+fn cache(objects: &RefCell<ObjectCache>, index: usize, object: MappedObject)
+  -> Rc<RefCell<MappedObject>> {
+    let object = Rc::new(RefCell::new(object));
+    if let Some(entry) = objects.borrow_mut().get_mut(index) {
+        *entry = Some(Rc::downgrade(&object));
+    }
+    object
+}
+
+/**
+ * MappedImage maps an image file read-only instead of reading it into an r_code::Image, so that
+ * a large image of learned models loads without copying its atoms. When the file is opened, only
+ * the header, the segment sizes and the checksums of the segments other than the code segment
+ * are checked. Each object is checked (including its checksum if the image has a header) only
+ * when it is first got, with get_object or by resolving a reference of another MappedObject.
+ * This returns a MappedObject whose code is borrowed from the mapping. The file must not be
+ * changed while it is mapped.
+ */
+pub struct MappedImage {
+    words_: Words,
+    format_version_: u32,
+    opcode_table_hash_: u32,
+    timestamp_: u64,
+    // The offset in words_ of the data which starts with the definition segment.
+    data_offset_: usize,
+    names_offset_: usize,
+    // The offset in words_ of the object checksums, or None for a legacy image.
+    object_checksums_offset_: Option<usize>,
+    def_size_: u32,
+    map_size_: u32,
+    code_size_: u32,
+    reloc_size_: u32,
+    names_size_: u32,
+    objects_: RefCell<ObjectCache>,
+    // The invalidated objects from get_object_or_invalid, apart from objects_ so that
+    // get_object returns the error again.
+    invalid_objects_: RefCell<ObjectCache>,
+}
+
+impl MappedImage {
+    /**
+     * Map the image file and check the header and segment sizes. On platforms without mmap, the
+     * file is read into memory.
+     * \param path The image file in the format written by r_code::Image::write, with a header or
+     * legacy.
+     * \return The MappedImage, or an ImageError if the file can't be mapped, the format is not
+     * supported, the segment sizes don't match the file size or a checksum doesn't match.
+     */
+    #[allow(clippy::manual_is_multiple_of)]
+    pub fn open(path: impl AsRef<Path>) -> Result<Rc<Self>, ImageError> {
+        let file = File::open(path)?;
+        let length = file.metadata()?.len() as usize;
+        if length % 4 != 0 {
+            return Err(ImageError::Truncated("image word".to_string()));
+        }
+        if length == 0 {
+            return Err(ImageError::Truncated("image timestamp".to_string()));
+        }
+
+        #[cfg(all(unix, target_endian = "little", target_pointer_width = "64"))]
+        let words = Words::Mapped(mmap::Mapping::new(&file, length)?);
+        #[cfg(not(all(unix, target_endian = "little", target_pointer_width = "64")))]
+        let words = {
+            use std::io::{BufReader, Read};
+            let mut reader = BufReader::new(file);
+            let mut words = Vec::with_capacity(length / 4);
+            let mut word = [0u8; 4];
+            for _ in 0..length / 4 {
+                reader.read_exact(&mut word)?;
+                words.push(u32::from_le_bytes(word));
+            }
+            Words::Read(words)
+        };
+
+        Self::from_words(words)
+    }
+
+    /**
+     * Create a MappedImage from words in memory, for example an image received from another
+     * process. The words are used in place of a mapped file.
+     */
+    pub fn from_vec(words: Vec<u32>) -> Result<Rc<Self>, ImageError> {
+        Self::from_words(Words::Read(words))
+    }
+
+    fn from_words(words: Words) -> Result<Rc<Self>, ImageError> {
+        let all_words = words.get();
+        let mut image = MappedImage {
+            format_version_: LEGACY_FORMAT_VERSION, opcode_table_hash_: 0, timestamp_: 0,
+            data_offset_: 6, names_offset_: 0, object_checksums_offset_: None, def_size_: 0,
+            map_size_: 0, code_size_: 0, reloc_size_: 0, names_size_: 0,
+            objects_: RefCell::new(vec![]), invalid_objects_: RefCell::new(vec![]),
+            words_: Words::Read(vec![]),
+        };
+
+        let mut header = None;
+        if all_words.first() == Some(&IMAGE_MAGIC) {
+            let image_header = ImageHeader::read(all_words)?;
+            let sizes = image_header.sizes_;
+            image.format_version_ = all_words[1];
+            image.opcode_table_hash_ = image_header.opcode_table_hash_;
+            image.timestamp_ = image_header.timestamp_;
+            image.object_checksums_offset_ = Some(HEADER_SIZE);
+            image.data_offset_ = HEADER_SIZE + sizes[1] as usize;
+            image.set_sizes(sizes[0], sizes[1], sizes[2], sizes[3]);
+            image.names_size_ = sizes[4];
+            image.names_offset_ = image.data_offset_ + image.data_size();
+            get_words(all_words, image.names_offset_, sizes[4] as usize, "image data")?;
+            header = Some(image_header);
+        }
+        else {
+            // The timestamp is followed by the segment sizes.
+            image.timestamp_ = get_timestamp(get_words(all_words, 0, 2, "image timestamp")?);
+            let sizes = get_words(all_words, 2, 4, "segment sizes")?;
+            image.set_sizes(sizes[0], sizes[1], sizes[2], sizes[3]);
+            get_words(all_words, 6, image.data_size(), "image data")?;
+            // The optional names segment follows the data with its size.
+            if let Some(size) = all_words.get(6 + image.data_size()) {
+                image.names_offset_ = 7 + image.data_size();
+                get_words(all_words, image.names_offset_, *size as usize, "names segment")?;
+                image.names_size_ = *size;
+            }
+        }
+        image.words_ = words;
+
+        if let Some(header) = header {
+            // The code segment is checked by object in get_object.
+            header.verify_segment(0, image.get_def_segment())?;
+            header.verify_segment(1, image.get_map_segment())?;
+            header.verify_segment(3, image.get_reloc_segment())?;
+            header.verify_segment(4, image.get_names_segment())?;
+        }
+        image.objects_ = RefCell::new(vec![None; image.map_size_ as usize]);
+        image.invalid_objects_ = RefCell::new(vec![None; image.map_size_ as usize]);
+        Ok(Rc::new(image))
+    }
+
+    fn set_sizes(&mut self, def_size: u32, map_size: u32, code_size: u32, reloc_size: u32) {
+        self.def_size_ = def_size;
+        self.map_size_ = map_size;
+        self.code_size_ = code_size;
+        self.reloc_size_ = reloc_size;
+    }
+
+    /**
+     * Return the size of the definition, object map, code and relocation segments.
+     */
+    fn data_size(&self) -> usize {
+        self.def_size_ as usize + self.map_size_ as usize + self.code_size_ as usize +
+          self.reloc_size_ as usize
+    }
+
+    /**
+     * Return the format version, which is LEGACY_FORMAT_VERSION for an image without a header.
+     */
+    pub fn format_version(&self) -> u32 { self.format_version_ }
+
+    /**
+     * Return the hash of the opcode table in the header, which is 0 for a legacy image.
+     */
+    pub fn opcode_table_hash(&self) -> u32 { self.opcode_table_hash_ }
+
+    /**
+     * Return the time at which the image was compiled in microseconds, or 0 if it is not set.
+     */
+    pub fn timestamp(&self) -> u64 { self.timestamp_ }
+
+    pub fn def_size(&self) -> u32 { self.def_size_ }
+
+    pub fn map_size(&self) -> u32 { self.map_size_ }
+
+    pub fn code_size(&self) -> u32 { self.code_size_ }
+
+    pub fn reloc_size(&self) -> u32 { self.reloc_size_ }
+
+    pub fn names_size(&self) -> u32 { self.names_size_ }
+
+    pub fn get_object_count(&self) -> u32 {
+        self.map_size_
+    }
+
+    /**
+     * Return the definition, object map, code and relocation segments. The offsets in the object
+     * map are from its start.
+     */
+    fn data(&self) -> &[u32] {
+        &self.words_.get()[self.data_offset_..self.data_offset_ + self.data_size()]
+    }
+
+    pub fn get_def_segment(&self) -> &[u32] {
+        &self.data()[..self.def_size_ as usize]
+    }
+
+    pub fn get_map_segment(&self) -> &[u32] {
+        let start = self.def_size_ as usize;
+        &self.data()[start..start + self.map_size_ as usize]
+    }
+
+    pub fn get_code_segment(&self) -> &[u32] {
+        let start = self.def_size_ as usize + self.map_size_ as usize;
+        &self.data()[start..start + self.code_size_ as usize]
+    }
+
+    pub fn get_reloc_segment(&self) -> &[u32] {
+        let start = self.def_size_ as usize + self.map_size_ as usize + self.code_size_ as usize;
+        &self.data()[start..start + self.reloc_size_ as usize]
+    }
+
+    pub fn get_names_segment(&self) -> &[u32] {
+        &self.words_.get()[self.names_offset_..self.names_offset_ + self.names_size_ as usize]
+    }
+
+    /**
+     * Return the words as atoms.
+     * \param offset The offset in the data after the segment sizes.
+     * \param size The number of atoms, which must be in the data.
+     */
+    pub(crate) fn atoms(&self, offset: usize, size: usize) -> &[Atom] {
+        // Atom is repr(transparent) over u32, so the words have the size and alignment of atoms.
+        const _: () = assert!(std::mem::size_of::<Atom>() == std::mem::size_of::<u32>() &&
+                              std::mem::align_of::<Atom>() == std::mem::align_of::<u32>());
+        let words = &self.data()[offset..offset + size];
+        // Safety: The words are a valid, aligned slice of u32 and the layout of Atom is the same.
+        unsafe { std::slice::from_raw_parts(words.as_ptr() as *const Atom, words.len()) }
+    }
+
+    pub(crate) fn word(&self, offset: usize) -> u32 {
+        self.data()[offset]
+    }
+
+    /**
+     * Return the object at the index in the object map. The same object is returned while
+     * something still holds it. The first time, only this object's header, references, markers
+     * and views are checked against the image. The objects which it references are checked when
+     * they are first used.
+     * \param image The mapped image, which the object keeps alive.
+     * \param index The index of the object in the object map.
+     * \return The object, or an ImageError if the index is not in the object map, or the object
+     * is truncated, doesn't match its checksum or has a reference to an object which is not in
+     * the image.
+     */
+    pub fn get_object(image: &Rc<MappedImage>, index: usize)
+      -> Result<Rc<RefCell<dyn Code>>, ImageError> {
+        Ok(Self::get_mapped_object(image, index)?)
+    }
+
+    /**
+     * Return the object at the index as in get_object, as a MappedObject so that the caller can
+     * use MappedObject::load_reference, load_markers and load_views to get the error of an
+     * object which it references.
+     */
+    pub fn get_mapped_object(image: &Rc<MappedImage>, index: usize)
+      -> Result<Rc<RefCell<MappedObject>>, ImageError> {
+        if let Some(object) = get_cached(&image.objects_, index) {
+            return Ok(object);
+        }
+        if index >= image.map_size_ as usize {
+            return Err(ImageError::Invalid(format!(
+                "The object index {} is not in the object map of size {}", index,
+                image.map_size_)));
+        }
+
+        let layout = Self::check_object(image, index)?;
+        let object = MappedObject::new(
+            Rc::clone(image), layout.oid_, layout.code_offset_, layout.code_size_,
+            layout.references_size_, layout.markers_size_, layout.sys_views_);
+        Ok(cache(&image.objects_, index, object))
+    }
+
+    /**
+     * Return the object at the index as in get_object. If the object is not valid, return an
+     * invalidated MappedObject with no code which holds the ImageError (see
+     * MappedObject::get_error), so that a MappedObject can resolve its references through the
+     * Code trait, which can't return an error.
+     * \param index The index of the object, which check_object checked is in the object map.
+     */
+    pub(crate) fn get_object_or_invalid(image: &Rc<MappedImage>, index: usize)
+      -> Rc<RefCell<dyn Code>> {
+        if let Some(object) = get_cached(&image.invalid_objects_, index) {
+            return object;
+        }
+        Self::get_object(image, index).unwrap_or_else(|error| {
+            // The OID is the first word of the object if its offset is in the code segment.
+            let code_start = image.def_size_ as usize + image.map_size_ as usize;
+            let oid = image.get_map_segment().get(index).map(|offset| *offset as usize)
+                .filter(|offset| *offset >= code_start && *offset < code_start +
+                        image.code_size_ as usize)
+                .map_or(UNDEFINED_OID, |offset| image.word(offset));
+            cache(&image.invalid_objects_, index,
+                  MappedObject::invalid(Rc::clone(image), oid, error))
+        })
+    }
+
+    /**
+     * Check the layout of the object at the index and return it.
+     */
+    fn check_object(image: &Rc<MappedImage>, index: usize) -> Result<ObjectLayout, ImageError> {
+        let code_start = image.def_size_ as usize + image.map_size_ as usize;
+        let code_end = code_start + image.code_size_ as usize;
+        let offset = image.get_map_segment()[index] as usize;
+        if offset < code_start || offset >= code_end {
+            return Err(ImageError::Invalid(format!(
+                "The object map has offset {} for object {} which is not in the code segment",
+                offset, index)).offset_by(image.data_offset_ + image.def_size_ as usize + index));
+        }
+        let object_data = &image.data()[offset..code_end];
+        if let Some(checksums_offset) = image.object_checksums_offset_ {
+            let object_words = get_object_words(
+                image.data(), image.def_size_, image.map_size_, image.code_size_, index);
+            if checksum(object_words) != image.words_.get()[checksums_offset + index] {
+                return Err(ImageError::ChecksumMismatch {
+                    segment: "code".to_string(), object_index: Some(index),
+                    oid: object_data.first().copied() });
+            }
+        }
+
+        Self::check_layout(image, index, offset, object_data)
+            .map_err(|error| error.offset_by(image.data_offset_ + offset))
+    }
+
+    /**
+     * Check the header, references, markers and views of the object data, which starts at the
+     * offset in the image data and ends with the code segment.
+     */
+    fn check_layout(image: &Rc<MappedImage>, index: usize, offset: usize, object_data: &[u32])
+      -> Result<ObjectLayout, ImageError> {
+        let header = get_words(object_data, 0, 5, "object header")?;
+        let oid = header[0];
+        let (code_size, references_size, markers_size, views_size) =
+          (header[1] as usize, header[2] as usize, header[3] as usize, header[4] as usize);
+        let object_count = image.map_size_ as usize;
+        let check_indices = |indices: &[u32]| -> Result<(), ImageError> {
+            match indices.iter().find(|i| **i as usize >= object_count) {
+                Some(reference_index) => Err(ImageError::DanglingReference {
+                    object_index: index, oid, reference_index: *reference_index }),
+                None => Ok(()),
+            }
+        };
+
+        if code_size > u16::MAX as usize {
+            return Err(ImageError::Invalid(format!(
+                "Object {} (OID {}) has {} atoms, more than can be indexed", index, oid,
+                code_size)).offset_by(1));
+        }
+        let mut object_offset = 5;
+        get_words(object_data, object_offset, code_size, "object code")?;
+        object_offset += code_size;
+        let references = get_words(object_data, object_offset, references_size, "references")?;
+        check_indices(references)?;
+        if references_size > u16::MAX as usize {
+            return Err(ImageError::Invalid(format!(
+                "Object {} (OID {}) has {} references, more than can be indexed", index, oid,
+                references_size)).offset_by(2));
+        }
+        object_offset += references_size;
+        let markers = get_words(object_data, object_offset, markers_size, "object markers")?;
+        check_indices(markers)?;
+        object_offset += markers_size;
+
+        let mut views = vec![];
+        for _ in 0..views_size {
+            let mut view = SysView::default();
+            object_offset += view.read(&object_data[object_offset..])
+                .map_err(|error| error.offset_by(object_offset))?;
+            check_indices(&view.references_)?;
+            if view.references_.len() > 2 {
+                return Err(ImageError::Invalid(format!(
+                    "Object {} (OID {}) view {} has {} references, but a view only has a host \
+                     and an origin", index, oid, views.len(), view.references_.len())));
+            }
+            views.push(view);
+        }
+
+        Ok(ObjectLayout {
+            oid_: oid, code_offset_: offset + 5, code_size_: code_size,
+            references_size_: references_size, markers_size_: markers_size, sys_views_: views })
+    }
+}
+
+/**
+ * The checked layout of an object in a MappedImage, used to create its MappedObject.
+ */
+struct ObjectLayout {
+    oid_: u32,
+    // The offset of the code in the image data.
+    code_offset_: usize,
+    code_size_: usize,
+    references_size_: usize,
+    markers_size_: usize,
+    sys_views_: Vec<SysView>,
+}
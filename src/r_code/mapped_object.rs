@@ -0,0 +1,354 @@
+use std::rc::Rc;
+use std::cell::RefCell;
+use std::fmt::Write;
+#[cfg(feature = "with_detail_oid")]
+use std::sync::atomic::Ordering;
+use once_cell::unsync::OnceCell;
+use super::atom;
+use super::atom::Atom;
+use super::code::Code;
+use super::code::CodeTrace;
+use super::code::object_address;
+use super::image::ImageError;
+use super::local_object::LocalObject;
+#[cfg(feature = "with_detail_oid")]
+use super::local_object::LAST_DETAIL_OID;
+use super::mapped_image::MappedImage;
+use super::object_names::ObjectNames;
+use super::sys_object::SysView;
+use super::view::View;
+
+/**
+ * A MappedObject is a read-only Code view of an object in a MappedImage. Its code is borrowed
+ * from the mapped words, and its references, markers and views are resolved from the image
+ * when they are first used. The first call which modifies the object (such as set_code,
+ * set_reference, add_view or invalidate) copies it into a LocalObject which handles all later
+ * calls, so the object keeps its identity as an Rc while the mapping stays read-only.
+ * Each referenced object is checked when it is first resolved. load_reference, load_markers and
+ * load_views return the ImageError if it is not valid. The methods of the Code trait can't
+ * return an error, so they resolve it to an invalidated MappedObject with no code whose
+ * get_error returns the error.
+ * Create a MappedObject with MappedImage::get_object.
+ */
+pub struct MappedObject {
+    image_: Rc<MappedImage>,
+    oid_: u32,
+    // The offset of the code in the image data. The references and markers follow it.
+    code_offset_: usize,
+    code_size_: usize,
+    references_size_: usize,
+    markers_size_: usize,
+    sys_views_: Vec<SysView>,
+    // The views with resolved references, built from sys_views_ when first used.
+    views_: OnceCell<Vec<View>>,
+    local_: Option<LocalObject>,
+    // The error if this stands for an object of the image which is not valid.
+    error_: Option<ImageError>,
+    #[cfg(feature = "with_detail_oid")]
+    detail_oid_: u64,
+}
+
+impl MappedObject {
+    /**
+     * Create a MappedObject for an object whose layout was checked by MappedImage::get_object.
+     */
+    pub(crate) fn new(
+      image: Rc<MappedImage>, oid: u32, code_offset: usize, code_size: usize,
+      references_size: usize, markers_size: usize, sys_views: Vec<SysView>) -> Self {
+        MappedObject {
+            image_: image, oid_: oid, code_offset_: code_offset, code_size_: code_size,
+            references_size_: references_size, markers_size_: markers_size,
+            sys_views_: sys_views, views_: OnceCell::new(), local_: None, error_: None,
+            #[cfg(feature = "with_detail_oid")]
+            detail_oid_: LAST_DETAIL_OID.fetch_add(1, Ordering::SeqCst),
+        }
+    }
+
+    /**
+     * Create an invalidated MappedObject with no code, references, markers or views for an
+     * object of the image which is not valid.
+     * \param oid The OID, or UNDEFINED_OID if it can't be read.
+     * \param error The error from checking the object.
+     */
+    pub(crate) fn invalid(image: Rc<MappedImage>, oid: u32, error: ImageError) -> Self {
+        let mut object = Self::new(image, oid, 0, 0, 0, 0, vec![]);
+        object.error_ = Some(error);
+        object
+    }
+
+    /**
+     * Return the error if this object stands for an object of the image which is not valid, as
+     * returned by resolving a reference through the Code trait.
+     */
+    pub fn get_error(&self) -> Option<&ImageError> {
+        self.error_.as_ref()
+    }
+
+    /**
+     * Return the code borrowed from the mapped image. After the object is modified, this is still
+     * the code in the image, not the modified code.
+     */
+    pub fn mapped_code(&self) -> &[Atom] {
+        self.image_.atoms(self.code_offset_, self.code_size_)
+    }
+
+    /**
+     * Return true if the object was modified, so that it is now handled by a LocalObject.
+     */
+    pub fn is_materialized(&self) -> bool {
+        self.local_.is_some()
+    }
+
+    /**
+     * Return reference i from the image, or the LocalObject if the object was modified.
+     * \return The referenced object, or an ImageError if i is out of range or the referenced
+     * object is not valid.
+     */
+    pub fn load_reference(&self, i: u16) -> Result<Rc<RefCell<dyn Code>>, ImageError> {
+        if let Some(local) = &self.local_ {
+            return local.try_get_reference(i).ok_or_else(|| self.out_of_range(i));
+        }
+        if i as usize >= self.references_size_ {
+            return Err(self.out_of_range(i));
+        }
+        MappedImage::get_object(&self.image_, self.reference_index(i))
+    }
+
+    /**
+     * Return the markers from the image, or of the LocalObject if the object was modified.
+     * \return The markers, or the ImageError of the first marker which is not valid.
+     */
+    pub fn load_markers(&self) -> Result<Vec<Rc<RefCell<dyn Code>>>, ImageError> {
+        match &self.local_ {
+            Some(local) => Ok(local.markers()),
+            None => (0..self.markers_size_)
+                .map(|i| MappedImage::get_object(&self.image_, self.marker_index(i))).collect(),
+        }
+    }
+
+    /**
+     * Return the views from the image, or of the LocalObject if the object was modified.
+     * \return The views, or the ImageError of the first host or origin which is not valid.
+     */
+    pub fn load_views(&self) -> Result<Vec<&View>, ImageError> {
+        if let Some(local) = &self.local_ {
+            return Ok(local.views());
+        }
+        for sys_view in &self.sys_views_ {
+            for reference in &sys_view.references_ {
+                MappedImage::get_object(&self.image_, *reference as usize)?;
+            }
+        }
+        Ok(self.mapped_views().iter().collect())
+    }
+
+    fn out_of_range(&self, i: u16) -> ImageError {
+        ImageError::Invalid(format!("MappedObject {}: Reference {} is out of range", self.oid_, i))
+    }
+
+    // check_layout checked that the indexes of this object are in the object map.
+    fn reference_index(&self, i: u16) -> usize {
+        self.image_.word(self.code_offset_ + self.code_size_ + i as usize) as usize
+    }
+
+    fn marker_index(&self, i: usize) -> usize {
+        self.image_.word(self.code_offset_ + self.code_size_ + self.references_size_ + i) as usize
+    }
+
+    fn get_object(&self, index: usize) -> Rc<RefCell<dyn Code>> {
+        MappedImage::get_object_or_invalid(&self.image_, index)
+    }
+
+    fn mapped_views(&self) -> &Vec<View> {
+        self.views_.get_or_init(|| {
+            self.sys_views_.iter().map(|sys_view| {
+                let mut view = self.build_view(sys_view);
+                // check_layout checked that a view has at most the host and origin references.
+                for (i, reference) in sys_view.references_.iter().enumerate() {
+                    view.set_reference(i as u16, &self.get_object(*reference as usize));
+                }
+                view
+            }).collect()
+        })
+    }
+
+    /**
+     * Copy the object into a LocalObject if this wasn't already done, and return it.
+     */
+    fn materialize(&mut self) -> &mut LocalObject {
+        if self.local_.is_none() {
+            let mut local = LocalObject::default();
+            local.set_oid(self.oid_);
+            local.resize_code(self.code_size_ as u16);
+            for (i, a) in self.mapped_code().iter().enumerate() {
+                local.set_code(i as u16, *a);
+            }
+            for i in 0..self.references_size() {
+                local.set_reference(i, &self.get_reference(i));
+            }
+            for marker in self.markers() {
+                local.add_marker(&marker);
+            }
+            self.mapped_views();
+            for view in self.views_.take().unwrap_or_default() {
+                local.add_view(view);
+            }
+            if self.error_.is_some() {
+                local.invalidate();
+            }
+            self.local_ = Some(local);
+        }
+        self.local_.as_mut().unwrap()
+    }
+}
+
+impl Code for MappedObject {
+    #[cfg(feature = "with_detail_oid")]
+    fn get_detail_oid(&self) -> u64 {
+        self.detail_oid_
+    }
+
+    #[cfg(feature = "with_detail_oid")]
+    fn set_detail_oid(&mut self, detail_oid: u64) {
+        self.detail_oid_ = detail_oid;
+        // Make sure the next assigned detail OID is higher.
+        LAST_DETAIL_OID.store(detail_oid + 1, Ordering::Relaxed);
+    }
+
+    fn get_oid(&self) -> u32 {
+        self.oid_
+    }
+
+    fn set_oid(&mut self, oid: u32) {
+        self.oid_ = oid;
+        if let Some(local) = &mut self.local_ {
+            local.set_oid(oid);
+        }
+    }
+
+    fn code(&self, i: u16) -> Atom {
+        match &self.local_ {
+            Some(local) => local.code(i),
+            None => self.mapped_code()[i as usize],
+        }
+    }
+
+    fn set_code(&mut self, i: u16, a: Atom) {
+        self.materialize().set_code(i, a);
+    }
+
+    fn code_size(&self) -> u16 {
+        match &self.local_ {
+            Some(local) => local.code_size(),
+            None => self.code_size_ as u16,
+        }
+    }
+
+    fn resize_code(&mut self, new_size: u16) {
+        self.materialize().resize_code(new_size);
+    }
+
+    fn set_reference(&mut self, i: u16, object: &Rc<RefCell<dyn Code>>) {
+        self.materialize().set_reference(i, object);
+    }
+
+    fn get_reference(&self, i: u16) -> Rc<RefCell<dyn Code>> {
+        match &self.local_ {
+            Some(local) => local.get_reference(i),
+            None => {
+                if i as usize >= self.references_size_ {
+                    panic!("MappedObject {}: Reference {} is out of range", self.oid_, i);
+                }
+                self.get_object(self.reference_index(i))
+            }
+        }
+    }
+
+    fn try_get_reference(&self, i: u16) -> Option<Rc<RefCell<dyn Code>>> {
+        self.load_reference(i).ok()
+    }
+
+    fn references_size(&self) -> u16 {
+        match &self.local_ {
+            Some(local) => local.references_size(),
+            None => self.references_size_ as u16,
+        }
+    }
+
+    fn clear_references(&mut self) {
+        self.materialize().clear_references();
+    }
+
+    fn markers(&self) -> Vec<Rc<RefCell<dyn Code>>> {
+        match &self.local_ {
+            Some(local) => local.markers(),
+            None => {
+                (0..self.markers_size_).map(|i| self.get_object(self.marker_index(i))).collect()
+            },
+        }
+    }
+
+    fn add_marker(&mut self, marker: &Rc<RefCell<dyn Code>>) {
+        self.materialize().add_marker(marker);
+    }
+
+    fn remove_marker(&mut self, marker: &Rc<RefCell<dyn Code>>) {
+        self.materialize().remove_marker(marker);
+    }
+
+    fn add_view(&mut self, view: View) -> bool {
+        self.materialize().add_view(view)
+    }
+
+    fn get_view(&self, group: &Rc<RefCell<dyn Code>>) -> Option<&View> {
+        match &self.local_ {
+            Some(local) => local.get_view(group),
+            None => {
+                let address = object_address(group);
+                self.mapped_views().iter().find(|view| {
+                    view.get_host().map(|host| object_address(&host)) == Some(address)
+                })
+            }
+        }
+    }
+
+    fn get_view_mut(&mut self, group: &Rc<RefCell<dyn Code>>) -> Option<&mut View> {
+        self.materialize().get_view_mut(group)
+    }
+
+    fn remove_view(&mut self, group: &Rc<RefCell<dyn Code>>) -> Option<View> {
+        self.materialize().remove_view(group)
+    }
+
+    fn views(&self) -> Vec<&View> {
+        match &self.local_ {
+            Some(local) => local.views(),
+            None => self.mapped_views().iter().collect(),
+        }
+    }
+
+    fn clear_views(&mut self) {
+        self.materialize().clear_views();
+    }
+
+    fn is_invalidated(&self) -> bool {
+        match &self.local_ {
+            Some(local) => local.is_invalidated(),
+            None => self.error_.is_some(),
+        }
+    }
+
+    fn invalidate(&mut self) -> bool {
+        self.materialize().invalidate()
+    }
+}
+
+impl CodeTrace for MappedObject {
+    fn trace_at(&self, i: u16, out: &mut impl Write, context: &mut atom::TraceContext) {
+        super::code::trace_at(self, i, out, context, &ObjectNames::new());
+    }
+
+    fn trace_out(&self, out: &mut impl Write) {
+        super::code::trace_out(self, out, &ObjectNames::new());
+    }
+}
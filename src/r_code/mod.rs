@@ -1,14 +1,31 @@
 pub mod atom;
 pub mod code;
+pub mod collector;
+pub mod compact_object;
+pub mod image;
 pub mod image_object;
 pub mod local_object;
+pub mod mapped_image;
+pub mod mapped_object;
+pub mod object_names;
+pub mod object_registry;
 pub mod sys_object;
 pub mod utils;
+pub mod view;
 
 pub use self::atom::Atom;
 pub use self::code::Code;
 pub use self::code::CodeTrace;
+pub use self::collector::Collector;
+pub use self::compact_object::CompactObject;
+pub use self::image::Image;
 pub use self::image_object::ImageObject;
 pub use self::local_object::LocalObject;
+pub use self::mapped_image::MappedImage;
+pub use self::mapped_object::MappedObject;
+pub use self::object_names::ObjectNames;
+pub use self::object_registry::ObjectRegistry;
 pub use self::sys_object::SysObject;
+pub use self::sys_object::SysView;
 pub use self::utils::Utils;
+pub use self::view::View;
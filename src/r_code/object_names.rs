@@ -0,0 +1,108 @@
+use std::collections::{BTreeMap, HashMap};
+use super::image::{get_string_size, get_words, read_string, write_string, ImageError};
+
+/**
+ * ObjectNames is the symbol table of the names which the user gave to objects in the source
+ * code (for example self, hand or position), indexed by OID with lookup both ways.
+ */
+#[derive(Clone, Default)]
+pub struct ObjectNames {
+    // Ordered by OID so that writing the table always gives the same image.
+    names_: BTreeMap<u32, String>,
+    oids_: HashMap<String, u32>,
+}
+
+impl ObjectNames {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /**
+     * Set the name of the object with the OID, replacing its previous name.
+     * \param oid The OID.
+     * \param name The name.
+     * \return True for success, false if another OID already has the name.
+     */
+    pub fn insert(&mut self, oid: u32, name: &str) -> bool {
+        if let Some(other_oid) = self.oids_.get(name) {
+            return *other_oid == oid;
+        }
+
+        if let Some(previous_name) = self.names_.insert(oid, name.to_string()) {
+            self.oids_.remove(&previous_name);
+        }
+        self.oids_.insert(name.to_string(), oid);
+        true
+    }
+
+    /**
+     * Remove the name of the object with the OID.
+     * \return The removed name, or None if the OID has no name.
+     */
+    pub fn remove(&mut self, oid: u32) -> Option<String> {
+        let name = self.names_.remove(&oid)?;
+        self.oids_.remove(&name);
+        Some(name)
+    }
+
+    pub fn get_name(&self, oid: u32) -> Option<&str> {
+        self.names_.get(&oid).map(String::as_str)
+    }
+
+    pub fn get_oid(&self, name: &str) -> Option<u32> {
+        self.oids_.get(name).copied()
+    }
+
+    pub fn len(&self) -> usize {
+        self.names_.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names_.is_empty()
+    }
+
+    /**
+     * Return an iterator over (OID, name) in order of OID.
+     */
+    pub fn iter(&self) -> impl Iterator<Item = (u32, &str)> {
+        self.names_.iter().map(|(oid, name)| (*oid, name.as_str()))
+    }
+
+    /**
+     * Read the words: name count, then for each name the OID and the string as in write_string.
+     * \return The number of words read.
+     */
+    pub fn read(&mut self, data: &[u32]) -> Result<usize, ImageError> {
+        let count = get_words(data, 0, 1, "object name count")?[0];
+        let mut offset = 1;
+        *self = ObjectNames::default();
+        for _ in 0..count {
+            let oid = get_words(data, offset, 1, "object name OID")?[0];
+            let (name, size) = read_string(data, offset + 1)?;
+            if !self.insert(oid, &name) {
+                return Err(ImageError::Invalid(format!(
+                    "The object name {} is used by more than one OID", name)).offset_by(offset));
+            }
+            offset += 1 + size;
+        }
+        Ok(offset)
+    }
+
+    /**
+     * Write the table in the format read by read.
+     * \param data The destination, which must have at least get_size() words.
+     */
+    pub fn write(&self, data: &mut [u32]) {
+        data[0] = self.names_.len() as u32;
+        let mut offset = 1;
+        for (oid, name) in &self.names_ {
+            data[offset] = *oid;
+            write_string(&mut data[offset + 1..], name);
+            offset += 1 + get_string_size(name);
+        }
+    }
+
+    pub fn get_size(&self) -> usize {
+        1 + self.names_.values().map(|name| 1 + get_string_size(name)).sum::<usize>()
+    }
+}
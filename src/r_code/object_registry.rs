@@ -0,0 +1,149 @@
+use std::rc::{Rc, Weak};
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use super::code::Code;
+use super::code::same_object;
+
+pub const UNDEFINED_OID: u32 = 0xFFFFFFFF;
+
+/**
+ * ObjectRegistry allocates the OIDs of a memory and resolves an OID to its object. It only holds
+ * weak references, so an object which is dropped is no longer resolved and its OID can be
+ * reused by register_with_oid (but is never returned again by allocate_oid). An OID from
+ * allocate_oid is reserved until it is registered with register_allocated, so that
+ * register_with_oid can't give it to another object.
+ */
+#[derive(Default)]
+pub struct ObjectRegistry {
+    next_oid_: u32,
+    objects_: HashMap<u32, Weak<RefCell<dyn Code>>>,
+    // The OIDs returned by allocate_oid which are not registered yet.
+    reserved_: HashSet<u32>,
+}
+
+impl ObjectRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /**
+     * Return a new OID which has not been allocated or registered in this registry, and reserve
+     * it. Register the object which gets the OID with register_allocated, or release the OID with
+     * unregister.
+     * \return The OID, or None if every OID below UNDEFINED_OID is used.
+     */
+    pub fn allocate_oid(&mut self) -> Option<u32> {
+        while self.objects_.contains_key(&self.next_oid_) {
+            self.next_oid_ = self.get_next_oid(self.next_oid_)?;
+        }
+        let oid = self.next_oid_;
+        if oid == UNDEFINED_OID {
+            return None;
+        }
+        // When the last OID is allocated, next_oid_ becomes UNDEFINED_OID so that the next call
+        // returns None.
+        self.next_oid_ = oid + 1;
+        self.reserved_.insert(oid);
+        Some(oid)
+    }
+
+    /**
+     * Return the OID after oid, or None if oid is the last OID before UNDEFINED_OID.
+     */
+    fn get_next_oid(&self, oid: u32) -> Option<u32> {
+        oid.checked_add(1).filter(|next_oid| *next_oid != UNDEFINED_OID)
+    }
+
+    /**
+     * Allocate a new OID, set it as the object's OID and register the object.
+     * \param object The object to register.
+     * \return The new OID, or None if every OID is used.
+     */
+    pub fn register(&mut self, object: &Rc<RefCell<dyn Code>>) -> Option<u32> {
+        let oid = self.allocate_oid()?;
+        object.borrow_mut().set_oid(oid);
+        self.register_allocated(object);
+        Some(oid)
+    }
+
+    /**
+     * Register the object with the OID that it got from allocate_oid.
+     * \param object The object to register.
+     * \return True for success, false if the OID of the object is not reserved by allocate_oid or
+     * was already registered.
+     */
+    pub fn register_allocated(&mut self, object: &Rc<RefCell<dyn Code>>) -> bool {
+        let oid = object.borrow().get_oid();
+        if !self.reserved_.remove(&oid) {
+            return false;
+        }
+        self.objects_.insert(oid, Rc::downgrade(object));
+        true
+    }
+
+    /**
+     * Register the object with the OID that it already has, for example an object loaded from an
+     * image. Later calls to allocate_oid return a higher OID.
+     * \param object The object to register.
+     * \return True for success, false if another live object is registered with the same OID, the
+     * OID is reserved by allocate_oid, or the OID is UNDEFINED_OID or the OID before it, which
+     * would leave no OID for allocate_oid.
+     */
+    pub fn register_with_oid(&mut self, object: &Rc<RefCell<dyn Code>>) -> bool {
+        let oid = object.borrow().get_oid();
+        let next_oid = match self.get_next_oid(oid) {
+            Some(next_oid) => next_oid,
+            None => return false,
+        };
+        if self.reserved_.contains(&oid) {
+            return false;
+        }
+        if let Some(existing) = self.get(oid) {
+            return same_object(&existing, object);
+        }
+
+        self.objects_.insert(oid, Rc::downgrade(object));
+        if next_oid > self.next_oid_ {
+            self.next_oid_ = next_oid;
+        }
+        true
+    }
+
+    /**
+     * Remove the object with the OID from the registry, or release the OID if it is reserved by
+     * allocate_oid.
+     * \return True if the OID was registered or reserved.
+     */
+    pub fn unregister(&mut self, oid: u32) -> bool {
+        self.objects_.remove(&oid).is_some() | self.reserved_.remove(&oid)
+    }
+
+    /**
+     * Return the object with the OID, or None if it is not registered or has been dropped.
+     */
+    pub fn get(&self, oid: u32) -> Option<Rc<RefCell<dyn Code>>> {
+        self.objects_.get(&oid).and_then(Weak::upgrade)
+    }
+
+    /**
+     * Return the number of registered objects, including dropped objects which haven't been
+     * purged.
+     */
+    pub fn len(&self) -> usize {
+        self.objects_.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.objects_.is_empty()
+    }
+
+    /**
+     * Remove the entries of dropped objects.
+     * \return The number of removed entries.
+     */
+    pub fn purge(&mut self) -> usize {
+        let previous_len = self.objects_.len();
+        self.objects_.retain(|_, object| object.strong_count() > 0);
+        previous_len - self.objects_.len()
+    }
+}
@@ -1,7 +1,203 @@
+use std::rc::Rc;
+use std::cell::RefCell;
 use super::Atom;
+use super::code::Code;
+use super::image::{get_words, ImageError};
+use super::image_object::ImageObject;
+use super::view::View;
 
+/**
+ * SysView is the persisted form of a view, where the references are indexes of objects in the
+ * image. See View for the layout of code_.
+ */
+#[derive(Default)]
+pub struct SysView {
+    pub code_: Vec<Atom>,
+    pub references_: Vec<u32>,
+}
+
+impl SysView {
+    /**
+     * Create a SysView from the view, replacing its host and origin with their index in the
+     * image.
+     * \param view The view.
+     * \param get_index Return the index in the image of an object, or None if it is not there.
+     * \return The SysView, or an ImageError if the host or origin is not in the image.
+     */
+    pub fn from_view(view: &View, get_index: impl Fn(&Rc<RefCell<dyn Code>>) -> Option<u32>)
+      -> Result<Self, ImageError> {
+        let mut references = vec![];
+        for reference in (0..2).map_while(|i| view.get_reference(i)) {
+            references.push(get_index(&reference).ok_or_else(|| ImageError::Invalid(format!(
+                "A view references object {} which is not in the image",
+                reference.borrow().get_oid())))?);
+        }
+        Ok(SysView { code_: (0..view.code_size()).map(|i| view.code(i)).collect(),
+                     references_: references })
+    }
+}
+
+impl ImageObject for SysView {
+    /**
+     * Read the words: code size, references size, code atoms, reference indexes.
+     */
+    fn read(&mut self, data: &[u32]) -> Result<usize, ImageError> {
+        let sizes = get_words(data, 0, 2, "view sizes")?;
+        let (code_size, references_size) = (sizes[0] as usize, sizes[1] as usize);
+        let mut offset = 2;
+
+        self.code_ = get_words(data, offset, code_size, "view code")?
+            .iter().map(|word| Atom::new(*word)).collect();
+        offset += code_size;
+        self.references_ = get_words(data, offset, references_size, "view references")?.to_vec();
+        offset += references_size;
+        Ok(offset)
+    }
+
+    fn write(&self, data: &mut [u32]) {
+        data[0] = self.code_.len() as u32;
+        data[1] = self.references_.len() as u32;
+        let mut offset = 2;
+        for a in &self.code_ {
+            data[offset] = a.atom_;
+            offset += 1;
+        }
+        data[offset..offset + self.references_.len()].copy_from_slice(&self.references_);
+    }
+
+    fn get_size(&self) -> usize {
+        2 + self.code_.len() + self.references_.len()
+    }
+}
+
+/**
+ * SysObject is the persisted form of an object, where the references are indexes of objects in
+ * the image.
+ */
 #[derive(Default)]
 pub struct SysObject {
+    pub oid_: u32,
+    // The detail OID is only unique while the program runs, so it is not written to an image.
+    #[cfg(feature = "with_detail_oid")]
+    pub detail_oid_: u64,
     pub code_: Vec<Atom>,
     pub references_: Vec<u16>,
+    // Indexes in the image of the markers which reference this object.
+    pub markers_: Vec<u32>,
+    pub views_: Vec<SysView>,
+}
+
+impl SysObject {
+    /**
+     * Create a SysObject from the source object with its OID, code, references, markers and
+     * views, replacing each referenced object by its index in the image. Markers which are not
+     * in the image are left out. The views are sorted by host so that the same object is always
+     * written the same way.
+     * \param source The object.
+     * \param get_index Return the index in the image of an object, or None if it is not there.
+     * \return The SysObject, or an ImageError if a reference or the host or origin of a view is
+     * not in the image, or a reference index is too large.
+     */
+    pub fn from_code(
+      source: &dyn Code, get_index: impl Fn(&Rc<RefCell<dyn Code>>) -> Option<u32>)
+      -> Result<Self, ImageError> {
+        let mut references = vec![];
+        for i in 0..source.references_size() {
+            let reference = source.get_reference(i);
+            let index = get_index(&reference).ok_or_else(|| ImageError::Invalid(format!(
+                "Object {} references object {} which is not in the image", source.get_oid(),
+                reference.borrow().get_oid())))?;
+            if index > u16::MAX as u32 {
+                return Err(ImageError::Invalid(format!(
+                    "Object {} has reference index {} which is too large", source.get_oid(),
+                    index)));
+            }
+            references.push(index as u16);
+        }
+
+        let mut views = source.views().iter().map(|view| SysView::from_view(view, &get_index))
+            .collect::<Result<Vec<_>, _>>()?;
+        views.sort_by_key(|view| view.references_.first().copied());
+
+        Ok(SysObject {
+            oid_: source.get_oid(),
+            #[cfg(feature = "with_detail_oid")]
+            detail_oid_: source.get_detail_oid(),
+            code_: (0..source.code_size()).map(|i| source.code(i)).collect(),
+            references_: references,
+            markers_: source.markers().iter().filter_map(&get_index).collect(),
+            views_: views,
+        })
+    }
+}
+
+impl ImageObject for SysObject {
+    /**
+     * Read the words: OID, code size, references size, markers size, views size, code atoms,
+     * reference indexes, marker indexes, views.
+     */
+    fn read(&mut self, data: &[u32]) -> Result<usize, ImageError> {
+        let header = get_words(data, 0, 5, "object header")?;
+        self.oid_ = header[0];
+        let code_size = header[1] as usize;
+        let references_size = header[2] as usize;
+        let markers_size = header[3] as usize;
+        let views_size = header[4] as usize;
+        let mut offset = 5;
+
+        self.code_ = get_words(data, offset, code_size, "object code")?
+            .iter().map(|word| Atom::new(*word)).collect();
+        offset += code_size;
+
+        self.references_ = vec![];
+        let references = get_words(data, offset, references_size, "object references")?;
+        for (i, reference) in references.iter().enumerate() {
+            if *reference > u16::MAX as u32 {
+                return Err(ImageError::Invalid(format!(
+                    "Object {} has reference index {} which is too large", self.oid_,
+                    reference)).offset_by(offset + i));
+            }
+            self.references_.push(*reference as u16);
+        }
+        offset += references_size;
+
+        self.markers_ = get_words(data, offset, markers_size, "object markers")?.to_vec();
+        offset += markers_size;
+
+        self.views_ = vec![];
+        for _ in 0..views_size {
+            let mut view = SysView::default();
+            offset += view.read(&data[offset..]).map_err(|error| error.offset_by(offset))?;
+            self.views_.push(view);
+        }
+        Ok(offset)
+    }
+
+    fn write(&self, data: &mut [u32]) {
+        data[0] = self.oid_;
+        data[1] = self.code_.len() as u32;
+        data[2] = self.references_.len() as u32;
+        data[3] = self.markers_.len() as u32;
+        data[4] = self.views_.len() as u32;
+        let mut offset = 5;
+        for a in &self.code_ {
+            data[offset] = a.atom_;
+            offset += 1;
+        }
+        for reference in &self.references_ {
+            data[offset] = *reference as u32;
+            offset += 1;
+        }
+        data[offset..offset + self.markers_.len()].copy_from_slice(&self.markers_);
+        offset += self.markers_.len();
+        for view in &self.views_ {
+            view.write(&mut data[offset..]);
+            offset += view.get_size();
+        }
+    }
+
+    fn get_size(&self) -> usize {
+        5 + self.code_.len() + self.references_.len() + self.markers_.len() +
+          self.views_.iter().map(|view| view.get_size()).sum::<usize>()
+    }
 }
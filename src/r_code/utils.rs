@@ -1,6 +1,7 @@
 use crate::core::UTimestamp;
 use crate::core::UDuration;
 use crate::core::u_duration::microseconds;
+use super::Atom;
 
 pub struct Utils {
 }
@@ -17,7 +18,7 @@ impl Utils {
    */
    pub fn to_string_s_ms_us(timestamp: UTimestamp, time_reference: UTimestamp) -> String {
       let duration = timestamp - time_reference;
-      let t = duration.as_microseconds().abs() as u64;
+      let t = duration.as_microseconds().unsigned_abs();
 
       let us = t % 1000;
       let ms = t / 1000;
@@ -36,8 +37,9 @@ impl Utils {
    * \param duration The duration.
    * \return The formatted time string.
    */
+   #[allow(clippy::manual_is_multiple_of)]
    pub fn to_string_us(duration: UDuration) -> String {
-      let us = duration.as_microseconds().abs() as u64;
+      let us = duration.as_microseconds().unsigned_abs();
 
       let sign = if duration < microseconds(0) { "-" } else { "" };
       if us % 1000 != 0 {
@@ -55,6 +57,28 @@ impl Utils {
       }
    }
 
+  /**
+   * Get the timestamp from the TIMESTAMP atom and the two following atoms.
+   * \param iptr The slice of atoms starting with the TIMESTAMP atom.
+   * \return The timestamp.
+   */
+   pub fn get_timestamp(iptr: &[Atom]) -> UTimestamp {
+      let t = ((iptr[1].atom_ as u64) << 32) | iptr[2].atom_ as u64;
+      UTimestamp::from_duration(microseconds(t as i64))
+   }
+
+  /**
+   * Set the TIMESTAMP atom and the two following atoms from the timestamp.
+   * \param iptr The slice of atoms to receive the TIMESTAMP atom and two data atoms.
+   * \param timestamp The timestamp.
+   */
+   pub fn set_timestamp(iptr: &mut [Atom], timestamp: UTimestamp) {
+      let t = timestamp.time_since_epoch().as_microseconds() as u64;
+      iptr[0] = Atom::Timestamp();
+      iptr[1].atom_ = (t >> 32) as u32;
+      iptr[2].atom_ = (t & 0x00000000FFFFFFFF) as u32;
+   }
+
    pub fn relative_time(t: UTimestamp) -> String {
         // TODO: Use module static time_reference
         let debug_time_reference = UTimestamp::from_duration(microseconds(0));
@@ -0,0 +1,265 @@
+use std::rc::Rc;
+use std::cell::RefCell;
+use std::fmt::Write;
+use crate::core::UTimestamp;
+use super::atom;
+use super::atom::Atom;
+use super::code::Code;
+use super::code::clamp_unit;
+use super::sys_object::SysView;
+use super::utils::Utils;
+
+// Member indexes in the code of a view: (view sync ijt sln res host org) for a standard view,
+// followed by act for a program or model view, or by cov and vis for a group view. The ijt
+// member is an I_PTR to the timestamp structure which follows the members.
+pub const VIEW_OPCODE: u16 = 0;
+pub const VIEW_SYNC: u16 = 1;
+pub const VIEW_IJT: u16 = 2;
+pub const VIEW_SLN: u16 = 3;
+pub const VIEW_RES: u16 = 4;
+pub const VIEW_HOST: u16 = 5;
+pub const VIEW_ORG: u16 = 6;
+pub const VIEW_ACT: u16 = 7; // program and model views.
+pub const GRP_VIEW_COV: u16 = 7;
+pub const GRP_VIEW_VIS: u16 = 8;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SyncMode {
+    SyncOnce = 0,
+    SyncPeriodic = 1,
+    SyncHold = 2,
+    SyncAxiom = 3,
+    SyncOnceAxiom = 4,
+}
+
+/**
+ * A View holds the attributes of an object in a host group: the injection time, sync mode,
+ * saliency, resilience and origin. An object has one view per group that it lives in. (See
+ * Code::get_view.) The view code is stored like object code, where the host and origin members
+ * are R_PTR atoms to the view's references.
+ */
+#[derive(Default)]
+pub struct View {
+    code_: Vec<Atom>,
+    // references_[0] is the host group, references_[1] is the origin. Neither is the viewed object.
+    references_: [Option<Rc<RefCell<dyn Code>>>; 2],
+}
+
+impl View {
+    /**
+     * Create a View by copying the code of the persisted source. The references of source are
+     * indexes in an image, so the caller must resolve them and call set_reference.
+     * \param source The persisted view.
+     */
+    pub fn from_sys_view(source: &SysView) -> Self {
+        View { code_: source.code_.clone(), references_: [None, None] }
+    }
+
+    pub fn code(&self, i: u16) -> Atom {
+        self.code_[i as usize]
+    }
+
+    pub fn set_code(&mut self, i: u16, a: Atom) {
+        if i >= self.code_size() {
+            self.code_.resize(i as usize + 1, Atom::default());
+        }
+        self.code_[i as usize] = a;
+    }
+
+    pub fn code_size(&self) -> u16 {
+        self.code_.len() as u16
+    }
+
+    /**
+     * Return the reference: 0 for the host and 1 for the origin.
+     * \return The referenced object, or None if it is not assigned or i is not 0 or 1.
+     */
+    pub fn get_reference(&self, i: u16) -> Option<Rc<RefCell<dyn Code>>> {
+        self.references_.get(i as usize)?.as_ref().map(Rc::clone)
+    }
+
+    /**
+     * Set the reference: 0 for the host and 1 for the origin.
+     * \return False if i is not 0 or 1, so the view is not changed.
+     */
+    pub fn set_reference(&mut self, i: u16, object: &Rc<RefCell<dyn Code>>) -> bool {
+        match self.references_.get_mut(i as usize) {
+            Some(reference) => {
+                *reference = Some(Rc::clone(object));
+                true
+            },
+            None => false,
+        }
+    }
+
+    pub fn clear_references(&mut self) {
+        self.references_ = [None, None];
+    }
+
+    /**
+     * Return true if this is a program or model view which has the act member.
+     */
+    pub fn has_act(&self) -> bool {
+        // The arity of a program or model view ends with the act member.
+        self.code_size() > VIEW_ACT && self.code(VIEW_OPCODE).getAtomCount() as u16 == VIEW_ACT
+    }
+
+    /**
+     * Return the sync mode, or None if the view is too short to have the sync member.
+     */
+    pub fn get_sync(&self) -> Option<SyncMode> {
+        Some(match self.get_member(VIEW_SYNC)?.asFloat() as u32 {
+            1 => SyncMode::SyncPeriodic,
+            2 => SyncMode::SyncHold,
+            3 => SyncMode::SyncAxiom,
+            4 => SyncMode::SyncOnceAxiom,
+            _ => SyncMode::SyncOnce,
+        })
+    }
+
+    /**
+     * Return the index of the TIMESTAMP structure which the ijt member points to, or None if the
+     * ijt member is not an I_PTR to a complete TIMESTAMP structure in the code.
+     */
+    fn get_ijt_index(&self) -> Option<usize> {
+        let a = self.get_member(VIEW_IJT)?;
+        let index = a.asIndex() as usize;
+        if a.getDescriptor() != atom::I_PTR || index + 2 >= self.code_.len() ||
+           self.code_[index].getDescriptor() != atom::TIMESTAMP {
+            return None;
+        }
+        Some(index)
+    }
+
+    /**
+     * Return the injection time, or None if the view doesn't have a valid ijt member.
+     */
+    pub fn get_ijt(&self) -> Option<UTimestamp> {
+        Some(Utils::get_timestamp(&self.code_[self.get_ijt_index()?..]))
+    }
+
+    /**
+     * Set the injection time.
+     * \return False if the view doesn't have a valid ijt member, so it is not changed.
+     */
+    pub fn set_ijt(&mut self, ijt: UTimestamp) -> bool {
+        match self.get_ijt_index() {
+            Some(index) => {
+                Utils::set_timestamp(&mut self.code_[index..], ijt);
+                true
+            },
+            None => false,
+        }
+    }
+
+    /**
+     * Return the saliency, or None if the view is too short to have the sln member.
+     */
+    pub fn get_sln(&self) -> Option<f32> {
+        Some(self.get_member(VIEW_SLN)?.asFloat())
+    }
+
+    /**
+     * Return the resilience, or None if the view is too short to have the res member.
+     */
+    pub fn get_res(&self) -> Option<f32> {
+        Some(self.get_member(VIEW_RES)?.asFloat())
+    }
+
+    /**
+     * Return the atom of the member, or None if it is past the end of the code.
+     */
+    fn get_member(&self, member_index: u16) -> Option<Atom> {
+        self.code_.get(member_index as usize).copied()
+    }
+
+    /**
+     * Return the act member of a program or model view, or None if this view doesn't have it.
+     */
+    pub fn get_act(&self) -> Option<f32> {
+        if self.has_act() { Some(self.code(VIEW_ACT).asFloat()) } else { None }
+    }
+
+    /**
+     * Return the value clamped to the valid range of the member: [0, 1] for sln and act, and
+     * non-negative for res. Return None if the member can't be modified.
+     */
+    fn clamp_member(&self, member_index: u16, value: f32) -> Option<f32> {
+        match member_index {
+            VIEW_SLN => Some(clamp_unit(value)),
+            VIEW_RES => Some(value.max(0.0)),
+            VIEW_ACT if self.has_act() => Some(clamp_unit(value)),
+            _ => None,
+        }
+    }
+
+    /**
+     * Add value to the member, as done by the _mod executive command on a view. The members which
+     * can be modified are sln, res and (for program and model views) act.
+     * \param member_index The index of the member in the view code, for example VIEW_SLN.
+     * \param value The value to add.
+     * \return The new value after clamping, or None if the member can't be modified.
+     */
+    pub fn mod_member(&mut self, member_index: u16, value: f32) -> Option<f32> {
+        if member_index >= self.code_size() {
+            return None;
+        }
+        let new_value = self.clamp_member(
+            member_index, self.code(member_index).asFloat() + value)?;
+        self.set_code(member_index, Atom::Float(new_value));
+        Some(new_value)
+    }
+
+    /**
+     * Set the member to value, as done by the _set executive command on a view. The members which
+     * can be set are sln, res and (for program and model views) act.
+     * \param member_index The index of the member in the view code, for example VIEW_SLN.
+     * \param value The new value.
+     * \return The new value after clamping, or None if the member can't be set.
+     */
+    pub fn set_member(&mut self, member_index: u16, value: f32) -> Option<f32> {
+        if member_index >= self.code_size() {
+            return None;
+        }
+        let new_value = self.clamp_member(member_index, value)?;
+        self.set_code(member_index, Atom::Float(new_value));
+        Some(new_value)
+    }
+
+    /**
+     * Return the host group from the R_PTR at VIEW_HOST, or None if it is not assigned.
+     */
+    pub fn get_host(&self) -> Option<Rc<RefCell<dyn Code>>> {
+        self.get_view_reference(VIEW_HOST)
+    }
+
+    /**
+     * Return the origin from the R_PTR at VIEW_ORG, or None if it is nil or not assigned.
+     */
+    pub fn get_org(&self) -> Option<Rc<RefCell<dyn Code>>> {
+        self.get_view_reference(VIEW_ORG)
+    }
+
+    fn get_view_reference(&self, member_index: u16) -> Option<Rc<RefCell<dyn Code>>> {
+        if member_index >= self.code_size() {
+            return None;
+        }
+        let a = self.code(member_index);
+        if a.getDescriptor() != atom::R_PTR {
+            return None;
+        }
+        self.get_reference(a.asIndex())
+    }
+
+    /**
+     * Print the trace of this View to the out stream.
+     */
+    pub fn trace_out(&self, out: &mut impl Write) {
+        let mut context = atom::TraceContext::default();
+        for i in 0..self.code_size() {
+            write!(out, "{}\t", i).unwrap();
+            self.code(i).trace(&mut context, out);
+            writeln!(out).unwrap();
+        }
+    }
+}
@@ -0,0 +1,99 @@
+use super::lexer::Span;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ExpressionKind {
+    // (head member...) which is an object, marker, operator call or device function command,
+    // depending on the head.
+    Structure { head_: String, head_span_: Span, members_: Vec<Expression> },
+    // [element...]. The empty set is written |[] or [].
+    Set(Vec<Expression>),
+    Number(f32),
+    Boolean(bool),
+    Nil,
+    String(String),
+    // In microseconds.
+    Duration(i64),
+    // In microseconds since the time reference.
+    Timestamp(i64),
+    // ":" which matches any value.
+    Wildcard,
+    // "::" which matches the rest of a structure.
+    TailWildcard,
+    // An undefined value of the type like |nb.
+    Undefined(String),
+    // A member type in a class or operator definition like :nb. The type ~ in :~ is the place of
+    // the arguments of a class template.
+    Type(String),
+    // The name of an object, a variable, a constant defined by the preprocessor or a keyword
+    // like this.
+    Symbol(String),
+}
+
+/**
+ * An Expression is a parsed Replicode expression with its span in the source.
+ */
+#[derive(Clone, Debug, PartialEq)]
+pub struct Expression {
+    pub kind_: ExpressionKind,
+    // The label before the expression, like p in p:(+ 1 2). A label before white space, as in
+    // the member "p: " of a pattern, is a variable which is parsed as a labeled Wildcard.
+    pub label_: Option<String>,
+    pub span_: Span,
+}
+
+impl Expression {
+    pub fn new(kind: ExpressionKind, span: Span) -> Self {
+        Expression { kind_: kind, label_: None, span_: span }
+    }
+
+    /**
+     * Return the head of a structure, or None if this is not a structure.
+     */
+    pub fn get_head(&self) -> Option<&str> {
+        match &self.kind_ {
+            ExpressionKind::Structure { head_, .. } => Some(head_),
+            _ => None,
+        }
+    }
+
+    /**
+     * Return the members of a structure or the elements of a set, or an empty slice.
+     */
+    pub fn get_members(&self) -> &[Expression] {
+        match &self.kind_ {
+            ExpressionKind::Structure { members_, .. } => members_,
+            ExpressionKind::Set(elements) => elements,
+            _ => &[],
+        }
+    }
+
+    /**
+     * Return true if this is a labeled wildcard, which is how a variable is parsed.
+     */
+    pub fn is_variable(&self) -> bool {
+        self.label_.is_some() && self.kind_ == ExpressionKind::Wildcard
+    }
+}
+
+/**
+ * A SourceObject is an object at the top level of a Replicode source file, like
+ *
+ *   stdin:(grp ...)
+ *   []
+ *      [SYNC_ONCE now 0 forever root nil COV_OFF 0]
+ *
+ * Its label, if any, is the label of its expression. Each view is a set of view members. An
+ * object with |[] has no views.
+ */
+#[derive(Clone, Debug, PartialEq)]
+pub struct SourceObject {
+    pub expression_: Expression,
+    pub views_: Vec<Expression>,
+    pub span_: Span,
+}
+
+impl SourceObject {
+    pub fn get_label(&self) -> Option<&str> {
+        self.expression_.label_.as_deref()
+    }
+}
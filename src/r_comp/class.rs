@@ -0,0 +1,148 @@
+use crate::r_code::Atom;
+use crate::r_code::image::{get_string_size, get_words, read_string, write_string, ImageError};
+use super::structure_member::{Iteration, StructureMember};
+
+/**
+ * ReturnType is the type of a class member or the value returned by an operator.
+ */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ReturnType {
+    Any = 0,
+    Number = 1,
+    Timestamp = 2,
+    Duration = 3,
+    Set = 4,
+    Boolean = 5,
+    String = 6,
+    NodeId = 7,
+    DeviceId = 8,
+    FunctionId = 9,
+    Class = 10,
+}
+
+impl ReturnType {
+    pub fn from_u32(value: u32) -> Option<Self> {
+        match value {
+            0 => Some(ReturnType::Any),
+            1 => Some(ReturnType::Number),
+            2 => Some(ReturnType::Timestamp),
+            3 => Some(ReturnType::Duration),
+            4 => Some(ReturnType::Set),
+            5 => Some(ReturnType::Boolean),
+            6 => Some(ReturnType::String),
+            7 => Some(ReturnType::NodeId),
+            8 => Some(ReturnType::DeviceId),
+            9 => Some(ReturnType::FunctionId),
+            10 => Some(ReturnType::Class),
+            _ => None,
+        }
+    }
+    /**
+     * Return the name of the type as it is written in a definition, like nb for :nb. A member of
+     * type Class is written with its class name instead.
+     */
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            ReturnType::Any => "any",
+            ReturnType::Number => "nb",
+            ReturnType::Timestamp => "ts",
+            ReturnType::Duration => "us",
+            ReturnType::Set => "[]",
+            ReturnType::Boolean => "bl",
+            ReturnType::String => "st",
+            ReturnType::NodeId => "nid",
+            ReturnType::DeviceId => "did",
+            ReturnType::FunctionId => "fid",
+            ReturnType::Class => "class",
+        }
+    }
+}
+
+/**
+ * A Class is the definition of a Replicode class, operator or device function: its head atom
+ * (which holds the opcode), its name and its members.
+ */
+#[derive(Clone)]
+pub struct Class {
+    pub atom_: Atom,
+    pub str_opcode_: String,
+    pub type_: ReturnType,
+    pub use_as_: Iteration,
+    pub things_to_read_: Vec<StructureMember>,
+}
+
+impl Class {
+    /**
+     * Read the words: atom, name, type, use_as, member count, members.
+     * \return The Class and the number of words read.
+     */
+    pub fn read(data: &[u32]) -> Result<(Self, usize), ImageError> {
+        let atom = Atom::new(get_words(data, 0, 1, "class atom")?[0]);
+        let mut offset = 1;
+        let (str_opcode, size) = read_string(data, offset)?;
+        offset += size;
+
+        let words = get_words(data, offset, 3, "class")?;
+        let type_ = ReturnType::from_u32(words[0]).ok_or_else(|| ImageError::Invalid(
+            format!("Class {} has unknown type {}", str_opcode, words[0])).offset_by(offset))?;
+        let use_as = Iteration::from_u32(words[1]).ok_or_else(|| ImageError::Invalid(
+            format!("Class {} has unknown use_as {}", str_opcode, words[1]))
+            .offset_by(offset + 1))?;
+        let member_count = words[2];
+        offset += 3;
+
+        let mut things_to_read = vec![];
+        for _ in 0..member_count {
+            let (member, size) = StructureMember::read(&data[offset..])
+                .map_err(|error| error.offset_by(offset))?;
+            things_to_read.push(member);
+            offset += size;
+        }
+
+        Ok((Class { atom_: atom, str_opcode_: str_opcode, type_, use_as_: use_as,
+                    things_to_read_: things_to_read }, offset))
+    }
+
+    /**
+     * Write this Class in the format read by read.
+     * \param data The destination, which must have at least get_size() words.
+     */
+    pub fn write(&self, data: &mut [u32]) {
+        data[0] = self.atom_.atom_;
+        write_string(&mut data[1..], &self.str_opcode_);
+        let mut offset = 1 + get_string_size(&self.str_opcode_);
+        data[offset] = self.type_ as u32;
+        data[offset + 1] = self.use_as_ as u32;
+        data[offset + 2] = self.things_to_read_.len() as u32;
+        offset += 3;
+        for member in &self.things_to_read_ {
+            member.write(&mut data[offset..]);
+            offset += member.get_size();
+        }
+    }
+
+    /**
+     * Return the definition as it is written in the source, like
+     * (fact obj: after:ts before:ts cfd:nb psln_thr:nb).
+     */
+    pub fn get_definition(&self) -> String {
+        let mut definition = format!("({}", self.str_opcode_);
+        for member in &self.things_to_read_ {
+            definition.push(' ');
+            definition.push_str(&member.name_);
+            definition.push(':');
+            match member.type_ {
+                ReturnType::Any => {},
+                ReturnType::Class => definition.push_str(&member.class_),
+                _ => definition.push_str(member.type_.get_name()),
+            }
+        }
+        definition.push(')');
+        definition
+    }
+
+    pub fn get_size(&self) -> usize {
+        4 + get_string_size(&self.str_opcode_) +
+          self.things_to_read_.iter().map(StructureMember::get_size).sum::<usize>()
+    }
+}
@@ -0,0 +1,579 @@
+use std::rc::Rc;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use crate::core::UTimestamp;
+use crate::core::u_duration::microseconds;
+use crate::r_code::atom;
+use crate::r_code::atom::Atom;
+use crate::r_code::code;
+use crate::r_code::code::object_address;
+use crate::r_code::view::{VIEW_HOST, VIEW_IJT, VIEW_ORG};
+use crate::r_code::{Code, LocalObject, ObjectNames, SysView, Utils, View};
+use super::ast::{Expression, ExpressionKind, SourceObject};
+use super::diagnostic::Diagnostic;
+use super::lexer::Span;
+use super::segments::{DefinitionSegment, Image};
+use super::type_checker::TypeChecker;
+
+/**
+ * Return the view class for the number of members of a view: a standard view has sync, ijt,
+ * sln, res, host and org, a program or model view also has act, and a group view also has cov
+ * and vis.
+ */
+pub(crate) fn get_view_class_name(member_count: usize) -> Option<&'static str> {
+    match member_count {
+        6 => Some("view"),
+        7 => Some("pgm_view"),
+        8 => Some("grp_view"),
+        _ => None,
+    }
+}
+
+/**
+ * Return the atom of an undefined value of the type, like |nb.
+ */
+fn get_undefined_atom(type_name: &str) -> Option<Atom> {
+    match type_name {
+        "nb" => Some(Atom::UndefinedFloat()),
+        "bl" => Some(Atom::UndefinedBoolean()),
+        "st" => Some(Atom::UndefinedString()),
+        "ts" | "us" => Some(Atom::UndefinedTimestamp()),
+        "nid" => Some(Atom::UndefinedNode()),
+        "did" => Some(Atom::UndefinedDevice()),
+        "fid" => Some(Atom::UndefinedDeviceFunction()),
+        _ => None,
+    }
+}
+
+/**
+ * Return the labels of the variables in the expression in order of first appearance.
+ */
+fn collect_variables(expression: &Expression, variables: &mut Vec<String>) {
+    if let (true, Some(label)) = (expression.is_variable(), &expression.label_) {
+        if !variables.contains(label) {
+            variables.push(label.clone());
+        }
+    }
+    for member in expression.get_members() {
+        collect_variables(member, variables);
+    }
+}
+
+/**
+ * ObjectCompiler holds the code and references of the object or view being compiled.
+ */
+struct ObjectCompiler<'a> {
+    compiler_: &'a Compiler<'a>,
+    code_: Vec<Atom>,
+    references_: Vec<Rc<RefCell<dyn Code>>>,
+    // The labels of the variables. The index of a variable is its VL_PTR index.
+    variables_: Vec<String>,
+    // The code index and span of each labeled expression.
+    labels_: HashMap<String, (u16, Span)>,
+}
+
+impl<'a> ObjectCompiler<'a> {
+    fn new(compiler: &'a Compiler<'a>) -> Self {
+        ObjectCompiler {
+            compiler_: compiler, code_: vec![], references_: vec![], variables_: vec![],
+            labels_: HashMap::new(),
+        }
+    }
+
+    /**
+     * Return the index of the next atom written at the end of the code.
+     */
+    fn get_extent(&self, span: &Span) -> Result<u16, Diagnostic> {
+        if self.code_.len() > 0x0FFF {
+            return Err(Diagnostic::error(
+                "The object has too many atoms to be indexed by a pointer", span));
+        }
+        Ok(self.code_.len() as u16)
+    }
+
+    /**
+     * Return the R_PTR for the object, adding it to the references if it isn't there.
+     */
+    fn get_reference_pointer(&mut self, object: &Rc<RefCell<dyn Code>>) -> Atom {
+        let address = object_address(object);
+        let index = match self.references_.iter().position(|r| object_address(r) == address) {
+            Some(index) => index,
+            None => {
+                self.references_.push(Rc::clone(object));
+                self.references_.len() - 1
+            },
+        };
+        Atom::RPointer(index as u16)
+    }
+
+    /**
+     * Return the head atom of the structure from its class or operator.
+     */
+    fn get_head_atom(&self, head: &str, head_span: &Span, arity: usize)
+      -> Result<Atom, Diagnostic> {
+        if arity > 0xFF {
+            return Err(Diagnostic::error(
+                format!("The structure {} has {} members, more than 255", head, arity),
+                head_span));
+        }
+        let definitions = self.compiler_.definition_segment_;
+        if let Some(class) = definitions.get_class(head) {
+            if class.atom_.getDescriptor() == atom::OPERATOR {
+                return Ok(Atom::Operator(class.atom_.asOpcode(), arity as u8));
+            }
+            if class.atom_.getDescriptor() == atom::DEVICE_FUNCTION {
+                return Err(Diagnostic::error(format!(
+                    "The device function {} is a member of a command, not a head", head),
+                    head_span));
+            }
+            if class.things_to_read_.len() != arity {
+                return Err(Diagnostic::error(format!(
+                    "The class {} has {} members, but {} are given", head,
+                    class.things_to_read_.len(), arity), head_span)
+                    .with_note(format!("{} is defined as {}", head, class.get_definition())));
+            }
+            // Keep the descriptor and opcode, with the arity.
+            return Ok(Atom::new((class.atom_.atom_ & 0xFFFFFF00) | arity as u32));
+        }
+        if let Some(opcode) = definitions.operator_names_.iter().position(|name| name == head) {
+            return Ok(Atom::Operator(opcode as u16, arity as u8));
+        }
+        let names = definitions.classes_.iter().chain(definitions.sys_classes_.iter())
+            .map(|(name, _)| name.as_str())
+            .chain(definitions.operator_names_.iter().map(String::as_str));
+        Err(Diagnostic::error(format!("Unknown class or operator {}", head), head_span)
+            .with_similar_name(head, names))
+    }
+
+    /**
+     * Write the structure at the index, followed by the values of its members which don't fit
+     * in one atom.
+     */
+    fn write_structure(&mut self, index: u16, expression: &Expression)
+      -> Result<(), Diagnostic> {
+        let (head_atom, members) = match &expression.kind_ {
+            ExpressionKind::Structure { head_, head_span_, members_ } =>
+                (self.get_head_atom(head_, head_span_, members_.len())?, members_),
+            ExpressionKind::Set(elements) => {
+                if elements.len() > 0xFF {
+                    return Err(Diagnostic::error(format!(
+                        "The set has {} elements, more than 255", elements.len()),
+                        &expression.span_));
+                }
+                (Atom::Set(elements.len() as u8), elements)
+            },
+            _ => return Err(Diagnostic::error("Expected a structure or set", &expression.span_)),
+        };
+        self.code_[index as usize] = head_atom;
+        self.write_members(index, members)
+    }
+
+    /**
+     * Reserve the atoms after the index for the members and write them.
+     */
+    fn write_members(&mut self, index: u16, members: &[Expression]) -> Result<(), Diagnostic> {
+        let first_member = index as usize + 1;
+        if self.code_.len() < first_member + members.len() {
+            self.code_.resize(first_member + members.len(), Atom::Nil());
+        }
+        for (i, member) in members.iter().enumerate() {
+            self.write_member((first_member + i) as u16, member)?;
+        }
+        Ok(())
+    }
+
+    /**
+     * Write the member atom at the index. A value which doesn't fit in one atom is written at the
+     * end of the code, and the member is an I_PTR to it.
+     */
+    fn write_member(&mut self, index: u16, expression: &Expression) -> Result<(), Diagnostic> {
+        if let Some(label) = &expression.label_ {
+            if expression.is_variable() {
+                let variable = self.variables_.iter().position(|v| v == label).unwrap_or(0);
+                self.code_[index as usize] = Atom::VLPointer(variable as u16);
+                return Ok(());
+            }
+        }
+
+        let value_index = match &expression.kind_ {
+            ExpressionKind::Structure { .. } | ExpressionKind::Set(_) |
+            ExpressionKind::String(_) | ExpressionKind::Duration(_) |
+            ExpressionKind::Timestamp(_) => self.get_extent(&expression.span_)?,
+            _ => index,
+        };
+        if let Some(label) = &expression.label_ {
+            if let Some((_, first_span)) = self.labels_.get(label) {
+                return Err(Diagnostic::error(
+                    format!("The label {} is defined more than once", label), &expression.span_)
+                    .with_secondary(first_span, "first defined here"));
+            }
+            if self.variables_.contains(label) {
+                return Err(Diagnostic::error(
+                    format!("The label {} is also a variable", label), &expression.span_));
+            }
+            self.labels_.insert(label.clone(), (value_index, expression.span_.clone()));
+        }
+        if value_index != index {
+            self.code_[index as usize] = Atom::IPointer(value_index);
+            self.code_.push(Atom::Nil());
+        }
+
+        let a = match &expression.kind_ {
+            ExpressionKind::Structure { .. } | ExpressionKind::Set(_) =>
+                return self.write_structure(value_index, expression),
+            ExpressionKind::String(value) => return self.write_string(value, &expression.span_),
+            ExpressionKind::Timestamp(value) => {
+                self.code_.resize(self.code_.len() + 2, Atom::Nil());
+                Utils::set_timestamp(&mut self.code_[value_index as usize..],
+                                     UTimestamp::from_duration(microseconds(*value)));
+                return Ok(());
+            },
+            ExpressionKind::Duration(value) => {
+                self.code_[value_index as usize] = Atom::Duration();
+                self.code_.push(Atom::new((*value as u64 >> 32) as u32));
+                self.code_.push(Atom::new(*value as u64 as u32));
+                return Ok(());
+            },
+            ExpressionKind::Number(value) => Atom::Float(*value),
+            ExpressionKind::Boolean(value) => Atom::Boolean(*value),
+            ExpressionKind::Nil => Atom::Nil(),
+            ExpressionKind::Wildcard => Atom::Wildcard(),
+            ExpressionKind::TailWildcard => Atom::TailWildcard(),
+            ExpressionKind::Undefined(type_name) => get_undefined_atom(type_name).ok_or_else(|| {
+                Diagnostic::error(format!("Unknown type |{}", type_name), &expression.span_)
+            })?,
+            ExpressionKind::Type(type_name) => return Err(Diagnostic::error(
+                format!("The member type :{} can only be in a class definition", type_name),
+                &expression.span_)),
+            ExpressionKind::Symbol(name) => self.get_symbol_atom(name, &expression.span_)?,
+        };
+        self.code_[index as usize] = a;
+        Ok(())
+    }
+
+    /**
+     * Write the STRING atom and the characters at the end of the code, which the caller
+     * already reserved for the STRING atom.
+     */
+    fn write_string(&mut self, value: &str, span: &Span) -> Result<(), Diagnostic> {
+        let bytes = value.as_bytes();
+        if bytes.len() > 0xFF {
+            return Err(Diagnostic::error(
+                format!("The string has {} bytes, more than 255", bytes.len()), span));
+        }
+        let last = self.code_.len() - 1;
+        self.code_[last] = Atom::String(bytes.len() as u8);
+        for chunk in bytes.chunks(4) {
+            let mut word = [0u8; 4];
+            word[..chunk.len()].copy_from_slice(chunk);
+            self.code_.push(Atom::new(u32::from_le_bytes(word)));
+        }
+        Ok(())
+    }
+
+    /**
+     * Return the atom for a name: a VL_PTR to a variable, a CODE_VL_PTR to a labeled expression,
+     * an R_PTR to a named object, a device function or one of this, view, mks and vws.
+     */
+    fn get_symbol_atom(&mut self, name: &str, span: &Span) -> Result<Atom, Diagnostic> {
+        if let Some(variable) = self.variables_.iter().position(|v| v == name) {
+            return Ok(Atom::VLPointer(variable as u16));
+        }
+        if let Some((index, _)) = self.labels_.get(name) {
+            return Ok(Atom::CodeVLPointer(*index));
+        }
+        if let Some(object) = self.compiler_.objects_by_name_.get(name) {
+            let object = Rc::clone(object);
+            return Ok(self.get_reference_pointer(&object));
+        }
+        let definitions = self.compiler_.definition_segment_;
+        if let Some(opcode) = definitions.function_names_.iter().position(|f| f == name) {
+            return Ok(Atom::DeviceFunction(opcode as u16));
+        }
+        match name {
+            "this" => Ok(Atom::This()),
+            "view" => Ok(Atom::View()),
+            "mks" => Ok(Atom::Mks()),
+            "vws" => Ok(Atom::Vws()),
+            _ => {
+                let names = self.variables_.iter().chain(self.labels_.keys())
+                    .chain(self.compiler_.objects_by_name_.keys())
+                    .chain(definitions.function_names_.iter()).map(String::as_str)
+                    .chain(["this", "view", "mks", "vws"].iter().copied());
+                let diagnostic = Diagnostic::error(format!("Unknown name {}", name), span)
+                    .with_similar_name(name, names);
+                if self.labels_.is_empty() && self.variables_.is_empty() {
+                    Err(diagnostic)
+                }
+                else {
+                    Err(diagnostic.with_note("a label must be defined before it is used"))
+                }
+            },
+        }
+    }
+}
+
+/**
+ * Compiler compiles parsed Replicode objects into LocalObjects with the classes, operators and
+ * device functions of a definition segment. The code is laid out like the C++ compiler: the
+ * head atom with the arity is followed by one atom per member, and a member which doesn't fit
+ * in one atom (a structure, set, string, timestamp or duration) is an I_PTR to where it is
+ * written after the end of the code so far, depth first. A variable (a label followed by white
+ * space, like p: in a pattern) and each later use of its name are a VL_PTR to the variable's
+ * index, in order of first appearance in the object. The name of a labeled expression is a
+ * CODE_VL_PTR to its code index. The name of an object is an R_PTR to the object's reference.
+ * This layout follows the C++ source, but it is not yet verified against images written by the
+ * C++ compiler. (See tests/compiler_golden.rs.)
+ */
+pub struct Compiler<'a> {
+    definition_segment_: &'a DefinitionSegment,
+    objects_by_name_: HashMap<String, Rc<RefCell<dyn Code>>>,
+    // The span of each compiled named object, for errors.
+    object_spans_: HashMap<String, Span>,
+    next_oid_: u32,
+}
+
+impl<'a> Compiler<'a> {
+    /**
+     * Create a Compiler for the classes in the definition segment.
+     * \param definition_segment The definition segment, usually from compiling std.replicode
+     * and the user classes with the preprocessor, or from an image.
+     */
+    pub fn new(definition_segment: &'a DefinitionSegment) -> Self {
+        Compiler {
+            definition_segment_: definition_segment, objects_by_name_: HashMap::new(),
+            object_spans_: HashMap::new(), next_oid_: 0,
+        }
+    }
+
+    /**
+     * Set the OID of the next compiled object. Each object gets the next OID.
+     */
+    pub fn set_next_oid(&mut self, oid: u32) {
+        self.next_oid_ = oid;
+    }
+
+    /**
+     * Add a named object which compiled objects can reference, such as a group from an image
+     * which was already loaded.
+     * \return False if the name is already used.
+     */
+    pub fn add_named_object(&mut self, name: &str, object: &Rc<RefCell<dyn Code>>) -> bool {
+        if self.objects_by_name_.contains_key(name) {
+            return false;
+        }
+        self.objects_by_name_.insert(name.to_string(), Rc::clone(object));
+        true
+    }
+
+    /**
+     * Return the named object, which was compiled or added with add_named_object.
+     */
+    pub fn get_named_object(&self, name: &str) -> Option<&Rc<RefCell<dyn Code>>> {
+        self.objects_by_name_.get(name)
+    }
+
+    /**
+     * Compile the objects. An object can reference a named object which comes later in the
+     * source. The named objects stay in this Compiler, so that objects compiled by a later call
+     * can reference them. If there is an error, the named objects and the next OID are restored
+     * to what they were before the call.
+     * \param objects The parsed objects.
+     * \return The compiled objects in order, or the Diagnostics of the objects which have an
+     * error or a member whose type doesn't agree with its definition. See TypeChecker.
+     */
+    pub fn compile(&mut self, objects: &[SourceObject])
+      -> Result<Vec<Rc<RefCell<dyn Code>>>, Vec<Diagnostic>> {
+        let saved_objects_by_name = self.objects_by_name_.clone();
+        let saved_object_spans = self.object_spans_.clone();
+        let saved_next_oid = self.next_oid_;
+        let mut compiled: Vec<Rc<RefCell<dyn Code>>> = vec![];
+        let mut diagnostics = vec![];
+        for object in objects {
+            let local: Rc<RefCell<dyn Code>> = Rc::new(RefCell::new(LocalObject::default()));
+            local.borrow_mut().set_oid(self.next_oid_);
+            self.next_oid_ += 1;
+            if let Some(name) = object.get_label() {
+                if !self.add_named_object(name, &local) {
+                    let mut diagnostic = Diagnostic::error(
+                        format!("The object name {} is used more than once", name),
+                        &object.expression_.span_);
+                    if let Some(first_span) = self.object_spans_.get(name) {
+                        diagnostic = diagnostic.with_secondary(first_span, "first used here");
+                    }
+                    diagnostics.push(diagnostic);
+                }
+                else {
+                    self.object_spans_.insert(name.to_string(), object.expression_.span_.clone());
+                }
+            }
+            compiled.push(local);
+        }
+
+        let type_checker = self.get_type_checker(objects);
+        for (object, local) in objects.iter().zip(compiled.iter()) {
+            match self.compile_object(object, local) {
+                Ok(()) => diagnostics.append(&mut type_checker.check_object(object)),
+                Err(diagnostic) => diagnostics.push(diagnostic),
+            }
+        }
+        if diagnostics.is_empty() {
+            return Ok(compiled);
+        }
+
+        self.objects_by_name_ = saved_objects_by_name;
+        self.object_spans_ = saved_object_spans;
+        self.next_oid_ = saved_next_oid;
+        // Break the reference cycles between the named objects so that they are freed.
+        for local in &compiled {
+            let mut local = local.borrow_mut();
+            local.clear_references();
+            local.clear_views();
+        }
+        Err(diagnostics)
+    }
+
+    /**
+     * Return a TypeChecker with the classes of the named objects, including the objects which
+     * are not compiled yet.
+     */
+    fn get_type_checker(&self, objects: &[SourceObject]) -> TypeChecker<'a> {
+        let mut type_checker = TypeChecker::new(self.definition_segment_);
+        for (name, object) in &self.objects_by_name_ {
+            let object = object.borrow();
+            if object.code_size() == 0 {
+                continue;
+            }
+            let opcode = object.code(0).asOpcode() as usize;
+            if let Some(class) = self.definition_segment_.classes_by_opcodes_.get(opcode) {
+                type_checker.add_object_class(name, &class.str_opcode_);
+            }
+        }
+        for object in objects {
+            if let (Some(name), Some(head)) = (object.get_label(), object.expression_.get_head()) {
+                type_checker.add_object_class(name, head);
+            }
+        }
+        type_checker
+    }
+
+    fn compile_object(&self, object: &SourceObject, local: &Rc<RefCell<dyn Code>>)
+      -> Result<(), Diagnostic> {
+        let expression = &object.expression_;
+        if let ExpressionKind::Structure { head_, head_span_, .. } = &expression.kind_ {
+            if self.definition_segment_.get_class(head_).is_none() {
+                let names = self.definition_segment_.classes_.iter()
+                    .chain(self.definition_segment_.sys_classes_.iter())
+                    .map(|(name, _)| name.as_str());
+                return Err(Diagnostic::error(format!("Unknown class {}", head_), head_span_)
+                    .with_similar_name(head_, names));
+            }
+        }
+        let mut compiler = ObjectCompiler::new(self);
+        collect_variables(expression, &mut compiler.variables_);
+        compiler.code_.push(Atom::Nil());
+        compiler.write_structure(0, expression)?;
+
+        for (i, a) in compiler.code_.iter().enumerate() {
+            local.borrow_mut().set_code(i as u16, *a);
+        }
+        for (i, reference) in compiler.references_.iter().enumerate() {
+            code::set_reference(local, i as u16, reference);
+        }
+        for view in &object.views_ {
+            let view = self.compile_view(view)?;
+            local.borrow_mut().add_view(view);
+        }
+        Ok(())
+    }
+
+    /**
+     * Compile a view like [SYNC_ONCE now 1 forever root nil]. The injection time is a
+     * timestamp, or now which is the time reference. The host is the name of a group and the
+     * origin is nil or the name of an object. As in the C++ View, the head atom is a structured
+     * set of the view class.
+     */
+    fn compile_view(&self, view: &Expression) -> Result<View, Diagnostic> {
+        let members = view.get_members();
+        let class_name = get_view_class_name(members.len()).ok_or_else(|| Diagnostic::error(
+            format!("A view has 6, 7 or 8 members, not {}", members.len()), &view.span_))?;
+        let class = self.definition_segment_.get_class(class_name).ok_or_else(|| {
+            Diagnostic::error(format!("The view class {} is not defined", class_name), &view.span_)
+        })?;
+
+        let mut compiler = ObjectCompiler::new(self);
+        compiler.code_.push(Atom::SSet(class.atom_.asOpcode(), members.len() as u8));
+        compiler.code_.resize(1 + members.len(), Atom::Nil());
+        let mut references = [None, None];
+        for (i, member) in members.iter().enumerate() {
+            let index = (i + 1) as u16;
+            let name = match &member.kind_ {
+                ExpressionKind::Symbol(name) => Some(name.as_str()),
+                _ => None,
+            };
+            let is_now = name == Some("now") || member.get_head() == Some("_now");
+            if index == VIEW_IJT && is_now {
+                compiler.write_member(index, &Expression::new(
+                    ExpressionKind::Timestamp(0), member.span_.clone()))?;
+            }
+            else if index == VIEW_HOST || index == VIEW_ORG {
+                let reference = if index == VIEW_HOST { 0 } else { 1 };
+                if index == VIEW_ORG && member.kind_ == ExpressionKind::Nil {
+                    continue;
+                }
+                let object = name.and_then(|name| self.objects_by_name_.get(name))
+                    .ok_or_else(|| {
+                        let diagnostic = Diagnostic::error(
+                            "Expected the name of an object for the view host or origin",
+                            &member.span_);
+                        match name {
+                            Some(name) => diagnostic.with_similar_name(
+                                name, self.objects_by_name_.keys().map(String::as_str)),
+                            None => diagnostic,
+                        }
+                    })?;
+                compiler.code_[index as usize] = Atom::RPointer(reference);
+                references[reference as usize] = Some(Rc::clone(object));
+            }
+            else {
+                compiler.write_member(index, member)?;
+                if !compiler.references_.is_empty() {
+                    return Err(Diagnostic::error(
+                        "A view can only reference its host and origin", &member.span_));
+                }
+            }
+        }
+
+        let mut view = View::from_sys_view(&SysView { code_: compiler.code_, references_: vec![] });
+        for (i, reference) in references.iter().enumerate() {
+            if let Some(reference) = reference {
+                view.set_reference(i as u16, reference);
+            }
+        }
+        Ok(view)
+    }
+
+    /**
+     * Compile the objects into an Image with a copy of the definition segment. The names of
+     * the labeled objects are added to the object names of the image.
+     * \param objects The parsed objects.
+     * \return The Image, or a Diagnostic for each object which has an error.
+     */
+    pub fn compile_image(&mut self, objects: &[SourceObject]) -> Result<Image, Vec<Diagnostic>> {
+        let compiled = self.compile(objects)?;
+        let mut image = Image {
+            definition_segment_: self.definition_segment_.clone(), ..Image::default() };
+        let mut object_names = ObjectNames::new();
+        for (object, local) in objects.iter().zip(compiled.iter()) {
+            if let Some(name) = object.get_label() {
+                object_names.insert(local.borrow().get_oid(), name);
+            }
+        }
+        // Only a program with more objects than an image can reference fails here, since the
+        // labels are unique.
+        image.add_objects(&compiled, &object_names).map_err(|error| vec![Diagnostic::error(
+            format!("Can't add the objects to the image: {}", error),
+            &objects[0].expression_.span_)])?;
+        Ok(image)
+    }
+}
@@ -0,0 +1,444 @@
+use std::rc::Rc;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
+use crate::core::UTimestamp;
+use crate::core::u_duration::microseconds;
+use crate::r_code::atom;
+use crate::r_code::atom::{Atom, TraceContext};
+use crate::r_code::code::object_address;
+use crate::r_code::object_registry::UNDEFINED_OID;
+use crate::r_code::{Code, Utils, View};
+use super::lexer::quote_string;
+use super::segments::{DefinitionSegment, Image};
+
+/**
+ * AtomSource is the code of an object or view with its references, which the decompiler reads.
+ */
+trait AtomSource {
+    fn code(&self, i: u16) -> Atom;
+    fn code_size(&self) -> u16;
+    fn get_reference(&self, i: u16) -> Option<Rc<RefCell<dyn Code>>>;
+}
+
+// The code of an object, which can't be a dyn AtomSource directly.
+struct CodeSource<'a>(&'a dyn Code);
+
+impl AtomSource for CodeSource<'_> {
+    fn code(&self, i: u16) -> Atom {
+        self.0.code(i)
+    }
+
+    fn code_size(&self) -> u16 {
+        self.0.code_size()
+    }
+
+    fn get_reference(&self, i: u16) -> Option<Rc<RefCell<dyn Code>>> {
+        if i < self.0.references_size() { Some(self.0.get_reference(i)) } else { None }
+    }
+}
+
+impl AtomSource for View {
+    fn code(&self, i: u16) -> Atom {
+        View::code(self, i)
+    }
+
+    fn code_size(&self) -> u16 {
+        View::code_size(self)
+    }
+
+    fn get_reference(&self, i: u16) -> Option<Rc<RefCell<dyn Code>>> {
+        // A view only has the host and origin references.
+        if i < 2 { View::get_reference(self, i) } else { None }
+    }
+}
+
+/**
+ * Return the string of the STRING atom at the index, or None if the code is too short.
+ */
+fn get_string(source: &dyn AtomSource, index: u16) -> Option<String> {
+    let a = source.code(index);
+    let character_count = (a.atom_ & 0x000000FF) as usize;
+    if index as usize + a.getAtomCount() as usize >= source.code_size() as usize {
+        return None;
+    }
+    let bytes: Vec<u8> = (1..=a.getAtomCount() as u16)
+        .flat_map(|i| source.code(index + i).atom_.to_le_bytes()).take(character_count).collect();
+    Some(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+/**
+ * Return the 64-bit value of the TIMESTAMP or DURATION atom at the index, or None if the code is
+ * too short.
+ */
+fn get_int64(source: &dyn AtomSource, index: u16) -> Option<i64> {
+    if index as usize + 2 >= source.code_size() as usize {
+        return None;
+    }
+    let high = source.code(index + 1).atom_ as u64;
+    Some((high << 32 | source.code(index + 2).atom_ as u64) as i64)
+}
+
+/**
+ * ObjectDecompiler holds the variable names of the object or view being decompiled.
+ */
+struct ObjectDecompiler<'a, 'b> {
+    decompiler_: &'b Decompiler<'a>,
+    source_: &'b dyn AtomSource,
+    // The code indexes which are the target of a CODE_VL_PTR, and their names.
+    labels_: HashMap<u16, String>,
+    // The VL_PTR indexes which were already written with a label, like v0:.
+    written_variables_: HashSet<u16>,
+    out_: String,
+}
+
+impl<'a, 'b> ObjectDecompiler<'a, 'b> {
+    fn new(decompiler: &'b Decompiler<'a>, source: &'b dyn AtomSource) -> Self {
+        // Name the CODE_VL_PTR targets after the variables, in code order.
+        let mut variable_count = 0;
+        let mut targets = vec![];
+        for i in 0..source.code_size() {
+            let a = source.code(i);
+            match a.getDescriptor() {
+                atom::VL_PTR => variable_count = variable_count.max(a.asIndex() as usize + 1),
+                atom::CODE_VL_PTR if !targets.contains(&a.asIndex()) => targets.push(a.asIndex()),
+                _ => {},
+            }
+        }
+        targets.sort_unstable();
+        let labels = targets.into_iter().enumerate()
+            .map(|(i, target)| (target, format!("v{}", variable_count + i))).collect();
+        ObjectDecompiler {
+            decompiler_: decompiler, source_: source, labels_: labels,
+            written_variables_: HashSet::new(), out_: String::new(),
+        }
+    }
+
+    /**
+     * Write the structure or set at the index.
+     * \param depth The nesting depth, to stop at a cycle of I_PTRs in corrupt code.
+     */
+    fn write_structure(&mut self, index: u16, depth: u16) {
+        let a = self.source_.code(index);
+        let arity = a.getAtomCount() as u16;
+        if index as usize + arity as usize >= self.source_.code_size() as usize ||
+           depth > self.source_.code_size() {
+            write!(self.out_, "<invalid structure at {}>", index).unwrap();
+            return;
+        }
+        if a.getDescriptor() == atom::SET {
+            if arity == 0 {
+                self.out_.push_str("|[]");
+                return;
+            }
+            self.out_.push('[');
+        }
+        else {
+            let head = self.decompiler_.get_head_name(a);
+            write!(self.out_, "({}", head).unwrap();
+        }
+        for i in 1..=arity {
+            if i > 1 || a.getDescriptor() != atom::SET {
+                self.out_.push(' ');
+            }
+            self.write_member(index + i, depth + 1);
+        }
+        self.out_.push(if a.getDescriptor() == atom::SET { ']' } else { ')' });
+    }
+
+    /**
+     * Write the member at the index, following an I_PTR to its value.
+     */
+    fn write_member(&mut self, index: u16, depth: u16) {
+        let a = self.source_.code(index);
+        let value_index = if a.getDescriptor() == atom::I_PTR { a.asIndex() } else { index };
+        if let Some(label) = self.labels_.get(&value_index) {
+            write!(self.out_, "{}:", label).unwrap();
+        }
+        if value_index != index {
+            if value_index >= self.source_.code_size() {
+                write!(self.out_, "<invalid pointer to {}>", value_index).unwrap();
+                return;
+            }
+            self.write_value(value_index, depth);
+        }
+        else {
+            self.write_atom(a);
+        }
+    }
+
+    /**
+     * Write the value at the index of an I_PTR.
+     */
+    fn write_value(&mut self, index: u16, depth: u16) {
+        let a = self.source_.code(index);
+        let time_reference = self.decompiler_.time_reference_;
+        match a.getDescriptor() {
+            atom::STRING if !a.isUndefined() && a.atom_ != Atom::UndefinedString().atom_ => {
+                match get_string(self.source_, index) {
+                    Some(value) => self.out_.push_str(&quote_string(&value)),
+                    None => write!(self.out_, "<invalid string at {}>", index).unwrap(),
+                }
+            },
+            atom::TIMESTAMP if a.atom_ != Atom::UndefinedTimestamp().atom_ => {
+                match get_int64(self.source_, index) {
+                    Some(value) => self.out_.push_str(&Utils::to_string_s_ms_us(
+                        UTimestamp::from_duration(microseconds(value)), time_reference)),
+                    None => write!(self.out_, "<invalid timestamp at {}>", index).unwrap(),
+                }
+            },
+            atom::DURATION => match get_int64(self.source_, index) {
+                Some(value) => self.out_.push_str(&Utils::to_string_us(microseconds(value))),
+                None => write!(self.out_, "<invalid duration at {}>", index).unwrap(),
+            },
+            atom::SET | atom::S_SET | atom::OBJECT | atom::MARKER | atom::OPERATOR |
+            atom::GROUP | atom::INSTANTIATED_PROGRAM | atom::INSTANTIATED_CPP_PROGRAM |
+            atom::INSTANTIATED_INPUT_LESS_PROGRAM | atom::INSTANTIATED_ANTI_PROGRAM |
+            atom::COMPOSITE_STATE | atom::MODEL => self.write_structure(index, depth),
+            _ => self.write_atom(a),
+        }
+    }
+
+    /**
+     * Write an atom which is not a pointer to a value.
+     */
+    fn write_atom(&mut self, a: Atom) {
+        if a.isFloat() {
+            if a.atom_ == Atom::UndefinedFloat().atom_ {
+                self.out_.push_str("|nb");
+            }
+            else {
+                write!(self.out_, "{}", a.asFloat()).unwrap();
+            }
+            return;
+        }
+        let undefined = [
+            (Atom::UndefinedBoolean(), "|bl"), (Atom::UndefinedString(), "|st"),
+            (Atom::UndefinedTimestamp(), "|ts"), (Atom::UndefinedNode(), "|nid"),
+            (Atom::UndefinedDevice(), "|did"), (Atom::UndefinedDeviceFunction(), "|fid")];
+        if let Some((_, name)) = undefined.iter().find(|(u, _)| u.atom_ == a.atom_) {
+            self.out_.push_str(name);
+            return;
+        }
+
+        match a.getDescriptor() {
+            atom::NIL => self.out_.push_str("nil"),
+            atom::BOOLEAN_ => self.out_.push_str(if a.asBoolean() { "true" } else { "false" }),
+            atom::WILDCARD => self.out_.push(':'),
+            atom::T_WILDCARD => self.out_.push_str("::"),
+            atom::VL_PTR => {
+                let is_first = self.written_variables_.insert(a.asIndex());
+                write!(self.out_, "v{}{}", a.asIndex(), if is_first { ":" } else { "" }).unwrap();
+            },
+            atom::CODE_VL_PTR => match self.labels_.get(&a.asIndex()) {
+                Some(label) => self.out_.push_str(label),
+                None => write!(self.out_, "<invalid label {}>", a.asIndex()).unwrap(),
+            },
+            atom::R_PTR => match self.source_.get_reference(a.asIndex()) {
+                Some(reference) =>
+                    self.out_.push_str(&self.decompiler_.get_object_name(&reference)),
+                None => write!(self.out_, "<invalid reference {}>", a.asIndex()).unwrap(),
+            },
+            atom::THIS => self.out_.push_str("this"),
+            atom::VIEW => self.out_.push_str("view"),
+            atom::MKS => self.out_.push_str("mks"),
+            atom::VWS => self.out_.push_str("vws"),
+            atom::DEVICE_FUNCTION => {
+                let names = &self.decompiler_.definition_segment_.function_names_;
+                match names.get(a.asOpcode() as usize) {
+                    Some(name) => self.out_.push_str(name),
+                    None => write!(self.out_, "fid{}", a.asOpcode()).unwrap(),
+                }
+            },
+            _ => {
+                // There is no Replicode syntax for the atom, so use its trace.
+                let mut trace = String::new();
+                a.trace(&mut TraceContext::default(), &mut trace);
+                write!(self.out_, "<{}>", trace).unwrap();
+            },
+        }
+    }
+}
+
+/**
+ * Decompiler prints objects as Replicode source, using the class, operator and device function
+ * names of a definition segment. A timestamp is printed relative to the time reference like
+ * 10s:100ms:0us and a duration like 100ms. A variable is named v0, v1, etc. in the order of its
+ * VL_PTR index, and is written as v0: where it first appears. An object is labeled with its
+ * name if it has one, or with a generated name like fact12 (the class and OID) if another
+ * decompiled object references it. A value which has no Replicode syntax, such as a pointer used
+ * only by the executive, is printed as its trace in angle brackets.
+ */
+pub struct Decompiler<'a> {
+    definition_segment_: &'a DefinitionSegment,
+    time_reference_: UTimestamp,
+    // The object names by object_address.
+    names_: RefCell<HashMap<usize, String>>,
+    used_names_: RefCell<HashSet<String>>,
+}
+
+impl<'a> Decompiler<'a> {
+    /**
+     * Create a Decompiler.
+     * \param definition_segment The definition segment with the names of the opcodes in the code.
+     * \param time_reference The time which is subtracted from timestamps, usually the session
+     * start time.
+     */
+    pub fn new(definition_segment: &'a DefinitionSegment, time_reference: UTimestamp) -> Self {
+        Decompiler {
+            definition_segment_: definition_segment, time_reference_: time_reference,
+            names_: RefCell::new(HashMap::new()), used_names_: RefCell::new(HashSet::new()),
+        }
+    }
+
+    /**
+     * Set the name of the object, such as from the object names of an image. Otherwise the name
+     * is generated.
+     * \return False if another object already has the name.
+     */
+    pub fn set_object_name(&mut self, object: &Rc<RefCell<dyn Code>>, name: &str) -> bool {
+        if !self.used_names_.borrow_mut().insert(name.to_string()) {
+            return false;
+        }
+        self.names_.borrow_mut().insert(object_address(object), name.to_string());
+        true
+    }
+
+    /**
+     * Return the name of the class, operator or other structure of the head atom.
+     */
+    fn get_head_name(&self, head: Atom) -> String {
+        let opcode = head.asOpcode() as usize;
+        let name = if head.getDescriptor() == atom::OPERATOR {
+            self.definition_segment_.operator_names_.get(opcode)
+        }
+        else {
+            self.definition_segment_.classes_by_opcodes_.get(opcode).map(|class| &class.str_opcode_)
+        };
+        match name {
+            Some(name) => name.clone(),
+            None => format!("opcode{}", opcode),
+        }
+    }
+
+    /**
+     * Return the name of the object, generating a unique name from its class and OID if it has
+     * none.
+     */
+    pub fn get_object_name(&self, object: &Rc<RefCell<dyn Code>>) -> String {
+        let address = object_address(object);
+        if let Some(name) = self.names_.borrow().get(&address) {
+            return name.clone();
+        }
+
+        let oid = object.borrow().get_oid();
+        let mut used_names = self.used_names_.borrow_mut();
+        let class_name = {
+            let object = object.borrow();
+            if object.code_size() > 0 { self.get_head_name(object.code(0)).replace('.', "_") }
+            else { "object".to_string() }
+        };
+        let base = if oid == UNDEFINED_OID { class_name } else { format!("{}{}", class_name, oid) };
+        let mut name = base.clone();
+        let mut suffix = 1;
+        while used_names.contains(&name) {
+            suffix += 1;
+            name = format!("{}_{}", base, suffix);
+        }
+        used_names.insert(name.clone());
+        self.names_.borrow_mut().insert(address, name.clone());
+        name
+    }
+
+    /**
+     * Return true if the object has a name from set_object_name.
+     */
+    fn has_name(&self, object: &Rc<RefCell<dyn Code>>) -> bool {
+        self.names_.borrow().contains_key(&object_address(object))
+    }
+
+    /**
+     * Return the code of the object, without a label or views, like
+     * (fact (mk.val hand position (vec3 1 2 3) 1) 10s:0ms:0us 10s:100ms:0us 1 1).
+     */
+    pub fn decompile_code(&self, object: &Rc<RefCell<dyn Code>>) -> String {
+        let object = object.borrow();
+        if object.code_size() == 0 {
+            return "<empty object>".to_string();
+        }
+        let source = CodeSource(&*object);
+        let mut decompiler = ObjectDecompiler::new(self, &source);
+        decompiler.write_structure(0, 0);
+        decompiler.out_
+    }
+
+    /**
+     * Return the view as a set of its members, like [0 0s:0ms:0us 1 1 root nil].
+     */
+    pub fn decompile_view(&self, view: &View) -> String {
+        let mut decompiler = ObjectDecompiler::new(self, view);
+        decompiler.out_.push('[');
+        let arity = (view.code(0).getAtomCount() as u16).min(view.code_size().saturating_sub(1));
+        for i in 1..=arity {
+            if i > 1 {
+                decompiler.out_.push(' ');
+            }
+            decompiler.write_member(i, 0);
+        }
+        decompiler.out_.push(']');
+        decompiler.out_
+    }
+
+    /**
+     * Return the objects as Replicode source. Each object is labeled if it has a name or is
+     * referenced by another of the objects, and is followed by its views, or |[] if it has none.
+     * \param objects The objects.
+     * \return The source, with a line or more for each object.
+     */
+    pub fn decompile(&self, objects: &[Rc<RefCell<dyn Code>>]) -> String {
+        let mut referenced = HashSet::new();
+        for object in objects {
+            let object = object.borrow();
+            for i in 0..object.references_size() {
+                referenced.insert(object_address(&object.get_reference(i)));
+            }
+        }
+
+        let mut out = String::new();
+        for object in objects {
+            if self.has_name(object) || referenced.contains(&object_address(object)) {
+                write!(out, "{}:", self.get_object_name(object)).unwrap();
+            }
+            writeln!(out, "{}", self.decompile_code(object)).unwrap();
+
+            let object = object.borrow();
+            let views = object.views();
+            if views.is_empty() {
+                out.push_str("|[]\n");
+                continue;
+            }
+            out.push_str("[]\n");
+            for view in views.iter() {
+                writeln!(out, "   {}", self.decompile_view(view)).unwrap();
+            }
+        }
+        out
+    }
+}
+
+/**
+ * Decompile the objects of the image with its definition segment and object names.
+ * \param image The image.
+ * \param time_reference The time which is subtracted from timestamps.
+ * \return The source, or an error message if the objects can't be loaded.
+ */
+pub fn decompile_image(image: &Image, time_reference: UTimestamp) -> Result<String, String> {
+    let objects = image.get_objects().map_err(|error| error.to_string())?;
+    let mut decompiler = Decompiler::new(&image.definition_segment_, time_reference);
+    for object in &objects {
+        if let Some(name) = image.object_names_.get_name(object.borrow().get_oid()) {
+            decompiler.set_object_name(object, name);
+        }
+    }
+    Ok(decompiler.decompile(&objects))
+}
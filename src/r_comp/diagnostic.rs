@@ -0,0 +1,250 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fmt::Write;
+use super::lexer::{ParseError, Span};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Severity {
+    Error,
+    Warning,
+    Note,
+    Help,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+            Severity::Note => write!(f, "note"),
+            Severity::Help => write!(f, "help"),
+        }
+    }
+}
+
+/**
+ * A SecondaryLabel is a span related to a diagnostic, like the first definition of a name which
+ * is defined twice, with a message about it.
+ */
+#[derive(Clone, Debug, PartialEq)]
+pub struct SecondaryLabel {
+    pub span_: Span,
+    pub message_: String,
+}
+
+/**
+ * A Child is a note or help message of a diagnostic, like a suggestion.
+ */
+#[derive(Clone, Debug, PartialEq)]
+pub struct Child {
+    pub severity_: Severity,
+    pub message_: String,
+}
+
+/**
+ * A Diagnostic is an error or warning about Replicode source at a primary span, with secondary
+ * spans, notes and suggestions like "did you mean `mk.val`?". Use render to show it with the
+ * source lines.
+ */
+#[derive(Clone, Debug, PartialEq)]
+pub struct Diagnostic {
+    pub severity_: Severity,
+    pub message_: String,
+    pub span_: Span,
+    pub secondary_: Vec<SecondaryLabel>,
+    // The notes and suggestions in order.
+    pub children_: Vec<Child>,
+}
+
+impl Diagnostic {
+    pub fn new(severity: Severity, message: impl Into<String>, span: &Span) -> Self {
+        Diagnostic {
+            severity_: severity, message_: message.into(), span_: span.clone(), secondary_: vec![],
+            children_: vec![],
+        }
+    }
+
+    pub fn error(message: impl Into<String>, span: &Span) -> Self {
+        Self::new(Severity::Error, message, span)
+    }
+
+    pub fn warning(message: impl Into<String>, span: &Span) -> Self {
+        Self::new(Severity::Warning, message, span)
+    }
+
+    pub fn with_secondary(mut self, span: &Span, message: impl Into<String>) -> Self {
+        self.secondary_.push(SecondaryLabel { span_: span.clone(), message_: message.into() });
+        self
+    }
+
+    pub fn with_note(mut self, note: impl Into<String>) -> Self {
+        self.children_.push(Child { severity_: Severity::Note, message_: note.into() });
+        self
+    }
+
+    pub fn with_suggestion(mut self, suggestion: impl Into<String>) -> Self {
+        self.children_.push(Child { severity_: Severity::Help, message_: suggestion.into() });
+        self
+    }
+
+    /**
+     * If one of the candidates is similar to the name, add the suggestion "did you mean" it.
+     */
+    pub fn with_similar_name<'a>(self, name: &str, candidates: impl IntoIterator<Item = &'a str>)
+      -> Self {
+        match find_similar_name(name, candidates) {
+            Some(similar) => self.with_suggestion(format!("did you mean `{}`?", similar)),
+            None => self,
+        }
+    }
+
+    /**
+     * Return the diagnostic as text for a terminal, like
+     *
+     *   error: Unknown class mk.vall
+     *    --> main.replicode:12:2
+     *      |
+     *   12 | (mk.vall self position 1 1)
+     *      |  ^^^^^^^
+     *      = help: did you mean `mk.val`?
+     *
+     * \param sources The source texts for the snippets. A span whose file is not in sources
+     * is shown without a snippet.
+     * \param is_colored If true, use ANSI colors.
+     */
+    pub fn render(&self, sources: &Sources, is_colored: bool) -> String {
+        let color = |code: &str, text: &str| {
+            if is_colored { format!("\x1b[{}m{}\x1b[0m", code, text) } else { text.to_string() }
+        };
+        let severity_color = match self.severity_ {
+            Severity::Error => "1;31",
+            Severity::Warning => "1;33",
+            Severity::Note | Severity::Help => "1;32",
+        };
+
+        let line_number_width = Some(&self.span_).into_iter()
+            .chain(self.secondary_.iter().map(|label| &label.span_))
+            .map(|span| span.line_.to_string().len()).max().unwrap_or(1);
+        let gutter = " ".repeat(line_number_width);
+        let mut out = String::new();
+        writeln!(out, "{}: {}", color(severity_color, &self.severity_.to_string()),
+                 color("1", &self.message_)).unwrap();
+
+        let write_snippet = |out: &mut String, span: &Span, marker: char, message: &str,
+                                 marker_color: &str| {
+            let line = match sources.get_line(span) {
+                Some(line) => line,
+                None => return,
+            };
+            writeln!(out, "{} {}", gutter, color("1;34", "|")).unwrap();
+            writeln!(out, "{} {} {}", color("1;34", &format!("{:>width$}", span.line_,
+                     width = line_number_width)), color("1;34", "|"), line).unwrap();
+            // Underline to the end of the span, or the end of the line for a multi-line span.
+            let start = (span.column_ as usize).saturating_sub(1).min(line.chars().count());
+            let length = sources.get_text(span).map_or(1, |text| {
+                text.split('\n').next().unwrap_or("").chars().count()
+            }).max(1);
+            let underline = format!("{}{}", marker.to_string().repeat(length),
+                                    if message.is_empty() { String::new() }
+                                    else { format!(" {}", message) });
+            writeln!(out, "{} {} {}{}", gutter, color("1;34", "|"), " ".repeat(start),
+                     color(marker_color, &underline)).unwrap();
+        };
+
+        writeln!(out, "{}{} {}", gutter, color("1;34", "-->"), self.span_).unwrap();
+        write_snippet(&mut out, &self.span_, '^', "", severity_color);
+        for label in &self.secondary_ {
+            writeln!(out, "{}{} {}", gutter, color("1;34", ":::"), label.span_).unwrap();
+            write_snippet(&mut out, &label.span_, '-', &label.message_, "1;34");
+        }
+        for child in &self.children_ {
+            writeln!(out, "{} {} {}: {}", gutter, color("1;34", "="), child.severity_,
+                     child.message_).unwrap();
+        }
+        out
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}: {}", self.span_, self.severity_, self.message_)
+    }
+}
+
+impl std::error::Error for Diagnostic {}
+
+impl From<ParseError> for Diagnostic {
+    fn from(error: ParseError) -> Self {
+        Diagnostic::error(error.message_, &error.span_)
+    }
+}
+
+/**
+ * Sources holds the text of each source file by name, for showing the source lines of a
+ * Diagnostic.
+ */
+#[derive(Clone, Debug, Default)]
+pub struct Sources {
+    texts_: HashMap<String, String>,
+}
+
+impl Sources {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /**
+     * Add the text of the file with the name used in its spans.
+     */
+    pub fn add(&mut self, file: &str, text: &str) {
+        self.texts_.insert(file.to_string(), text.to_string());
+    }
+
+    /**
+     * Return the source text of the span, or None if the file is not known or the span is not
+     * in its text.
+     */
+    pub fn get_text(&self, span: &Span) -> Option<&str> {
+        self.texts_.get(&*span.file_)?.get(span.start_..span.end_)
+    }
+
+    /**
+     * Return the line where the span starts, without the newline.
+     */
+    pub fn get_line(&self, span: &Span) -> Option<&str> {
+        let text = self.texts_.get(&*span.file_)?;
+        let start = text.get(..span.start_)?.rfind('\n').map_or(0, |i| i + 1);
+        let end = text[start..].find('\n').map_or(text.len(), |i| start + i);
+        Some(text[start..end].trim_end_matches('\r'))
+    }
+}
+
+/**
+ * Return the Levenshtein distance between the strings, counting characters.
+ */
+fn get_edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, a_char) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution = previous[j] + if a_char == *b_char { 0 } else { 1 };
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+/**
+ * Return the candidate which is most similar to the name, if it differs by at most one edit for
+ * every three characters.
+ */
+pub fn find_similar_name<'a>(name: &str, candidates: impl IntoIterator<Item = &'a str>)
+  -> Option<&'a str> {
+    let maximum_distance = (name.chars().count() / 3).max(1);
+    candidates.into_iter().filter(|candidate| *candidate != name)
+        .map(|candidate| (get_edit_distance(name, candidate), candidate))
+        .filter(|(distance, _)| *distance <= maximum_distance)
+        .min_by_key(|(distance, _)| *distance).map(|(_, candidate)| candidate)
+}
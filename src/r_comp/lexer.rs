@@ -0,0 +1,448 @@
+use std::fmt;
+use std::rc::Rc;
+use crate::core::UDuration;
+use crate::core::u_duration::ParseTimeError;
+
+/**
+ * A Span is the location of a token or expression in a source file: the byte offsets of its start
+ * and end in the file text, and the line and column of its start, counting from 1.
+ */
+#[derive(Clone, Debug, PartialEq)]
+pub struct Span {
+    pub file_: Rc<str>,
+    pub start_: usize,
+    pub end_: usize,
+    pub line_: u32,
+    pub column_: u32,
+}
+
+impl Span {
+    /**
+     * Return a span from the start of this span to the end of the other span, which must be
+     * later in the same file.
+     */
+    pub fn to(&self, other: &Span) -> Span {
+        Span { end_: other.end_.max(self.end_), ..self.clone() }
+    }
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.file_, self.line_, self.column_)
+    }
+}
+
+/**
+ * A ParseError is an error from the lexer, parser or preprocessor at a span of the source.
+ */
+#[derive(Clone, Debug, PartialEq)]
+pub struct ParseError {
+    pub message_: String,
+    pub span_: Span,
+}
+
+impl ParseError {
+    pub fn new(message: impl Into<String>, span: &Span) -> Self {
+        ParseError { message_: message.into(), span_: span.clone() }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.span_, self.message_)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum TokenKind {
+    LeftParen,
+    RightParen,
+    LeftBracket,
+    RightBracket,
+    // |[] which is the empty set.
+    EmptySet,
+    // A name followed by a colon like "p:", which labels the expression right after it, or is a
+    // variable if it is followed by white space.
+    Label(String),
+    // A colon followed by a name like ":nb", which is a member type in a class definition.
+    Type(String),
+    // ":"
+    Wildcard,
+    // "::"
+    TailWildcard,
+    // A vertical bar followed by a type name like "|nb", which is an undefined value.
+    Undefined(String),
+    // An exclamation mark followed by a name like "!class".
+    Directive(String),
+    Number(f32),
+    // A number with the unit us, ms or s, in microseconds.
+    Duration(i64),
+    // Numbers with units separated by colons like 1s:200ms:0us, in microseconds since the time
+    // reference.
+    Timestamp(i64),
+    String(String),
+    // Any other name, including class names like mk.val, operators like + and keywords like
+    // nil and true.
+    Symbol(String),
+}
+
+impl fmt::Display for TokenKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TokenKind::LeftParen => write!(f, "("),
+            TokenKind::RightParen => write!(f, ")"),
+            TokenKind::LeftBracket => write!(f, "["),
+            TokenKind::RightBracket => write!(f, "]"),
+            TokenKind::EmptySet => write!(f, "|[]"),
+            TokenKind::Label(name) => write!(f, "{}:", name),
+            TokenKind::Type(name) => write!(f, ":{}", name),
+            TokenKind::Wildcard => write!(f, ":"),
+            TokenKind::TailWildcard => write!(f, "::"),
+            TokenKind::Undefined(name) => write!(f, "|{}", name),
+            TokenKind::Directive(name) => write!(f, "!{}", name),
+            TokenKind::Number(value) => write!(f, "{}", value),
+            TokenKind::Duration(us) => write!(f, "{}us", us),
+            TokenKind::Timestamp(us) => write!(f, "{}us (timestamp)", us),
+            TokenKind::String(value) => write!(f, "{:?}", value),
+            TokenKind::Symbol(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+/**
+ * A Token is one lexical element of Replicode source with its span.
+ */
+#[derive(Clone, Debug, PartialEq)]
+pub struct Token {
+    pub kind_: TokenKind,
+    pub span_: Span,
+    // True if the token is directly after the previous token with no white space, as in the
+    // expression "p:(+ 1 2)" after the label "p:".
+    pub is_joined_: bool,
+    // True if the token is the first on its line.
+    pub starts_line_: bool,
+}
+
+// The characters which end a name.
+fn is_delimiter(c: char) -> bool {
+    c.is_whitespace() || "()[]{};\":|".contains(c)
+}
+
+/**
+ * A Location is the byte offset, line and column where a token starts.
+ */
+#[derive(Clone, Copy)]
+struct Location {
+    position_: usize,
+    line_: u32,
+    column_: u32,
+}
+
+/**
+ * Lexer splits Replicode source into tokens. A comment starts with a semicolon and goes to the
+ * end of the line. A line which ends with [] opens a set whose elements are on the following
+ * lines which are indented more than it, as in:
+ *
+ *   []
+ *      [SYNC_ONCE now 1 forever root nil]
+ *
+ * The lexer returns these sets with LeftBracket and RightBracket tokens like a set on one line.
+ */
+pub struct Lexer<'a> {
+    file_: Rc<str>,
+    text_: &'a str,
+    position_: usize,
+    line_: u32,
+    // The column of position_, counting characters from 1.
+    column_: u32,
+    line_start_: usize,
+    // The indentation of each line which opened a set with a trailing [].
+    indented_sets_: Vec<usize>,
+    tokens_: Vec<Token>,
+}
+
+impl<'a> Lexer<'a> {
+    /**
+     * Create a Lexer for the text of the file.
+     * \param file The file name to put in spans.
+     * \param text The source text.
+     */
+    pub fn new(file: &str, text: &'a str) -> Self {
+        Lexer {
+            file_: Rc::from(file), text_: text, position_: 0, line_: 1, column_: 1,
+            line_start_: 0,
+            indented_sets_: vec![], tokens_: vec![],
+        }
+    }
+
+    /**
+     * Split the text into tokens.
+     * \return The tokens, or a ParseError for an unterminated string or an invalid number.
+     */
+    pub fn tokenize(mut self) -> Result<Vec<Token>, ParseError> {
+        let mut is_joined = false;
+        let mut starts_line = true;
+        while let Some(c) = self.peek() {
+            if c == '\n' {
+                self.advance();
+                self.start_line();
+                is_joined = false;
+                starts_line = true;
+                continue;
+            }
+            if c.is_whitespace() {
+                self.advance();
+                is_joined = false;
+                continue;
+            }
+            if c == ';' {
+                while self.peek().is_some_and(|c| c != '\n') {
+                    self.advance();
+                }
+                continue;
+            }
+
+            let start = self.location();
+            let kind = self.read_token()?;
+            let span = self.span_from(start);
+            if kind == TokenKind::LeftBracket && self.rest().starts_with(']') &&
+               self.is_end_of_line(1) {
+                // A trailing [] opens an indented set.
+                self.advance();
+                let indentation = self.indentation(self.line_start_);
+                self.indented_sets_.push(indentation);
+            }
+            self.tokens_.push(Token {
+                kind_: kind, span_: span, is_joined_: is_joined, starts_line_: starts_line });
+            is_joined = true;
+            starts_line = false;
+        }
+
+        let end = self.span_from(self.location());
+        for _ in 0..self.indented_sets_.len() {
+            self.push_closing_bracket(&end);
+        }
+        Ok(self.tokens_)
+    }
+
+    fn rest(&self) -> &'a str {
+        &self.text_[self.position_..]
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.rest().chars().next()
+    }
+
+    fn advance(&mut self) {
+        if let Some(c) = self.peek() {
+            self.position_ += c.len_utf8();
+            if c == '\n' {
+                self.line_ += 1;
+                self.column_ = 1;
+                self.line_start_ = self.position_;
+            }
+            else {
+                self.column_ += 1;
+            }
+        }
+    }
+
+    fn location(&self) -> Location {
+        Location { position_: self.position_, line_: self.line_, column_: self.column_ }
+    }
+
+    /**
+     * Return the span from the start to the current position.
+     */
+    fn span_from(&self, start: Location) -> Span {
+        Span {
+            file_: Rc::clone(&self.file_), start_: start.position_, end_: self.position_,
+            line_: start.line_, column_: start.column_,
+        }
+    }
+
+    /**
+     * Return true if only white space or a comment follows the offset in the rest of the line.
+     */
+    fn is_end_of_line(&self, offset: usize) -> bool {
+        for c in self.rest()[offset..].chars() {
+            match c {
+                '\n' | ';' => return true,
+                _ if c.is_whitespace() => {},
+                _ => return false,
+            }
+        }
+        true
+    }
+
+    /**
+     * Return the number of spaces and tabs at the start of the line.
+     */
+    fn indentation(&self, line_start: usize) -> usize {
+        self.text_[line_start..].chars().take_while(|c| *c == ' ' || *c == '\t').count()
+    }
+
+    /**
+     * At the start of a line which is not blank, close the indented sets which are not indented
+     * less than this line.
+     */
+    fn start_line(&mut self) {
+        if self.is_end_of_line(0) {
+            return;
+        }
+        let indentation = self.indentation(self.line_start_);
+        let span = self.span_from(self.location());
+        while self.indented_sets_.last().is_some_and(|set| *set >= indentation) {
+            self.indented_sets_.pop();
+            self.push_closing_bracket(&span);
+        }
+    }
+
+    fn push_closing_bracket(&mut self, span: &Span) {
+        self.tokens_.push(Token {
+            kind_: TokenKind::RightBracket, span_: span.clone(), is_joined_: false,
+            starts_line_: false });
+    }
+
+    fn read_name(&mut self) -> String {
+        let start = self.position_;
+        while self.peek().is_some_and(|c| !is_delimiter(c)) {
+            self.advance();
+        }
+        self.text_[start..self.position_].to_string()
+    }
+
+    fn read_token(&mut self) -> Result<TokenKind, ParseError> {
+        let start = self.location();
+        let c = self.peek().unwrap_or(' ');
+        let mut chars = self.rest().chars();
+        chars.next();
+        let next = chars.next();
+        match c {
+            '(' => { self.advance(); Ok(TokenKind::LeftParen) },
+            ')' => { self.advance(); Ok(TokenKind::RightParen) },
+            '[' => { self.advance(); Ok(TokenKind::LeftBracket) },
+            ']' => { self.advance(); Ok(TokenKind::RightBracket) },
+            '"' => self.read_string(),
+            ':' => {
+                self.advance();
+                if self.peek() == Some(':') {
+                    self.advance();
+                    return Ok(TokenKind::TailWildcard);
+                }
+                if self.peek() == Some('~') {
+                    self.advance();
+                    return Ok(TokenKind::Type("~".to_string()));
+                }
+                if self.rest().starts_with("[]") {
+                    self.advance();
+                    self.advance();
+                    return Ok(TokenKind::Type("[]".to_string()));
+                }
+                match self.read_name() {
+                    name if name.is_empty() => Ok(TokenKind::Wildcard),
+                    name => Ok(TokenKind::Type(name)),
+                }
+            },
+            '|' => {
+                self.advance();
+                if self.rest().starts_with("[]") {
+                    self.advance();
+                    self.advance();
+                    return Ok(TokenKind::EmptySet);
+                }
+                match self.read_name() {
+                    name if name.is_empty() => Err(ParseError::new(
+                        "Expected a type name or [] after |", &self.span_from(start))),
+                    name => Ok(TokenKind::Undefined(name)),
+                }
+            },
+            '!' if next.is_some_and(|c| c.is_alphabetic()) => {
+                self.advance();
+                Ok(TokenKind::Directive(self.read_name()))
+            },
+            _ if c.is_ascii_digit() || ((c == '-' || c == '+' || c == '.') &&
+                 next.is_some_and(|c| c.is_ascii_digit())) => self.read_number(),
+            '{' | '}' => Err(ParseError::new(
+                format!("Unexpected character {}", c), &self.span_from(start))),
+            _ => {
+                let name = self.read_name();
+                if self.peek() == Some(':') && !self.rest().starts_with("::") {
+                    self.advance();
+                    return Ok(TokenKind::Label(name));
+                }
+                Ok(TokenKind::Symbol(name))
+            },
+        }
+    }
+
+    fn read_string(&mut self) -> Result<TokenKind, ParseError> {
+        let start = self.location();
+        self.advance();
+        let mut value = String::new();
+        loop {
+            match self.peek() {
+                None | Some('\n') => return Err(ParseError::new(
+                    "The string is not terminated", &self.span_from(start))),
+                Some('"') => {
+                    self.advance();
+                    return Ok(TokenKind::String(value));
+                },
+                Some('\\') => {
+                    self.advance();
+                    let escaped = self.peek();
+                    self.advance();
+                    match escaped {
+                        Some('n') => value.push('\n'),
+                        Some('t') => value.push('\t'),
+                        Some(c) => value.push(c),
+                        None => {},
+                    }
+                },
+                Some(c) => {
+                    self.advance();
+                    value.push(c);
+                },
+            }
+        }
+    }
+
+    /**
+     * Read a number, a duration like 100ms or a timestamp like 1s:200ms:0us.
+     */
+    fn read_number(&mut self) -> Result<TokenKind, ParseError> {
+        let start = self.location();
+        let mut text = self.read_name();
+        // Timestamps join durations with colons.
+        while self.peek() == Some(':') &&
+              self.rest()[1..].starts_with(|c: char| c.is_ascii_digit()) {
+            self.advance();
+            text.push(':');
+            text.push_str(&self.read_name());
+        }
+
+        let error = |message: &str| ParseError::new(
+            format!("{} {}", message, text), &self.span_from(start));
+        match text.parse::<UDuration>() {
+            Ok(duration) if text.contains(':') =>
+                return Ok(TokenKind::Timestamp(duration.as_microseconds())),
+            Ok(duration) => return Ok(TokenKind::Duration(duration.as_microseconds())),
+            Err(ParseTimeError::Overflow(_)) => return Err(error("Out of range time")),
+            Err(ParseTimeError::Invalid(_)) if text.contains(':') =>
+                return Err(error("Invalid timestamp")),
+            Err(ParseTimeError::Invalid(_)) => {},
+        }
+        text.parse::<f32>().map(TokenKind::Number).map_err(|_| error("Invalid number"))
+    }
+}
+
+/**
+ * Return the string in double quotes with the escapes which the Lexer reads, for writing source.
+ */
+pub fn quote_string(value: &str) -> String {
+    let escaped = value.replace('\\', "\\\\").replace('"', "\\\"")
+        .replace('\n', "\\n").replace('\t', "\\t");
+    format!("\"{}\"", escaped)
+}
@@ -0,0 +1,262 @@
+use std::collections::HashSet;
+use crate::r_code::atom::Atom;
+use crate::r_code::image::ImageError;
+use crate::r_code::object_registry::UNDEFINED_OID;
+use crate::r_code::{SysObject, SysView};
+use super::class::Class;
+use super::opcode_map::OpcodeMap;
+use super::segments::{DefinitionSegment, Image};
+
+// The largest opcode which fits in an atom.
+const MAX_OPCODE: usize = 0x0FFF;
+
+/**
+ * Return true if the classes have the same kind of head atom, arity and members, ignoring the
+ * opcode.
+ */
+fn same_definition(class: &Class, other: &Class) -> bool {
+    class.atom_.getDescriptor() == other.atom_.getDescriptor() &&
+      class.atom_.getAtomCount() == other.atom_.getAtomCount() &&
+      class.things_to_read_.len() == other.things_to_read_.len() &&
+      class.things_to_read_.iter().zip(other.things_to_read_.iter()).all(|(member, other)| {
+          member.name_ == other.name_ && member.type_ == other.type_
+      })
+}
+
+/**
+ * Append to classes each class of from_classes with a name which is not already there.
+ * \return The index in classes of the first appended class, or an ImageError if a class with the
+ * same name has a different definition.
+ */
+fn merge_named_classes(
+  classes: &mut Vec<(String, Class)>, from_classes: &[(String, Class)])
+  -> Result<usize, ImageError> {
+    let first_appended = classes.len();
+    for (name, class) in from_classes {
+        match classes.iter().find(|(class_name, _)| class_name == name) {
+            Some((_, existing)) if !same_definition(existing, class) =>
+                return Err(ImageError::Invalid(format!(
+                    "The class {} has a different definition in each image", name))),
+            Some(_) => {}
+            None => classes.push((name.clone(), class.clone())),
+        }
+    }
+    Ok(first_appended)
+}
+
+fn merge_names(names: &mut Vec<String>, from_names: &[String]) {
+    for name in from_names {
+        if !names.contains(name) {
+            names.push(name.clone());
+        }
+    }
+}
+
+/**
+ * Add the classes, operators and device functions of from which are not in the definition
+ * segment, giving them the next opcodes.
+ * \return An ImageError if a class has a different definition in each segment or there are too
+ * many opcodes. In this case the definition segment is partly merged.
+ */
+fn merge_definitions(
+  definition_segment: &mut DefinitionSegment, from: &DefinitionSegment)
+  -> Result<(), ImageError> {
+    let first_appended_by_opcode = definition_segment.classes_by_opcodes_.len();
+    for class in &from.classes_by_opcodes_ {
+        match definition_segment.classes_by_opcodes_.iter()
+              .find(|existing| existing.str_opcode_ == class.str_opcode_) {
+            Some(existing) if !same_definition(existing, class) =>
+                return Err(ImageError::Invalid(format!(
+                    "The class {} has a different definition in each image", class.str_opcode_))),
+            Some(_) => {}
+            None => definition_segment.classes_by_opcodes_.push(class.clone()),
+        }
+    }
+    let first_appended_class =
+      merge_named_classes(&mut definition_segment.classes_, &from.classes_)?;
+    let first_appended_sys_class =
+      merge_named_classes(&mut definition_segment.sys_classes_, &from.sys_classes_)?;
+    merge_names(&mut definition_segment.class_names_, &from.class_names_);
+    merge_names(&mut definition_segment.operator_names_, &from.operator_names_);
+    merge_names(&mut definition_segment.function_names_, &from.function_names_);
+
+    for (kind, count) in [
+      ("class", definition_segment.classes_by_opcodes_.len()),
+      ("operator", definition_segment.operator_names_.len()),
+      ("device function", definition_segment.function_names_.len())] {
+        if count > MAX_OPCODE + 1 {
+            return Err(ImageError::Invalid(format!(
+                "The merged images have {} {} opcodes, more than the maximum {}", count, kind,
+                MAX_OPCODE + 1)));
+        }
+    }
+
+    // The appended classes still have the opcodes of from.
+    let opcode_map = OpcodeMap::new(from, definition_segment);
+    for class in definition_segment.classes_by_opcodes_[first_appended_by_opcode..].iter_mut() {
+        class.atom_ = opcode_map.remap_atom(class.atom_)?;
+    }
+    for (_, class) in definition_segment.classes_[first_appended_class..].iter_mut()
+        .chain(definition_segment.sys_classes_[first_appended_sys_class..].iter_mut()) {
+        class.atom_ = opcode_map.remap_atom(class.atom_)?;
+    }
+    Ok(())
+}
+
+/**
+ * Return the code with the opcodes remapped.
+ */
+fn remap_code(code: &[Atom], opcode_map: &OpcodeMap) -> Result<Vec<Atom>, ImageError> {
+    let mut code = code.to_vec();
+    opcode_map.remap_code(&mut code)?;
+    Ok(code)
+}
+
+impl Image {
+    /**
+     * Merge the other image into this one so that its objects can reference each other and
+     * the objects of this image. The definition segments are unified by name: the classes,
+     * operators and device functions of the other image which are not in this image get the next
+     * opcodes, and the opcodes in the code of the other image's objects and views are remapped.
+     * The other image's objects are appended to the code segment, keeping their OID unless it is
+     * already used, in which case they get the next unused OID. A named object of the other
+     * image with the same name as an object of this image is taken to be a reference to it (for
+     * example a model which references the std object stdin): references to it are linked to
+     * the object of this image, its markers and views on other hosts are added there, and its
+     * own code is dropped. The relocation segment is rebuilt.
+     * \param other The image to merge.
+     * \return Ok, or an ImageError if a class has a different definition in each image, the
+     * images have too many opcodes or objects, or objects with the same name have a different
+     * class. In this case this image is not changed.
+     */
+    pub fn merge(&mut self, other: &Image) -> Result<(), ImageError> {
+        let other_objects = &other.code_segment_.objects_;
+        for (i, object) in other_objects.iter().enumerate() {
+            let mut references = object.references_.iter().map(|r| *r as u32)
+                .chain(object.views_.iter().flat_map(|view| view.references_.iter().copied()))
+                .chain(object.markers_.iter().copied());
+            if let Some(reference_index) = references.find(|r| *r as usize >= other_objects.len()) {
+                return Err(ImageError::DanglingReference {
+                    object_index: i, oid: object.oid_, reference_index });
+            }
+        }
+
+        let mut definition_segment = self.definition_segment_.clone();
+        merge_definitions(&mut definition_segment, &other.definition_segment_)?;
+        let opcode_map = OpcodeMap::new(&other.definition_segment_, &definition_segment);
+
+        let objects = &self.code_segment_.objects_;
+        let mut used_oids: HashSet<u32> = objects.iter().map(|object| object.oid_).collect();
+        let mut next_oid = 0;
+
+        // Find the new index of each object of other. Named objects which are already in this
+        // image are linked to the existing object.
+        let mut new_indices = vec![];
+        let mut added = vec![];
+        let mut linked = vec![];
+        for (i, other_object) in other_objects.iter().enumerate() {
+            let name = other.object_names_.get_name(other_object.oid_);
+            if let Some(existing_oid) = name.and_then(|name| self.object_names_.get_oid(name)) {
+                let name = name.unwrap_or_default();
+                let existing_index = objects.iter().position(|object| object.oid_ == existing_oid)
+                    .ok_or_else(|| ImageError::Invalid(format!(
+                        "The object name {} has OID {} which is not in the image", name,
+                        existing_oid)))?;
+                let existing_head = objects[existing_index].code_.first().copied();
+                let other_head = match other_object.code_.first() {
+                    Some(head) => Some(opcode_map.remap_atom(*head)?),
+                    None => None,
+                };
+                if existing_head.map(|a| a.atom_) != other_head.map(|a| a.atom_) {
+                    return Err(ImageError::Invalid(format!(
+                        "The object named {} has a different class in each image", name)));
+                }
+                new_indices.push(existing_index);
+                linked.push((i, existing_index));
+                continue;
+            }
+
+            let mut oid = other_object.oid_;
+            if oid != UNDEFINED_OID && used_oids.contains(&oid) {
+                while used_oids.contains(&next_oid) {
+                    next_oid += 1;
+                }
+                oid = next_oid;
+            }
+            used_oids.insert(oid);
+            new_indices.push(objects.len() + added.len());
+            added.push((i, oid));
+        }
+
+        if objects.len() + added.len() > u16::MAX as usize + 1 {
+            return Err(ImageError::Invalid(format!(
+                "The merged image has {} objects, more than can be referenced",
+                objects.len() + added.len())));
+        }
+        let new_index = |index: u32| new_indices[index as usize] as u32;
+        let new_view = |view: &SysView| -> Result<SysView, ImageError> {
+            Ok(SysView { code_: remap_code(&view.code_, &opcode_map)?,
+                         references_: view.references_.iter().map(|r| new_index(*r)).collect() })
+        };
+        let mut new_objects = vec![];
+        for (i, oid) in &added {
+            let object = &other_objects[*i];
+            new_objects.push(SysObject {
+                oid_: *oid,
+                #[cfg(feature = "with_detail_oid")]
+                detail_oid_: object.detail_oid_,
+                code_: remap_code(&object.code_, &opcode_map)?,
+                references_: object.references_.iter().map(|r| new_index(*r as u32) as u16)
+                    .collect(),
+                markers_: object.markers_.iter().map(|m| new_index(*m)).collect(),
+                views_: object.views_.iter().map(new_view).collect::<Result<_, _>>()?,
+            });
+        }
+        let mut linked_views = vec![];
+        for (i, existing_index) in &linked {
+            let views = other_objects[*i].views_.iter().map(new_view)
+                .collect::<Result<Vec<_>, _>>()?;
+            linked_views.push((*i, *existing_index, views));
+        }
+
+        // Nothing can fail now, so update this image.
+        self.definition_segment_ = definition_segment;
+        for (i, existing_index, views) in linked_views {
+            let existing = &mut self.code_segment_.objects_[existing_index];
+            for marker in &other_objects[i].markers_ {
+                let marker = new_index(*marker);
+                if !existing.markers_.contains(&marker) {
+                    existing.markers_.push(marker);
+                }
+            }
+            for view in views {
+                let host = view.references_.first();
+                if !existing.views_.iter().any(|existing| existing.references_.first() == host) {
+                    existing.views_.push(view);
+                }
+            }
+        }
+        for ((i, oid), object) in added.iter().zip(new_objects) {
+            if let Some(name) = other.object_names_.get_name(other_objects[*i].oid_) {
+                self.object_names_.insert(*oid, name);
+            }
+            self.code_segment_.objects_.push(object);
+        }
+        self.relocation_segment_.build(&self.code_segment_);
+        Ok(())
+    }
+}
+
+/**
+ * Link the images into one image by merging each into an empty image in order, so that a named
+ * object of a later image links to the object with the same name in an earlier image.
+ * \param images The images, for example the std image, an ontology and learned models.
+ * \return The linked image, or the ImageError from Image::merge.
+ */
+pub fn link(images: &[Image]) -> Result<Image, ImageError> {
+    let mut result = Image::default();
+    for image in images {
+        result.merge(image)?;
+    }
+    Ok(result)
+}
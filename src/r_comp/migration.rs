@@ -0,0 +1,51 @@
+use std::collections::BTreeMap;
+use crate::r_code::image::ImageError;
+use super::opcode_map::OpcodeMap;
+use super::segments::{DefinitionSegment, Image};
+
+impl Image {
+    /**
+     * Migrate the image to a new definition segment, for example after a class was added to
+     * user.classes.replicode which shifts the opcodes of the later classes. The opcode of each
+     * atom in the code of the objects and views is changed to the opcode of the class, operator
+     * or device function with the same name in the new definition segment, which replaces the
+     * image's definition segment.
+     * \param definition_segment The new definition segment.
+     * \return Ok, or ImageError::MissingDefinitions listing every class, operator or device
+     * function used by the image which is not in the new definition segment and the OIDs of
+     * the objects which use it. In this case the image is not changed.
+     */
+    pub fn migrate(&mut self, definition_segment: &DefinitionSegment) -> Result<(), ImageError> {
+        let opcode_map = OpcodeMap::new(&self.definition_segment_, definition_segment);
+
+        let mut missing: BTreeMap<String, Vec<u32>> = BTreeMap::new();
+        for object in &self.code_segment_.objects_ {
+            let codes = Some(&object.code_).into_iter()
+                .chain(object.views_.iter().map(|view| &view.code_));
+            for code in codes {
+                for description in opcode_map.get_missing(code) {
+                    let oids = missing.entry(description).or_default();
+                    if !oids.contains(&object.oid_) {
+                        oids.push(object.oid_);
+                    }
+                }
+            }
+        }
+        if !missing.is_empty() {
+            return Err(ImageError::MissingDefinitions(missing.iter().map(|(description, oids)| {
+                let oids: Vec<String> = oids.iter().map(|oid| oid.to_string()).collect();
+                format!("{} used by OID {}", description, oids.join(", "))
+            }).collect()));
+        }
+
+        // The missing check means that remapping can't fail.
+        for object in self.code_segment_.objects_.iter_mut() {
+            opcode_map.remap_code(&mut object.code_)?;
+            for view in object.views_.iter_mut() {
+                opcode_map.remap_code(&mut view.code_)?;
+            }
+        }
+        self.definition_segment_ = definition_segment.clone();
+        Ok(())
+    }
+}
@@ -0,0 +1,29 @@
+pub mod ast;
+pub mod class;
+pub mod compiler;
+pub mod decompiler;
+pub mod diagnostic;
+pub mod lexer;
+pub mod linker;
+pub mod migration;
+pub mod opcode_map;
+pub mod parser;
+pub mod preprocessor;
+pub mod segments;
+pub mod structure_member;
+pub mod type_checker;
+pub mod validation;
+
+pub use self::ast::{Expression, ExpressionKind, SourceObject};
+pub use self::class::Class;
+pub use self::compiler::Compiler;
+pub use self::decompiler::{decompile_image, Decompiler};
+pub use self::diagnostic::{Diagnostic, Severity, Sources};
+pub use self::lexer::{Lexer, ParseError, Span};
+pub use self::linker::link;
+pub use self::opcode_map::OpcodeMap;
+pub use self::parser::{parse, Parser};
+pub use self::preprocessor::{compile_file, Preprocessor, SourceMap};
+pub use self::segments::Image;
+pub use self::structure_member::StructureMember;
+pub use self::type_checker::{TypeChecker, ValueType};
@@ -0,0 +1,167 @@
+use crate::r_code::atom;
+use crate::r_code::atom::Atom;
+use crate::r_code::image::ImageError;
+use super::segments::DefinitionSegment;
+
+// The cast opcode of a CODE_VL_PTR which has no cast.
+const NO_CAST_OPCODE: u16 = 0x0FFF;
+
+/**
+ * The new opcode for each opcode of one opcode space, found by name.
+ */
+#[derive(Clone, Default)]
+struct OpcodeTable {
+    // The names in the source definition segment, indexed by opcode.
+    names_: Vec<String>,
+    new_opcodes_: Vec<Option<u16>>,
+}
+
+impl OpcodeTable {
+    fn new(from_names: &[String], to_names: &[String]) -> Self {
+        OpcodeTable {
+            names_: from_names.to_vec(),
+            new_opcodes_: from_names.iter().map(|name| {
+                to_names.iter().position(|to_name| to_name == name).map(|opcode| opcode as u16)
+            }).collect(),
+        }
+    }
+
+    /**
+     * Return the new opcode, or a description of the class, operator or device function if it
+     * is not in the new definition segment, like "class fact".
+     */
+    fn get(&self, opcode: u16, kind: &str) -> Result<u16, String> {
+        match self.new_opcodes_.get(opcode as usize) {
+            Some(Some(new_opcode)) => Ok(*new_opcode),
+            Some(None) => Err(format!("{} {}", kind, self.names_[opcode as usize])),
+            None => Err(format!("{} opcode {} (not in the old definition segment)", kind, opcode)),
+        }
+    }
+
+    fn is_identity(&self) -> bool {
+        self.new_opcodes_.iter().enumerate().all(|(opcode, new_opcode)| {
+            *new_opcode == Some(opcode as u16)
+        })
+    }
+}
+
+/**
+ * OpcodeMap maps the opcodes of the atoms compiled with one definition segment to the opcodes of
+ * the same classes, operators and device functions in another definition segment, matching them
+ * by name. Classes are indexed by classes_by_opcodes_, operators by operator_names_ and device
+ * functions by function_names_.
+ */
+#[derive(Clone, Default)]
+pub struct OpcodeMap {
+    classes_: OpcodeTable,
+    operators_: OpcodeTable,
+    functions_: OpcodeTable,
+}
+
+impl OpcodeMap {
+    /**
+     * Create an OpcodeMap from the opcodes of one definition segment to another.
+     * \param from The definition segment which was used to compile the atoms.
+     * \param to The definition segment with the new opcodes.
+     */
+    pub fn new(from: &DefinitionSegment, to: &DefinitionSegment) -> Self {
+        let class_names = |segment: &DefinitionSegment| -> Vec<String> {
+            segment.classes_by_opcodes_.iter().map(|class| class.str_opcode_.clone()).collect()
+        };
+        OpcodeMap {
+            classes_: OpcodeTable::new(&class_names(from), &class_names(to)),
+            operators_: OpcodeTable::new(&from.operator_names_, &to.operator_names_),
+            functions_: OpcodeTable::new(&from.function_names_, &to.function_names_),
+        }
+    }
+
+    /**
+     * Return true if every opcode maps to itself, so that atoms don't need to be remapped.
+     */
+    pub fn is_identity(&self) -> bool {
+        self.classes_.is_identity() && self.operators_.is_identity() &&
+          self.functions_.is_identity()
+    }
+
+    /**
+     * Return the atom with its opcode replaced by the new opcode. Atoms without an opcode, an
+     * untyped wildcard and a CODE_VL_PTR without a cast are returned unchanged.
+     * \param a The atom.
+     * \return The remapped atom, or an ImageError if the class, operator or device function of
+     * the opcode is not in the new definition segment.
+     */
+    pub fn remap_atom(&self, a: Atom) -> Result<Atom, ImageError> {
+        self.try_remap_atom(a).map_err(|missing| ImageError::Invalid(format!(
+            "The {} is not in the new definition segment", missing)))
+    }
+
+    fn try_remap_atom(&self, a: Atom) -> Result<Atom, String> {
+        let with_opcode = |opcode: u16| {
+            Atom::new((a.atom_ & 0xFFF000FF) | (((opcode as u32) & 0x0FFF) << 8))
+        };
+        match a.getDescriptor() {
+            atom::OBJECT | atom::MARKER | atom::S_SET | atom::GROUP | atom::INSTANTIATED_PROGRAM |
+            atom::INSTANTIATED_CPP_PROGRAM | atom::INSTANTIATED_INPUT_LESS_PROGRAM |
+            atom::INSTANTIATED_ANTI_PROGRAM | atom::COMPOSITE_STATE | atom::MODEL =>
+                Ok(with_opcode(self.classes_.get(a.asOpcode(), "class")?)),
+            // Wildcard() has opcode 0 for any class.
+            atom::WILDCARD if a.asOpcode() == 0 => Ok(a),
+            atom::WILDCARD => Ok(with_opcode(self.classes_.get(a.asOpcode(), "class")?)),
+            atom::OPERATOR => Ok(with_opcode(self.operators_.get(a.asOpcode(), "operator")?)),
+            atom::DEVICE_FUNCTION if a.atom_ == Atom::UndefinedDeviceFunction().atom_ => Ok(a),
+            atom::DEVICE_FUNCTION =>
+                Ok(with_opcode(self.functions_.get(a.asOpcode(), "device function")?)),
+            atom::CODE_VL_PTR if a.asCastOpcode() == NO_CAST_OPCODE => Ok(a),
+            atom::CODE_VL_PTR => Ok(Atom::CodeVLPointer_cast_opcode(
+                a.asIndex(), self.classes_.get(a.asCastOpcode(), "class")?)),
+            _ => Ok(a),
+        }
+    }
+
+    /**
+     * Remap the opcode of each atom in the code, skipping the data words of timestamps,
+     * durations and strings.
+     * \param code The code to change in place.
+     * \return Ok, or an ImageError from remap_atom. In this case the code is partly remapped.
+     */
+    pub fn remap_code(&self, code: &mut [Atom]) -> Result<(), ImageError> {
+        for i in atom_indices(code) {
+            code[i] = self.remap_atom(code[i])?;
+        }
+        Ok(())
+    }
+
+    /**
+     * Return a description of each class, operator or device function used in the code which is
+     * not in the new definition segment, like "class fact", without duplicates.
+     */
+    pub fn get_missing(&self, code: &[Atom]) -> Vec<String> {
+        let mut missing = vec![];
+        for i in atom_indices(code) {
+            if let Err(description) = self.try_remap_atom(code[i]) {
+                if !missing.contains(&description) {
+                    missing.push(description);
+                }
+            }
+        }
+        missing
+    }
+}
+
+/**
+ * Return the indices of the atoms in the code, leaving out the data words of timestamps,
+ * durations and strings which can look like atoms.
+ */
+fn atom_indices(code: &[Atom]) -> Vec<usize> {
+    let mut indices = vec![];
+    let mut i = 0;
+    while i < code.len() {
+        indices.push(i);
+        i += 1 + match code[i].getDescriptor() {
+            atom::TIMESTAMP | atom::DURATION => 2,
+            atom::STRING => code[i].getAtomCount() as usize,
+            _ => 0,
+        };
+    }
+    indices
+}
@@ -0,0 +1,224 @@
+use super::ast::{Expression, ExpressionKind, SourceObject};
+use super::lexer::{Lexer, ParseError, Span, Token, TokenKind};
+
+// The maximum number of nested structures and sets, so that deeply nested source returns a
+// ParseError instead of overflowing the stack in the parser or compiler.
+pub const MAX_DEPTH: usize = 256;
+
+/**
+ * Parser builds Expressions and SourceObjects from the tokens of the Lexer. Directives like
+ * !class must be handled by the preprocessor first.
+ */
+pub struct Parser {
+    tokens_: Vec<Token>,
+    position_: usize,
+    // The number of structures and sets which are being parsed.
+    depth_: usize,
+    // The span after the last token, for errors at the end of the input.
+    end_span_: Span,
+}
+
+impl Parser {
+    /**
+     * Create a Parser for the tokens.
+     * \param tokens The tokens from the Lexer or the preprocessor.
+     * \param end_span The span for an error at the end of the tokens, usually the end of the
+     * source file.
+     */
+    pub fn new(tokens: Vec<Token>, end_span: Span) -> Self {
+        Parser { tokens_: tokens, position_: 0, depth_: 0, end_span_: end_span }
+    }
+
+    pub fn is_at_end(&self) -> bool {
+        self.position_ >= self.tokens_.len()
+    }
+
+    /**
+     * Return the next token without consuming it, or None at the end.
+     */
+    pub fn peek(&self) -> Option<&Token> {
+        self.tokens_.get(self.position_)
+    }
+
+    fn next(&mut self) -> Result<Token, ParseError> {
+        match self.tokens_.get(self.position_) {
+            Some(token) => {
+                self.position_ += 1;
+                Ok(token.clone())
+            },
+            None => Err(ParseError::new("Unexpected end of the source", &self.end_span_)),
+        }
+    }
+
+    /**
+     * Parse the top-level objects until the end of the tokens.
+     * \return The objects, or the first ParseError.
+     */
+    pub fn parse_objects(&mut self) -> Result<Vec<SourceObject>, ParseError> {
+        let mut objects = vec![];
+        while !self.is_at_end() {
+            objects.push(self.parse_object()?);
+        }
+        Ok(objects)
+    }
+
+    /**
+     * Parse a top-level object: a labeled or unlabeled structure followed by its views, which
+     * are a set of sets, or |[] for no views.
+     */
+    pub fn parse_object(&mut self) -> Result<SourceObject, ParseError> {
+        let expression = self.parse_expression()?;
+        if expression.get_head().is_none() {
+            return Err(ParseError::new(
+                "Expected an object like (class member...) at the top level", &expression.span_));
+        }
+
+        let mut span = expression.span_.clone();
+        let mut views = vec![];
+        match self.peek().map(|token| &token.kind_) {
+            Some(TokenKind::EmptySet) => span = span.to(&self.next()?.span_),
+            Some(TokenKind::LeftBracket) => {
+                let view_set = self.parse_expression()?;
+                span = span.to(&view_set.span_);
+                for view in view_set.get_members() {
+                    if !matches!(view.kind_, ExpressionKind::Set(_)) {
+                        return Err(ParseError::new(
+                            "Expected a view like [member...] in the set of views", &view.span_));
+                    }
+                    views.push(view.clone());
+                }
+            },
+            _ => {},
+        }
+        Ok(SourceObject { expression_: expression, views_: views, span_: span })
+    }
+
+    /**
+     * Parse one expression with its optional label.
+     * \return The Expression, or a ParseError if the tokens don't start an expression or it has
+     * more than MAX_DEPTH nested structures and sets.
+     */
+    pub fn parse_expression(&mut self) -> Result<Expression, ParseError> {
+        let token = self.next()?;
+        if let TokenKind::Label(label) = &token.kind_ {
+            // A label joined to an expression labels it. Otherwise it is a variable.
+            let is_labeling = self.peek().is_some_and(|next| {
+                next.is_joined_ &&
+                  !matches!(next.kind_, TokenKind::RightParen | TokenKind::RightBracket)
+            });
+            let mut expression = if is_labeling {
+                // Check for a second label here, since parsing it would recurse once per label.
+                if let Some(Token { kind_: TokenKind::Label(_), span_, .. }) = self.peek() {
+                    return Err(ParseError::new("An expression can't have two labels", span_));
+                }
+                let expression = self.parse_expression()?;
+                Expression { span_: token.span_.to(&expression.span_), ..expression }
+            }
+            else {
+                Expression::new(ExpressionKind::Wildcard, token.span_.clone())
+            };
+            expression.label_ = Some(label.clone());
+            return Ok(expression);
+        }
+
+        let kind = match token.kind_ {
+            TokenKind::LeftParen | TokenKind::LeftBracket => {
+                if self.depth_ >= MAX_DEPTH {
+                    return Err(ParseError::new(format!(
+                        "The expression is nested more than {} deep", MAX_DEPTH), &token.span_));
+                }
+                self.depth_ += 1;
+                let expression = if token.kind_ == TokenKind::LeftParen {
+                    self.parse_structure(&token.span_)
+                }
+                else {
+                    self.parse_set(&token.span_)
+                };
+                self.depth_ -= 1;
+                return expression;
+            },
+            TokenKind::EmptySet => ExpressionKind::Set(vec![]),
+            TokenKind::Number(value) => ExpressionKind::Number(value),
+            TokenKind::Duration(value) => ExpressionKind::Duration(value),
+            TokenKind::Timestamp(value) => ExpressionKind::Timestamp(value),
+            TokenKind::String(value) => ExpressionKind::String(value),
+            TokenKind::Wildcard => ExpressionKind::Wildcard,
+            TokenKind::TailWildcard => ExpressionKind::TailWildcard,
+            TokenKind::Undefined(name) => ExpressionKind::Undefined(name),
+            TokenKind::Type(name) => ExpressionKind::Type(name),
+            TokenKind::Symbol(name) => match name.as_str() {
+                "true" => ExpressionKind::Boolean(true),
+                "false" => ExpressionKind::Boolean(false),
+                "nil" => ExpressionKind::Nil,
+                _ => ExpressionKind::Symbol(name),
+            },
+            TokenKind::Directive(name) => return Err(ParseError::new(
+                format!("The directive !{} must be handled by the preprocessor", name),
+                &token.span_)),
+            kind => return Err(ParseError::new(format!("Unexpected {}", kind), &token.span_)),
+        };
+        Ok(Expression::new(kind, token.span_))
+    }
+
+    fn parse_structure(&mut self, start: &Span) -> Result<Expression, ParseError> {
+        let head = self.next()?;
+        let head_name = match head.kind_ {
+            TokenKind::Symbol(name) => name,
+            kind => return Err(ParseError::new(
+                format!("Expected a class, operator or function name after ( but got {}", kind),
+                &head.span_)),
+        };
+
+        let mut members = vec![];
+        loop {
+            if let Some(Token { kind_: TokenKind::RightParen, span_, .. }) = self.peek() {
+                let span = start.to(span_);
+                self.position_ += 1;
+                return Ok(Expression::new(ExpressionKind::Structure {
+                    head_: head_name, head_span_: head.span_, members_: members }, span));
+            }
+            if self.is_at_end() {
+                return Err(ParseError::new("The ( is not closed", start));
+            }
+            members.push(self.parse_expression()?);
+        }
+    }
+
+    fn parse_set(&mut self, start: &Span) -> Result<Expression, ParseError> {
+        let mut elements = vec![];
+        loop {
+            if let Some(Token { kind_: TokenKind::RightBracket, span_, .. }) = self.peek() {
+                let span = start.to(span_);
+                self.position_ += 1;
+                return Ok(Expression::new(ExpressionKind::Set(elements), span));
+            }
+            if self.is_at_end() {
+                return Err(ParseError::new("The [ is not closed", start));
+            }
+            elements.push(self.parse_expression()?);
+        }
+    }
+}
+
+/**
+ * Return the span at the end of the text, for an error at the end of the source.
+ */
+pub fn get_end_span(file: &str, text: &str) -> Span {
+    let line_start = text.rfind('\n').map_or(0, |i| i + 1);
+    Span {
+        file_: file.into(), start_: text.len(), end_: text.len(),
+        line_: text.matches('\n').count() as u32 + 1,
+        column_: text[line_start..].chars().count() as u32 + 1,
+    }
+}
+
+/**
+ * Parse the objects of Replicode source which has no directives.
+ * \param file The file name for the spans.
+ * \param text The source text.
+ * \return The objects, or the first ParseError.
+ */
+pub fn parse(file: &str, text: &str) -> Result<Vec<SourceObject>, ParseError> {
+    let tokens = Lexer::new(file, text).tokenize()?;
+    Parser::new(tokens, get_end_span(file, text)).parse_objects()
+}
@@ -0,0 +1,768 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use crate::core::UTimestamp;
+use crate::core::u_duration::microseconds;
+use crate::r_code::atom::{self, Atom};
+use crate::r_code::Utils;
+use super::ast::{Expression, ExpressionKind, SourceObject};
+use super::class::{Class, ReturnType};
+use super::compiler::Compiler;
+use super::diagnostic::{Diagnostic, Sources};
+use super::lexer::{quote_string, Lexer, ParseError, Span, Token, TokenKind};
+use super::parser::{get_end_span, Parser};
+use super::segments::{DefinitionSegment, Image};
+use super::structure_member::{Iteration, ReadId, StructureMember};
+
+/**
+ * A Macro is defined by !def. A constant like "!def SYNC_ONCE 0" has no parameters. A macro
+ * like "!def (now_plus d) (+ (_now) d)" has parameters which are replaced by the arguments.
+ */
+struct Macro {
+    parameters_: Option<Vec<String>>,
+    body_: Vec<Token>,
+}
+
+/**
+ * A Condition is an open !ifdef or !ifundef.
+ */
+struct Condition {
+    is_active_: bool,
+    has_else_: bool,
+    span_: Span,
+}
+
+/**
+ * A SourceMapEntry maps one token of the expanded text to where it is in the original source.
+ */
+#[derive(Clone, Debug, PartialEq)]
+pub struct SourceMapEntry {
+    pub expanded_start_: usize,
+    pub expanded_end_: usize,
+    // The span of the token in the file where it is written, which for a token of a macro body
+    // is in the !def.
+    pub span_: Span,
+    // The span of the macro use which the token was expanded from, or None if the token is not
+    // from a macro expansion.
+    pub expanded_from_: Option<Span>,
+}
+
+/**
+ * A SourceMap maps byte offsets in the text from Preprocessor::get_expanded_text back to the
+ * original source.
+ */
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SourceMap {
+    pub entries_: Vec<SourceMapEntry>,
+}
+
+impl SourceMap {
+    /**
+     * Return the entry of the token at the offset in the expanded text, or None if the offset is
+     * in white space between tokens.
+     */
+    pub fn get_entry(&self, offset: usize) -> Option<&SourceMapEntry> {
+        let index = self.entries_.partition_point(|entry| entry.expanded_end_ <= offset);
+        self.entries_.get(index).filter(|entry| entry.expanded_start_ <= offset)
+    }
+}
+
+/**
+ * Return the text of the token in a form which the Lexer reads back as the same token.
+ */
+fn to_source(kind: &TokenKind) -> String {
+    match kind {
+        TokenKind::Timestamp(us) => Utils::to_string_s_ms_us(
+            UTimestamp::from_duration(microseconds(*us)), UTimestamp::default()),
+        TokenKind::String(value) => quote_string(value),
+        kind => kind.to_string(),
+    }
+}
+
+/**
+ * Return the index after the expression which starts at the index, including a label which is
+ * joined to the expression and the members of a structure or set.
+ * \param tokens The tokens.
+ * \param start The index of the first token of the expression.
+ * \param end_span The span for the error if there is no expression at start.
+ */
+fn get_expression_end(tokens: &[Token], start: usize, end_span: &Span)
+  -> Result<usize, ParseError> {
+    let token = tokens.get(start)
+        .ok_or_else(|| ParseError::new("Expected an expression", end_span))?;
+    match token.kind_ {
+        TokenKind::Label(_) => match tokens.get(start + 1) {
+            Some(next) if next.is_joined_ &&
+              !matches!(next.kind_, TokenKind::RightParen | TokenKind::RightBracket) =>
+                get_expression_end(tokens, start + 1, end_span),
+            _ => Ok(start + 1),
+        },
+        TokenKind::LeftParen | TokenKind::LeftBracket => {
+            let mut depth = 0;
+            for (i, token) in tokens.iter().enumerate().skip(start) {
+                match token.kind_ {
+                    TokenKind::LeftParen | TokenKind::LeftBracket => depth += 1,
+                    TokenKind::RightParen | TokenKind::RightBracket => {
+                        depth -= 1;
+                        if depth == 0 {
+                            return Ok(i + 1);
+                        }
+                    },
+                    _ => {},
+                }
+            }
+            Err(ParseError::new(format!("The {} is not closed", token.kind_), &token.span_))
+        },
+        TokenKind::RightParen | TokenKind::RightBracket =>
+            Err(ParseError::new(format!("Unexpected {}", token.kind_), &token.span_)),
+        _ => Ok(start + 1),
+    }
+}
+
+/**
+ * Split the tokens into the tokens of each expression.
+ */
+fn split_expressions<'a>(tokens: &'a [Token], end_span: &Span)
+  -> Result<Vec<&'a [Token]>, ParseError> {
+    let mut expressions = vec![];
+    let mut start = 0;
+    while start < tokens.len() {
+        let end = get_expression_end(tokens, start, end_span)?;
+        expressions.push(&tokens[start..end]);
+        start = end;
+    }
+    Ok(expressions)
+}
+
+/**
+ * Copy the tokens so that the first token is joined to what comes before it or starts a line
+ * like the token which it replaces.
+ */
+fn replace_token(tokens: &[Token], replaced: &Token, output: &mut Vec<Token>) {
+    let start = output.len();
+    output.extend_from_slice(tokens);
+    if let Some(first) = output.get_mut(start) {
+        first.is_joined_ = replaced.is_joined_;
+        first.starts_line_ = replaced.starts_line_;
+    }
+}
+
+/**
+ * Return the read ID, type, iteration and class of a member type in a class or operator
+ * definition, like nb in psln_thr:nb or a class name.
+ */
+fn get_member_type(type_name: &str, definition_segment: &DefinitionSegment)
+  -> Option<(ReadId, ReturnType, Iteration, String)> {
+    let (read_id, return_type) = match type_name {
+        "nb" => (ReadId::Number, ReturnType::Number),
+        "ts" => (ReadId::Timestamp, ReturnType::Timestamp),
+        "us" => (ReadId::Duration, ReturnType::Duration),
+        "bl" => (ReadId::Boolean, ReturnType::Boolean),
+        "st" => (ReadId::String, ReturnType::String),
+        "nid" => (ReadId::Node, ReturnType::NodeId),
+        "did" => (ReadId::Device, ReturnType::DeviceId),
+        "fid" => (ReadId::Function, ReturnType::FunctionId),
+        "[]" => return Some((ReadId::Set, ReturnType::Set, Iteration::ISet, String::new())),
+        _ if definition_segment.get_class(type_name).is_some() => return Some(
+            (ReadId::Class, ReturnType::Class, Iteration::IExpression, type_name.to_string())),
+        _ => return None,
+    };
+    Some((read_id, return_type, Iteration::IExpression, String::new()))
+}
+
+/**
+ * Preprocessor runs the directives of Replicode source and expands its macros, giving the tokens
+ * for the Parser and the classes, operators and device functions for the Compiler. The
+ * directives are:
+ *
+ *   !class (name member:type...)  Define a class. A class whose name starts with _ is a template
+ *                                 whose :~ member is replaced by the members in (_name member...)
+ *                                 of a later class definition.
+ *   !op (name :type...):type      Define an operator with its argument and return types.
+ *   !dfn (name :type...)          Define a device function.
+ *   !def NAME value               Define a constant.
+ *   !def (NAME param...) value    Define a macro with parameters.
+ *   !undef NAME                   Remove a constant or macro.
+ *   !ifdef NAME, !ifundef NAME    Keep the source up to !else or !endif if NAME is defined, or
+ *                                 not defined.
+ *   !load path                    Process a file, relative to the directory of this file. A file
+ *                                 which was already loaded is skipped.
+ *
+ * Each token keeps its span in the file where it is written, and the tokens from a macro
+ * expansion also remember the span of the macro use. See get_expanded_text.
+ */
+#[derive(Default)]
+pub struct Preprocessor {
+    definition_segment_: DefinitionSegment,
+    templates_: HashMap<String, Vec<Expression>>,
+    macros_: HashMap<String, Macro>,
+    conditions_: Vec<Condition>,
+    // The files being loaded, for detecting a cycle of !load.
+    loading_: Vec<PathBuf>,
+    loaded_: HashSet<PathBuf>,
+    tokens_: Vec<Token>,
+    // For each token in tokens_, the span of the macro use it was expanded from.
+    expansions_: Vec<Option<Span>>,
+    sources_: Sources,
+}
+
+impl Preprocessor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /**
+     * Get the classes, operators and device functions which were defined so far.
+     */
+    pub fn get_definition_segment(&self) -> &DefinitionSegment {
+        &self.definition_segment_
+    }
+
+    /**
+     * Get the expanded tokens of the processed files without the directives.
+     */
+    pub fn get_tokens(&self) -> &[Token] {
+        &self.tokens_
+    }
+
+    /**
+     * Get the text of each processed file, for rendering a Diagnostic.
+     */
+    pub fn get_sources(&self) -> &Sources {
+        &self.sources_
+    }
+
+    /**
+     * If the primary span of the diagnostic is a token which was expanded from a macro, add the
+     * span of the macro use.
+     */
+    pub fn add_expansion_notes(&self, diagnostic: Diagnostic) -> Diagnostic {
+        let expanded_from = self.tokens_.iter().zip(self.expansions_.iter())
+            .find(|(token, _)| token.span_ == diagnostic.span_)
+            .and_then(|(_, expanded_from)| expanded_from.clone());
+        match expanded_from {
+            Some(span) => diagnostic.with_secondary(&span, "expanded from this macro use"),
+            None => diagnostic,
+        }
+    }
+
+    /**
+     * Return true if the constant or macro is defined.
+     */
+    pub fn is_defined(&self, name: &str) -> bool {
+        self.macros_.contains_key(name)
+    }
+
+    /**
+     * Process the file and add its expanded tokens.
+     * \param path The path of the file.
+     * \return Ok, or a ParseError if the file can't be read, is in a cycle of !load or has an
+     * error.
+     */
+    pub fn process_file(&mut self, path: &Path) -> Result<(), ParseError> {
+        let span = get_end_span(&path.display().to_string(), "");
+        self.load_file(path, &span)
+    }
+
+    /**
+     * Process the source text and add its expanded tokens.
+     * \param file The file name for the spans.
+     * \param text The source text.
+     * \param directory The directory for the relative paths of !load.
+     * \return Ok, or the first ParseError.
+     */
+    pub fn process_text(&mut self, file: &str, text: &str, directory: &Path)
+      -> Result<(), ParseError> {
+        self.sources_.add(file, text);
+        let tokens = Lexer::new(file, text).tokenize()?;
+        let condition_count = self.conditions_.len();
+        self.process_tokens(&tokens, directory)?;
+        if let Some(condition) = self.conditions_.get(condition_count) {
+            return Err(ParseError::new("The condition is not closed by !endif", &condition.span_));
+        }
+        Ok(())
+    }
+
+    /**
+     * Parse the objects of the expanded tokens.
+     */
+    pub fn parse_objects(&self) -> Result<Vec<SourceObject>, ParseError> {
+        let end_span = self.tokens_.last().map_or_else(|| get_end_span("", ""), |token| {
+            Span { start_: token.span_.end_, ..token.span_.clone() }
+        });
+        Parser::new(self.tokens_.clone(), end_span).parse_objects()
+    }
+
+    /**
+     * Return the expanded tokens as source text, with a SourceMap from the text back to the
+     * original source. Parsing the text gives the same objects as parse_objects.
+     */
+    pub fn get_expanded_text(&self) -> (String, SourceMap) {
+        let mut text = String::new();
+        let mut source_map = SourceMap::default();
+        for (token, expanded_from) in self.tokens_.iter().zip(self.expansions_.iter()) {
+            if !text.is_empty() {
+                if token.starts_line_ {
+                    text.push('\n');
+                }
+                else if !token.is_joined_ {
+                    text.push(' ');
+                }
+            }
+            let start = text.len();
+            text.push_str(&to_source(&token.kind_));
+            source_map.entries_.push(SourceMapEntry {
+                expanded_start_: start, expanded_end_: text.len(), span_: token.span_.clone(),
+                expanded_from_: expanded_from.clone() });
+        }
+        (text, source_map)
+    }
+
+    fn load_file(&mut self, path: &Path, span: &Span) -> Result<(), ParseError> {
+        let canonical_path = fs::canonicalize(path).map_err(|error| ParseError::new(
+            format!("Can't open {}: {}", path.display(), error), span))?;
+        if self.loading_.contains(&canonical_path) {
+            let cycle: Vec<String> = self.loading_.iter().chain(Some(&canonical_path))
+                .map(|path| path.display().to_string()).collect();
+            return Err(ParseError::new(
+                format!("The !load is in a cycle: {}", cycle.join(" -> ")), span));
+        }
+        if self.loaded_.contains(&canonical_path) {
+            return Ok(());
+        }
+        let text = fs::read_to_string(path).map_err(|error| ParseError::new(
+            format!("Can't read {}: {}", path.display(), error), span))?;
+
+        let directory = path.parent().map_or_else(PathBuf::new, Path::to_path_buf);
+        self.loading_.push(canonical_path.clone());
+        let result = self.process_text(&path.display().to_string(), &text, &directory);
+        self.loading_.pop();
+        self.loaded_.insert(canonical_path);
+        result
+    }
+
+    fn is_active(&self) -> bool {
+        self.conditions_.iter().all(|condition| condition.is_active_)
+    }
+
+    fn process_tokens(&mut self, tokens: &[Token], directory: &Path) -> Result<(), ParseError> {
+        let mut i = 0;
+        while i < tokens.len() {
+            if let TokenKind::Directive(name) = &tokens[i].kind_ {
+                i = self.process_directive(name, tokens, i, directory)?;
+                continue;
+            }
+            if !self.is_active() {
+                i += 1;
+                continue;
+            }
+
+            let end = tokens[i..].iter().position(|token| {
+                matches!(token.kind_, TokenKind::Directive(_))
+            }).map_or(tokens.len(), |position| i + position);
+            let mut output = vec![];
+            self.expand(&tokens[i..end], &mut vec![], &mut output)?;
+            for (token, expanded_from) in output {
+                self.tokens_.push(token);
+                self.expansions_.push(expanded_from);
+            }
+            i = end;
+        }
+        Ok(())
+    }
+
+    /**
+     * Expand the macros in the tokens, including the macros in an expansion.
+     * \param tokens The tokens.
+     * \param expanding The names of the macros being expanded, to detect a macro which expands
+     * to itself.
+     * \param output Add each expanded token and the span of the macro use it is from.
+     */
+    fn expand(&self, tokens: &[Token], expanding: &mut Vec<String>,
+      output: &mut Vec<(Token, Option<Span>)>) -> Result<(), ParseError> {
+        let mut i = 0;
+        while i < tokens.len() {
+            let token = &tokens[i];
+            let invocation = match (&token.kind_, tokens.get(i + 1).map(|next| &next.kind_)) {
+                (TokenKind::Symbol(name), _) => self.macros_.get(name)
+                    .filter(|m| m.parameters_.is_none()).map(|m| (name, m, i + 1)),
+                (TokenKind::LeftParen, Some(TokenKind::Symbol(name))) => {
+                    match self.macros_.get(name) {
+                        Some(m) if m.parameters_.is_some() =>
+                            Some((name, m, get_expression_end(tokens, i, &token.span_)?)),
+                        _ => None,
+                    }
+                },
+                _ => None,
+            };
+            let (name, m, end) = match invocation {
+                Some(invocation) => invocation,
+                None => {
+                    output.push((token.clone(), None));
+                    i += 1;
+                    continue;
+                },
+            };
+            if expanding.contains(name) {
+                return Err(ParseError::new(
+                    format!("The macro {} expands to itself", name), &token.span_));
+            }
+
+            let mut body = vec![];
+            match &m.parameters_ {
+                None => replace_token(&m.body_, token, &mut body),
+                Some(parameters) => {
+                    let arguments = split_expressions(&tokens[i + 2..end - 1], &token.span_)?;
+                    if arguments.len() != parameters.len() {
+                        return Err(ParseError::new(format!(
+                            "The macro {} has {} parameters, but {} arguments are given", name,
+                            parameters.len(), arguments.len()), &token.span_));
+                    }
+                    for body_token in &m.body_ {
+                        let argument = match &body_token.kind_ {
+                            TokenKind::Symbol(symbol) =>
+                                parameters.iter().position(|parameter| parameter == symbol),
+                            _ => None,
+                        };
+                        match argument {
+                            Some(argument) =>
+                                replace_token(arguments[argument], body_token, &mut body),
+                            None => body.push(body_token.clone()),
+                        }
+                    }
+                    if let Some(first) = body.first_mut() {
+                        first.is_joined_ = token.is_joined_;
+                        first.starts_line_ = token.starts_line_;
+                    }
+                },
+            }
+
+            let expanded_from = token.span_.to(&tokens[end - 1].span_);
+            let start = output.len();
+            expanding.push(name.clone());
+            self.expand(&body, expanding, output)?;
+            expanding.pop();
+            for (_, entry_expanded_from) in &mut output[start..] {
+                *entry_expanded_from = Some(expanded_from.clone());
+            }
+            i = end;
+        }
+        Ok(())
+    }
+
+    /**
+     * Process the directive at tokens[i].
+     * \return The index of the token after the directive and its arguments.
+     */
+    fn process_directive(&mut self, name: &str, tokens: &[Token], i: usize, directory: &Path)
+      -> Result<usize, ParseError> {
+        let directive = &tokens[i];
+        let get_name = |tokens: &[Token]| match tokens.get(i + 1).map(|token| &token.kind_) {
+            Some(TokenKind::Symbol(name)) => Ok(name.clone()),
+            _ => Err(ParseError::new(
+                format!("Expected a name after !{}", name), &directive.span_)),
+        };
+
+        match name {
+            "ifdef" | "ifundef" => {
+                let is_defined = self.is_defined(&get_name(tokens)?);
+                self.conditions_.push(Condition {
+                    is_active_: is_defined == (name == "ifdef"), has_else_: false,
+                    span_: directive.span_.clone() });
+                return Ok(i + 2);
+            },
+            "else" => {
+                match self.conditions_.last_mut() {
+                    Some(condition) if !condition.has_else_ => {
+                        condition.is_active_ = !condition.is_active_;
+                        condition.has_else_ = true;
+                    },
+                    _ => return Err(ParseError::new(
+                        "The !else is not after !ifdef or !ifundef", &directive.span_)),
+                }
+                return Ok(i + 1);
+            },
+            "endif" => {
+                if self.conditions_.pop().is_none() {
+                    return Err(ParseError::new(
+                        "The !endif is not after !ifdef or !ifundef", &directive.span_));
+                }
+                return Ok(i + 1);
+            },
+            _ => {},
+        }
+        if !self.is_active() {
+            return Ok(i + 1);
+        }
+
+        match name {
+            "class" | "op" | "dfn" => {
+                let end = get_expression_end(tokens, i + 1, &directive.span_)?;
+                let expression = Parser::new(tokens[i + 1..end].to_vec(), directive.span_.clone())
+                    .parse_expression()?;
+                if name == "class" {
+                    self.define_class(&expression)?;
+                    return Ok(end);
+                }
+                if name == "dfn" {
+                    self.define_function(&expression)?;
+                    return Ok(end);
+                }
+                let return_type = match tokens.get(end) {
+                    Some(Token { kind_: TokenKind::Type(return_type), is_joined_: true, .. }) =>
+                        Some(return_type.as_str()),
+                    _ => None,
+                };
+                self.define_operator(&expression, return_type)?;
+                Ok(if return_type.is_some() { end + 1 } else { end })
+            },
+            "def" => self.define_macro(tokens, i),
+            "undef" => {
+                self.macros_.remove(&get_name(tokens)?);
+                Ok(i + 2)
+            },
+            "load" => {
+                let path = match tokens.get(i + 1).map(|token| &token.kind_) {
+                    Some(TokenKind::Symbol(path)) | Some(TokenKind::String(path)) => path,
+                    _ => return Err(ParseError::new(
+                        "Expected a file path after !load", &directive.span_)),
+                };
+                let span = directive.span_.to(&tokens[i + 1].span_);
+                self.load_file(&directory.join(path), &span)?;
+                Ok(i + 2)
+            },
+            _ => Err(ParseError::new(format!("Unknown directive !{}", name), &directive.span_)),
+        }
+    }
+
+    /**
+     * Define a constant or a macro with parameters from the !def at tokens[i].
+     * \return The index of the token after the value.
+     */
+    fn define_macro(&mut self, tokens: &[Token], i: usize) -> Result<usize, ParseError> {
+        let directive = &tokens[i];
+        let name_end = get_expression_end(tokens, i + 1, &directive.span_)?;
+        let (name, parameters) = match &tokens[i + 1..name_end] {
+            [Token { kind_: TokenKind::Symbol(name), .. }] => (name.clone(), None),
+            [Token { kind_: TokenKind::LeftParen, .. },
+             Token { kind_: TokenKind::Symbol(name), .. }, parameters @ ..,
+             Token { kind_: TokenKind::RightParen, .. }] => {
+                let parameters = parameters.iter().map(|token| match &token.kind_ {
+                    TokenKind::Symbol(parameter) => Ok(parameter.clone()),
+                    _ => Err(ParseError::new("Expected a parameter name", &token.span_)),
+                }).collect::<Result<Vec<_>, _>>()?;
+                (name.clone(), Some(parameters))
+            },
+            _ => return Err(ParseError::new(
+                "Expected NAME or (NAME parameter...) after !def", &directive.span_)),
+        };
+        if self.macros_.contains_key(&name) {
+            return Err(ParseError::new(
+                format!("The macro {} is already defined", name), &tokens[i + 1].span_));
+        }
+
+        let end = get_expression_end(tokens, name_end, &directive.span_)?;
+        self.macros_.insert(name, Macro { parameters_: parameters,
+                                          body_: tokens[name_end..end].to_vec() });
+        Ok(end)
+    }
+
+    /**
+     * Replace each member like (_obj psln_thr:nb) which uses a class template by the members of
+     * the template, with its :~ member replaced by the members given to the template.
+     */
+    fn expand_templates(&self, members: &[Expression]) -> Result<Vec<Expression>, ParseError> {
+        let mut expanded = vec![];
+        for member in members {
+            let (name, head_span, arguments) = match &member.kind_ {
+                ExpressionKind::Structure { head_, head_span_, members_ }
+                  if head_.starts_with('_') && member.label_.is_none() =>
+                    (head_, head_span_, members_),
+                _ => {
+                    expanded.push(member.clone());
+                    continue;
+                },
+            };
+            let template = self.templates_.get(name).ok_or_else(|| ParseError::new(
+                format!("Unknown class template {}", name), head_span))?;
+            let mut has_arguments = false;
+            for template_member in template {
+                if template_member.kind_ == ExpressionKind::Type("~".to_string()) {
+                    expanded.extend(self.expand_templates(arguments)?);
+                    has_arguments = true;
+                }
+                else {
+                    expanded.push(template_member.clone());
+                }
+            }
+            if !has_arguments && !arguments.is_empty() {
+                return Err(ParseError::new(
+                    format!("The class template {} has no :~ for the members", name),
+                    &member.span_));
+            }
+        }
+        Ok(expanded)
+    }
+
+    /**
+     * Return the StructureMember of a member in a class or operator definition like psln_thr:nb,
+     * :nb, obj: which has any type, or mks:[] which is a set.
+     */
+    fn get_structure_member(&self, member: &Expression, class_name: &str)
+      -> Result<StructureMember, ParseError> {
+        let name = member.label_.clone().unwrap_or_default();
+        let type_name = match &member.kind_ {
+            ExpressionKind::Wildcard | ExpressionKind::TailWildcard => {
+                return Ok(StructureMember {
+                    read_id_: ReadId::Any, type_: ReturnType::Any, class_: String::new(),
+                    iteration_: Iteration::IExpression, name_: name });
+            },
+            ExpressionKind::Set(_) => "[]",
+            ExpressionKind::Symbol(type_name) | ExpressionKind::Type(type_name) => type_name,
+            _ => return Err(ParseError::new(
+                "Expected a member like name:type in the definition", &member.span_)),
+        };
+        // A class can have a member of its own class.
+        let member_type = if type_name == class_name {
+            Some((ReadId::Class, ReturnType::Class, Iteration::IExpression, type_name.to_string()))
+        }
+        else {
+            get_member_type(type_name, &self.definition_segment_)
+        };
+        let (read_id, return_type, iteration, class) = member_type.ok_or_else(|| {
+            ParseError::new(format!("Unknown member type {}", type_name), &member.span_)
+        })?;
+        Ok(StructureMember {
+            read_id_: read_id, type_: return_type, class_: class, iteration_: iteration,
+            name_: name })
+    }
+
+    /**
+     * Return the name and the StructureMembers of a definition like (name member...).
+     */
+    fn get_definition(&self, expression: &Expression, directive: &str)
+      -> Result<(String, Vec<StructureMember>), ParseError> {
+        let (name, members) = match &expression.kind_ {
+            ExpressionKind::Structure { head_, members_, .. } => (head_, members_),
+            _ => return Err(ParseError::new(
+                format!("Expected (name member...) after !{}", directive), &expression.span_)),
+        };
+        if self.definition_segment_.get_class(name).is_some() {
+            return Err(ParseError::new(
+                format!("{} is already defined", name), &expression.span_));
+        }
+        let members = self.expand_templates(members)?;
+        if members.len() > 0xFF {
+            return Err(ParseError::new(
+                format!("{} has {} members, more than 255", name, members.len()),
+                &expression.span_));
+        }
+        let members = members.iter().map(|member| self.get_structure_member(member, name))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok((name.clone(), members))
+    }
+
+    fn add_class(&mut self, class: Class) {
+        self.definition_segment_.classes_.push((class.str_opcode_.clone(), class));
+    }
+
+    /**
+     * Define a class from !class, or a class template if the name starts with _. As in the C++
+     * preprocessor, the head atom of the class is a marker if its name starts with mk., a group
+     * for grp, an instantiated program for ipgm, an instantiated C++ program for icpp_pgm, a
+     * composite state for cst, a model for mdl, and otherwise an object. A class with one of
+     * these system atoms is added to the sys classes, and an object class to the classes.
+     */
+    fn define_class(&mut self, expression: &Expression) -> Result<(), ParseError> {
+        if let ExpressionKind::Structure { head_, head_span_, members_ } = &expression.kind_ {
+            if head_.starts_with('_') {
+                if self.templates_.contains_key(head_) {
+                    return Err(ParseError::new(
+                        format!("The class template {} is already defined", head_), head_span_));
+                }
+                let members = self.expand_templates(members_)?;
+                self.templates_.insert(head_.clone(), members);
+                return Ok(());
+            }
+        }
+
+        let (name, members) = self.get_definition(expression, "class")?;
+        let opcode = self.definition_segment_.classes_by_opcodes_.len() as u16;
+        let arity = members.len() as u8;
+        let a = match name.as_str() {
+            _ if name.starts_with("mk.") => Atom::Marker(opcode, arity),
+            "grp" => Atom::Group(opcode, arity),
+            "ipgm" => Atom::InstantiatedProgram(opcode, arity),
+            "icpp_pgm" => Atom::InstantiatedCPPProgram(opcode, arity),
+            "cst" => Atom::CompositeState(opcode, arity),
+            "mdl" => Atom::Model(opcode, arity),
+            _ => Atom::Object(opcode, arity),
+        };
+        let class = Class {
+            atom_: a, str_opcode_: name.clone(), type_: ReturnType::Any,
+            use_as_: Iteration::IExpression, things_to_read_: members };
+        self.definition_segment_.classes_by_opcodes_.push(class.clone());
+        self.definition_segment_.class_names_.push(name.clone());
+        if a.getDescriptor() == atom::OBJECT {
+            self.add_class(class);
+        }
+        else {
+            self.definition_segment_.sys_classes_.push((name, class));
+        }
+        Ok(())
+    }
+
+    /**
+     * Define an operator from !op with the return type after the definition, or any type.
+     */
+    fn define_operator(&mut self, expression: &Expression, return_type: Option<&str>)
+      -> Result<(), ParseError> {
+        let (name, members) = self.get_definition(expression, "op")?;
+        let return_type = match return_type {
+            Some(type_name) => get_member_type(type_name, &self.definition_segment_)
+                .map(|(_, return_type, _, _)| return_type).ok_or_else(|| ParseError::new(
+                    format!("Unknown return type {}", type_name), &expression.span_))?,
+            None => ReturnType::Any,
+        };
+        let opcode = self.definition_segment_.operator_names_.len() as u16;
+        let class = Class {
+            atom_: Atom::Operator(opcode, members.len() as u8), str_opcode_: name.clone(),
+            type_: return_type, use_as_: Iteration::IExpression, things_to_read_: members };
+        self.definition_segment_.operator_names_.push(name);
+        self.add_class(class);
+        Ok(())
+    }
+
+    /**
+     * Define a device function from !dfn.
+     */
+    fn define_function(&mut self, expression: &Expression) -> Result<(), ParseError> {
+        let (name, members) = self.get_definition(expression, "dfn")?;
+        let opcode = self.definition_segment_.function_names_.len() as u16;
+        let class = Class {
+            atom_: Atom::DeviceFunction(opcode), str_opcode_: name.clone(),
+            type_: ReturnType::Any, use_as_: Iteration::IExpression, things_to_read_: members };
+        self.definition_segment_.function_names_.push(name);
+        self.add_class(class);
+        Ok(())
+    }
+}
+
+/**
+ * Preprocess, parse and compile the Replicode file, usually a program which does
+ * "!load ./std.replicode" for the standard classes.
+ * \param path The path of the file.
+ * \return The Image with the definitions and objects, or the Diagnostics with the Sources to
+ * render them.
+ */
+pub fn compile_file(path: &Path) -> Result<Image, (Vec<Diagnostic>, Sources)> {
+    let mut preprocessor = Preprocessor::new();
+    let objects = preprocessor.process_file(path)
+        .and_then(|_| preprocessor.parse_objects())
+        .map_err(|error| (vec![error.into()], preprocessor.get_sources().clone()))?;
+    Compiler::new(preprocessor.get_definition_segment()).compile_image(&objects)
+        .map_err(|diagnostics| {
+            (diagnostics.into_iter().map(|diagnostic| preprocessor.add_expansion_notes(diagnostic))
+             .collect(), preprocessor.get_sources().clone())
+        })
+}
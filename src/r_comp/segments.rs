@@ -0,0 +1,656 @@
+use std::rc::Rc;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::path::Path;
+use crate::core::UTimestamp;
+use crate::core::u_duration::microseconds;
+use crate::r_code;
+use crate::r_code::atom;
+use crate::r_code::{Code, ImageObject, LocalObject, SysObject};
+use crate::r_code::code;
+use crate::r_code::code::object_address;
+use crate::r_code::object_names::ObjectNames;
+use crate::r_code::image::{
+  checksum_bytes, get_string_size, get_words, read_string, write_string, ImageError,
+  LEGACY_FORMAT_VERSION};
+use super::class::Class;
+
+fn read_strings(data: &[u32], offset: &mut usize) -> Result<Vec<String>, ImageError> {
+    let count = get_words(data, *offset, 1, "string count")?[0];
+    *offset += 1;
+    let mut strings = vec![];
+    for _ in 0..count {
+        let (s, size) = read_string(data, *offset)?;
+        strings.push(s);
+        *offset += size;
+    }
+    Ok(strings)
+}
+
+fn write_strings(data: &mut [u32], offset: &mut usize, strings: &[String]) {
+    data[*offset] = strings.len() as u32;
+    *offset += 1;
+    for s in strings {
+        write_string(&mut data[*offset..], s);
+        *offset += get_string_size(s);
+    }
+}
+
+fn read_named_classes(
+  data: &[u32], offset: &mut usize) -> Result<Vec<(String, Class)>, ImageError> {
+    let count = get_words(data, *offset, 1, "class count")?[0];
+    *offset += 1;
+    let mut classes = vec![];
+    for _ in 0..count {
+        let (name, size) = read_string(data, *offset)?;
+        *offset += size;
+        let (class, size) = Class::read(&data[*offset..])
+            .map_err(|error| error.offset_by(*offset))?;
+        *offset += size;
+        classes.push((name, class));
+    }
+    Ok(classes)
+}
+
+fn write_named_classes(data: &mut [u32], offset: &mut usize, classes: &[(String, Class)]) {
+    data[*offset] = classes.len() as u32;
+    *offset += 1;
+    for (name, class) in classes {
+        write_string(&mut data[*offset..], name);
+        *offset += get_string_size(name);
+        class.write(&mut data[*offset..]);
+        *offset += class.get_size();
+    }
+}
+
+/**
+ * The definition segment holds the class and opcode tables which were used to compile the
+ * objects. The named class lists keep the order in which they were read so that writing them
+ * reproduces the same image.
+ */
+#[derive(Clone, Default)]
+pub struct DefinitionSegment {
+    // Classes indexed by opcode, including set classes.
+    pub classes_by_opcodes_: Vec<Class>,
+    // Non-sys classes, operators and device functions.
+    pub classes_: Vec<(String, Class)>,
+    pub sys_classes_: Vec<(String, Class)>,
+    // Classes and sys-classes, not including set classes.
+    pub class_names_: Vec<String>,
+    pub operator_names_: Vec<String>,
+    pub function_names_: Vec<String>,
+}
+
+impl DefinitionSegment {
+    /**
+     * Read the segment: classes_by_opcodes, classes, sys_classes, class_names, operator_names
+     * and function_names, each preceded by its count.
+     * \return The number of words read.
+     */
+    pub fn read(&mut self, data: &[u32]) -> Result<usize, ImageError> {
+        let count = get_words(data, 0, 1, "classes by opcodes count")?[0];
+        let mut offset = 1;
+        self.classes_by_opcodes_ = vec![];
+        for _ in 0..count {
+            let (class, size) = Class::read(&data[offset..])
+                .map_err(|error| error.offset_by(offset))?;
+            self.classes_by_opcodes_.push(class);
+            offset += size;
+        }
+
+        self.classes_ = read_named_classes(data, &mut offset)?;
+        self.sys_classes_ = read_named_classes(data, &mut offset)?;
+        self.class_names_ = read_strings(data, &mut offset)?;
+        self.operator_names_ = read_strings(data, &mut offset)?;
+        self.function_names_ = read_strings(data, &mut offset)?;
+        Ok(offset)
+    }
+
+    /**
+     * Write the segment in the format read by read.
+     * \param data The destination, which must have at least get_size() words.
+     */
+    pub fn write(&self, data: &mut [u32]) {
+        data[0] = self.classes_by_opcodes_.len() as u32;
+        let mut offset = 1;
+        for class in &self.classes_by_opcodes_ {
+            class.write(&mut data[offset..]);
+            offset += class.get_size();
+        }
+
+        write_named_classes(data, &mut offset, &self.classes_);
+        write_named_classes(data, &mut offset, &self.sys_classes_);
+        write_strings(data, &mut offset, &self.class_names_);
+        write_strings(data, &mut offset, &self.operator_names_);
+        write_strings(data, &mut offset, &self.function_names_);
+    }
+
+    pub fn get_size(&self) -> usize {
+        let named_classes_size = |classes: &[(String, Class)]| -> usize {
+            1 + classes.iter().map(|(name, class)| get_string_size(name) + class.get_size())
+                .sum::<usize>()
+        };
+        let strings_size = |strings: &[String]| -> usize {
+            1 + strings.iter().map(|s| get_string_size(s)).sum::<usize>()
+        };
+
+        1 + self.classes_by_opcodes_.iter().map(Class::get_size).sum::<usize>() +
+          named_classes_size(&self.classes_) + named_classes_size(&self.sys_classes_) +
+          strings_size(&self.class_names_) + strings_size(&self.operator_names_) +
+          strings_size(&self.function_names_)
+    }
+
+    /**
+     * Return the class with the name from classes_ or sys_classes_.
+     */
+    pub fn get_class(&self, name: &str) -> Option<&Class> {
+        self.classes_.iter().chain(self.sys_classes_.iter())
+            .find(|(class_name, _)| class_name == name).map(|(_, class)| class)
+    }
+
+    /**
+     * Return the opcode of the class with the name, which is its index in classes_by_opcodes_.
+     * (The opcode of an operator or device function is its index in operator_names_ or
+     * function_names_.)
+     */
+    pub fn get_opcode(&self, name: &str) -> Option<u16> {
+        self.classes_by_opcodes_.iter().position(|class| class.str_opcode_ == name)
+            .map(|opcode| opcode as u16)
+    }
+
+    /**
+     * Return the hash of the names of the classes, operators and device functions in order of
+     * opcode, which is stored in the image header. If two definition segments have the same
+     * hash, then their opcodes are very probably the same.
+     */
+    pub fn get_opcode_table_hash(&self) -> u32 {
+        // Each name is followed by 0, and each table by 0xFF which is not in UTF-8.
+        let tables = [
+            self.classes_by_opcodes_.iter().map(|class| class.str_opcode_.as_str()).collect(),
+            self.operator_names_.iter().map(String::as_str).collect(),
+            self.function_names_.iter().map(String::as_str).collect::<Vec<&str>>()];
+        checksum_bytes(tables.iter().flat_map(|names| {
+            names.iter().flat_map(|name| name.bytes().chain(Some(0))).chain(Some(0xFF))
+        }))
+    }
+
+    /**
+     * Return the map from opcode to class name, to pass to atom::set_opcode_names.
+     */
+    pub fn get_opcode_names(&self) -> HashMap<u16, String> {
+        self.classes_by_opcodes_.iter().enumerate()
+            .map(|(opcode, class)| (opcode as u16, class.str_opcode_.clone())).collect()
+    }
+}
+
+/**
+ * The object map holds the offset of each object in the image data, in words from the start of
+ * the definition segment.
+ */
+#[derive(Default)]
+pub struct ObjectMap {
+    pub objects_: Vec<u32>,
+}
+
+impl ObjectMap {
+    pub fn read(&mut self, data: &[u32], size: usize) -> Result<usize, ImageError> {
+        self.objects_ = get_words(data, 0, size, "object map")?.to_vec();
+        Ok(size)
+    }
+
+    /**
+     * Set the offset of each object in the code segment.
+     * \param code_segment The code segment.
+     * \param code_segment_offset The offset of the code segment in the image data.
+     */
+    pub fn build(&mut self, code_segment: &CodeSegment, code_segment_offset: u32) {
+        let mut offset = code_segment_offset;
+        self.objects_ = code_segment.objects_.iter().map(|object| {
+            let object_offset = offset;
+            offset += object.get_size() as u32;
+            object_offset
+        }).collect();
+    }
+
+    pub fn write(&self, data: &mut [u32]) {
+        data[..self.objects_.len()].copy_from_slice(&self.objects_);
+    }
+
+    pub fn get_size(&self) -> usize {
+        self.objects_.len()
+    }
+}
+
+#[derive(Default)]
+pub struct CodeSegment {
+    pub objects_: Vec<SysObject>,
+}
+
+impl CodeSegment {
+    /**
+     * Read object_count objects.
+     * \return The number of words read.
+     */
+    pub fn read(&mut self, data: &[u32], object_count: usize) -> Result<usize, ImageError> {
+        self.objects_ = vec![];
+        let mut offset = 0;
+        for _ in 0..object_count {
+            let mut object = SysObject::default();
+            offset += object.read(data.get(offset..).unwrap_or(&[]))
+                .map_err(|error| error.offset_by(offset))?;
+            self.objects_.push(object);
+        }
+        Ok(offset)
+    }
+
+    pub fn write(&self, data: &mut [u32]) {
+        let mut offset = 0;
+        for object in &self.objects_ {
+            object.write(&mut data[offset..]);
+            offset += object.get_size();
+        }
+    }
+
+    pub fn get_size(&self) -> usize {
+        self.objects_.iter().map(|object| object.get_size()).sum()
+    }
+}
+
+/**
+ * A PtrEntry is the location of an R_PTR atom which points to a given object: the index of the
+ * object which holds the atom, the index of its view which holds the atom (or NO_VIEW if the
+ * atom is in the object code) and the index of the atom.
+ */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PtrEntry {
+    pub object_: u32,
+    pub view_: u32,
+    pub pointer_: u32,
+}
+
+impl PtrEntry {
+    pub const NO_VIEW: u32 = 0xFFFFFFFF;
+}
+
+/**
+ * A RelocationEntry lists the pointers to one object.
+ */
+#[derive(Clone, Default)]
+pub struct RelocationEntry {
+    pub ptr_entries_: Vec<PtrEntry>,
+}
+
+/**
+ * The relocation segment has one RelocationEntry for each object in the image, in order, so that
+ * a loader can patch the pointers to an object when it is relocated.
+ */
+#[derive(Default)]
+pub struct RelocationSegment {
+    pub entries_: Vec<RelocationEntry>,
+}
+
+impl RelocationSegment {
+    /**
+     * Read the words: entry count, then for each entry the ptr entry count followed by the
+     * (object, view, pointer) triples.
+     * \return The number of words read.
+     */
+    pub fn read(&mut self, data: &[u32]) -> Result<usize, ImageError> {
+        let count = get_words(data, 0, 1, "relocation entry count")?[0];
+        let mut offset = 1;
+        self.entries_ = vec![];
+        for _ in 0..count {
+            let ptr_count = get_words(data, offset, 1, "relocation ptr entry count")?[0] as usize;
+            offset += 1;
+            let words = get_words(
+                data, offset, ptr_count.saturating_mul(3), "relocation ptr entries")?;
+            let ptr_entries = words.chunks(3).map(|triple| PtrEntry {
+                object_: triple[0], view_: triple[1], pointer_: triple[2] }).collect();
+            offset += words.len();
+            self.entries_.push(RelocationEntry { ptr_entries_: ptr_entries });
+        }
+        Ok(offset)
+    }
+
+    pub fn write(&self, data: &mut [u32]) {
+        data[0] = self.entries_.len() as u32;
+        let mut offset = 1;
+        for entry in &self.entries_ {
+            data[offset] = entry.ptr_entries_.len() as u32;
+            offset += 1;
+            for ptr_entry in &entry.ptr_entries_ {
+                data[offset] = ptr_entry.object_;
+                data[offset + 1] = ptr_entry.view_;
+                data[offset + 2] = ptr_entry.pointer_;
+                offset += 3;
+            }
+        }
+    }
+
+    pub fn get_size(&self) -> usize {
+        1 + self.entries_.iter().map(|entry| 1 + 3 * entry.ptr_entries_.len()).sum::<usize>()
+    }
+
+    /**
+     * Replace the entries with one entry for each object in the code segment, listing the R_PTR
+     * atoms in the code of the objects and their views which point to it.
+     */
+    pub fn build(&mut self, code_segment: &CodeSegment) {
+        self.entries_ = vec![RelocationEntry::default(); code_segment.objects_.len()];
+        for (object_index, object) in code_segment.objects_.iter().enumerate() {
+            for (pointer, a) in object.code_.iter().enumerate() {
+                add_reference_ptr_entry(
+                    self, *a, &object.references_, object_index as u32, PtrEntry::NO_VIEW,
+                    pointer as u32);
+            }
+            for (view_index, view) in object.views_.iter().enumerate() {
+                let view_references: Vec<u16> =
+                    view.references_.iter().map(|reference| *reference as u16).collect();
+                for (pointer, a) in view.code_.iter().enumerate() {
+                    add_reference_ptr_entry(
+                        self, *a, &view_references, object_index as u32, view_index as u32,
+                        pointer as u32);
+                }
+            }
+        }
+    }
+
+    /**
+     * Add a PtrEntry to the entry of the target object, adding entries as needed.
+     */
+    pub fn add_ptr_entry(&mut self, target_index: u32, ptr_entry: PtrEntry) {
+        if target_index as usize >= self.entries_.len() {
+            self.entries_.resize(target_index as usize + 1, RelocationEntry::default());
+        }
+        self.entries_[target_index as usize].ptr_entries_.push(ptr_entry);
+    }
+}
+
+/**
+ * Image holds the decoded segments of an r_code::Image.
+ */
+#[derive(Default)]
+pub struct Image {
+    pub definition_segment_: DefinitionSegment,
+    pub object_map_: ObjectMap,
+    pub code_segment_: CodeSegment,
+    pub relocation_segment_: RelocationSegment,
+    pub object_names_: ObjectNames,
+    // The time at which the image was compiled, as in the C++ image.
+    pub timestamp_: UTimestamp,
+}
+
+impl Image {
+    /**
+     * Decode the segments of the image and check that the object map agrees with the objects in
+     * the code segment. If the image has a header, check that its opcode table hash matches the
+     * definition segment.
+     * \param image The raw image.
+     * \return The decoded Image, or an ImageError if a segment is truncated or invalid.
+     */
+    pub fn load(image: &r_code::Image) -> Result<Self, ImageError> {
+        let mut result = Image {
+            timestamp_: UTimestamp::from_duration(microseconds(image.timestamp() as i64)),
+            ..Image::default() };
+        // Give each error the byte offset in the image file.
+        let at_segment = |segment: usize| {
+            let segment_offset = image.get_segment_offset(segment);
+            move |error: ImageError| error.offset_by(segment_offset)
+        };
+
+        let def_size = result.definition_segment_.read(image.get_def_segment())
+            .map_err(at_segment(0))?;
+        if def_size != image.def_size() as usize {
+            return Err(at_segment(0)(ImageError::Invalid(format!(
+                "The definition segment size is {} but its content has {} words",
+                image.def_size(), def_size))));
+        }
+
+        let object_count = image.get_object_count() as usize;
+        result.object_map_.read(image.get_map_segment(), object_count).map_err(at_segment(1))?;
+        let code_size = result.code_segment_.read(image.get_code_segment(), object_count)
+            .map_err(at_segment(2))?;
+        if code_size != image.code_size() as usize {
+            return Err(at_segment(2)(ImageError::Invalid(format!(
+                "The code segment size is {} but its objects have {} words",
+                image.code_size(), code_size))));
+        }
+
+        // Check each object's offset. We already know that the objects fill the code segment.
+        let mut offset = image.def_size() + image.map_size();
+        for (i, object) in result.code_segment_.objects_.iter().enumerate() {
+            if result.object_map_.objects_[i] != offset {
+                return Err(at_segment(1)(ImageError::Invalid(format!(
+                    "The object map has offset {} for object {} (OID {}), expected {}",
+                    result.object_map_.objects_[i], i, object.oid_, offset)).offset_by(i)));
+            }
+            offset += object.get_size() as u32;
+        }
+
+        result.relocation_segment_.read(image.get_reloc_segment()).map_err(at_segment(3))?;
+        if image.names_size() > 0 {
+            result.object_names_.read(image.get_names_segment()).map_err(at_segment(4))?;
+        }
+
+        if image.format_version() != LEGACY_FORMAT_VERSION &&
+           image.opcode_table_hash() != result.definition_segment_.get_opcode_table_hash() {
+            return Err(ImageError::Invalid(format!(
+                "The opcode table hash {:#010x} in the header doesn't match the definition segment",
+                image.opcode_table_hash())));
+        }
+        Ok(result)
+    }
+
+    /**
+     * Read and decode the image file.
+     */
+    pub fn read_file(path: impl AsRef<Path>) -> Result<Self, ImageError> {
+        Self::load(&r_code::Image::read_file(path)?)
+    }
+
+    pub fn get_object_count(&self) -> usize {
+        self.code_segment_.objects_.len()
+    }
+
+    /**
+     * Encode the segments into an r_code::Image. This rebuilds the object map from the objects in
+     * the code segment. Serializing an Image which was just loaded produces the same data. The
+     * r_code::Image has the current format with a header. To write it for the C++ version, call
+     * its set_format_version(LEGACY_FORMAT_VERSION).
+     */
+    pub fn serialize(&mut self) -> r_code::Image {
+        let def_size = self.definition_segment_.get_size();
+        let map_size = self.code_segment_.objects_.len();
+        let code_size = self.code_segment_.get_size();
+        let reloc_size = self.relocation_segment_.get_size();
+        // Leave out the optional names segment if there are no names.
+        let names_size =
+            if self.object_names_.is_empty() { 0 } else { self.object_names_.get_size() };
+        self.object_map_.build(&self.code_segment_, (def_size + map_size) as u32);
+
+        let mut image = r_code::Image::new(
+            def_size as u32, map_size as u32, code_size as u32, reloc_size as u32,
+            names_size as u32);
+        image.set_opcode_table_hash(self.definition_segment_.get_opcode_table_hash());
+        image.set_timestamp(self.timestamp_.time_since_epoch().as_microseconds() as u64);
+        let data = image.data_mut();
+        self.definition_segment_.write(data);
+        self.object_map_.write(&mut data[def_size..]);
+        self.code_segment_.write(&mut data[def_size + map_size..]);
+        self.relocation_segment_.write(&mut data[def_size + map_size + code_size..]);
+        if names_size > 0 {
+            self.object_names_.write(&mut data[def_size + map_size + code_size + reloc_size..]);
+        }
+        image
+    }
+
+    /**
+     * Append the objects to the code segment as SysObjects, followed by the objects that they
+     * reference (directly or through the host and origin of their views) which were not in the
+     * list. Each reference is replaced by the index of the referenced object in the image, and
+     * the relocation segment gets an entry for each R_PTR. Markers of an object which are not in
+     * the image are left out. Objects already in the code segment are not recognized, so add all
+     * the objects in one call. If an object's OID has a name in object_names, the name is added
+     * to object_names_.
+     * \param objects The objects to add, in the order that they should appear in the image.
+     * \param object_names The names of the objects, such as the labels of the source objects.
+     * \return An ImageError if an object can't be written, such as when a reference index is too
+     * large for an image with more than 65536 objects, or if the name of an object is already
+     * used by another OID in object_names_. The image is not changed in this case.
+     */
+    pub fn add_objects(
+      &mut self, objects: &[Rc<RefCell<dyn Code>>], object_names: &ObjectNames)
+      -> Result<(), ImageError> {
+        let first_index = self.code_segment_.objects_.len() as u32;
+        let mut indices: HashMap<usize, u32> = HashMap::new();
+        let mut ordered = vec![];
+        assign_indices(objects, first_index, &mut indices, &mut ordered);
+
+        // Every object that an object references is in indices.
+        let sys_objects = ordered.iter().map(|object| SysObject::from_code(
+          &*object.borrow(), |object| indices.get(&object_address(object)).copied()))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut names = self.object_names_.clone();
+        for sys_object in &sys_objects {
+            if let Some(name) = object_names.get_name(sys_object.oid_) {
+                if !names.insert(sys_object.oid_, name) {
+                    return Err(ImageError::Invalid(format!(
+                        "The name {} of OID {} is already used by OID {}", name, sys_object.oid_,
+                        names.get_oid(name).unwrap_or_default())));
+                }
+            }
+        }
+
+        for (i, sys_object) in sys_objects.into_iter().enumerate() {
+            let object_index = first_index + i as u32;
+            for (pointer, a) in sys_object.code_.iter().enumerate() {
+                add_reference_ptr_entry(
+                    &mut self.relocation_segment_, *a, &sys_object.references_, object_index,
+                    PtrEntry::NO_VIEW, pointer as u32);
+            }
+            for (view_index, view) in sys_object.views_.iter().enumerate() {
+                let view_references: Vec<u16> =
+                    view.references_.iter().map(|reference| *reference as u16).collect();
+                for (pointer, a) in view.code_.iter().enumerate() {
+                    add_reference_ptr_entry(
+                        &mut self.relocation_segment_, *a, &view_references, object_index,
+                        view_index as u32, pointer as u32);
+                }
+            }
+
+            self.code_segment_.objects_.push(sys_object);
+        }
+        self.object_names_ = names;
+
+        // Make sure that every object has a relocation entry, even if nothing points to it.
+        let object_count = self.code_segment_.objects_.len();
+        if self.relocation_segment_.entries_.len() < object_count {
+            self.relocation_segment_.entries_.resize(object_count, RelocationEntry::default());
+        }
+        Ok(())
+    }
+
+    /**
+     * Create one LocalObject for each object in the code segment with its code and OID, then
+     * link the objects by setting each reference to the object at the reference index. Views
+     * are rebuilt and added to their objects with their host and origin references resolved.
+     * References are set with code::set_reference, so the markers of each object are restored
+     * from the marker objects which reference it.
+     * \return The objects in image order, or ImageError::DanglingReference if a reference index
+     * is not an object in the image, or ImageError::Invalid if a view has more references than
+     * the host and origin.
+     */
+    pub fn get_objects(&self) -> Result<Vec<Rc<RefCell<dyn Code>>>, ImageError> {
+        let sys_objects = &self.code_segment_.objects_;
+        let objects: Vec<Rc<RefCell<dyn Code>>> = sys_objects.iter().map(|sys_object| {
+            let mut object = LocalObject::default();
+            object.load(sys_object);
+            Rc::new(RefCell::new(object)) as Rc<RefCell<dyn Code>>
+        }).collect();
+
+        let resolve = |object_index: usize, reference_index: u32| {
+            objects.get(reference_index as usize).ok_or(ImageError::DanglingReference {
+                object_index, oid: sys_objects[object_index].oid_, reference_index })
+        };
+
+        for (object_index, sys_object) in sys_objects.iter().enumerate() {
+            let object = &objects[object_index];
+            for (i, reference_index) in sys_object.references_.iter().enumerate() {
+                let reference = resolve(object_index, *reference_index as u32)?;
+                code::set_reference(object, i as u16, reference);
+            }
+
+            for (view_index, sys_view) in sys_object.views_.iter().enumerate() {
+                if sys_view.references_.len() > 2 {
+                    return Err(ImageError::Invalid(format!(
+                        "Object {} (OID {}) view {} has {} references, but a view only has a \
+                         host and an origin", object_index, sys_object.oid_, view_index,
+                        sys_view.references_.len())));
+                }
+                let mut view = object.borrow().build_view(sys_view);
+                for (i, reference_index) in sys_view.references_.iter().enumerate() {
+                    view.set_reference(i as u16, resolve(object_index, *reference_index)?);
+                }
+                object.borrow_mut().add_view(view);
+            }
+        }
+
+        Ok(objects)
+    }
+}
+
+/**
+ * Give each object the next index, in the order of objects, each followed depth first by the
+ * objects it references and the hosts and origins of its views which don't have an index yet.
+ * This uses a stack instead of recursion so that a long chain of references can't overflow the
+ * call stack.
+ * \param objects The objects to add to the image.
+ * \param first_index The index of the first object.
+ * \param indices Gets the index of each object by object_address.
+ * \param ordered Gets the objects in index order.
+ */
+fn assign_indices(
+  objects: &[Rc<RefCell<dyn Code>>], first_index: u32, indices: &mut HashMap<usize, u32>,
+  ordered: &mut Vec<Rc<RefCell<dyn Code>>>) {
+    // Push in reverse order so that the objects are popped in order.
+    let mut to_visit: Vec<Rc<RefCell<dyn Code>>> = objects.iter().rev().map(Rc::clone).collect();
+
+    while let Some(object) = to_visit.pop() {
+        let address = object_address(&object);
+        if indices.contains_key(&address) {
+            // Already visited.
+            continue;
+        }
+        indices.insert(address, first_index + ordered.len() as u32);
+
+        let mut referenced = vec![];
+        {
+            let object_ref = object.borrow();
+            for i in 0..object_ref.references_size() {
+                referenced.push(object_ref.get_reference(i));
+            }
+            for view in object_ref.views() {
+                referenced.extend((0..2).filter_map(|i| view.get_reference(i)));
+            }
+        }
+        to_visit.extend(referenced.into_iter().rev()
+            .filter(|reference| !indices.contains_key(&object_address(reference))));
+        ordered.push(object);
+    }
+}
+
+/**
+ * If the atom is an R_PTR, add a PtrEntry for it to the entry of the referenced object.
+ */
+fn add_reference_ptr_entry(
+  relocation_segment: &mut RelocationSegment, a: atom::Atom, references: &[u16],
+  object_index: u32, view_index: u32, pointer: u32) {
+    if a.getDescriptor() != atom::R_PTR {
+        return;
+    }
+    if let Some(target_index) = references.get(a.asIndex() as usize) {
+        relocation_segment.add_ptr_entry(*target_index as u32, PtrEntry {
+            object_: object_index, view_: view_index, pointer_: pointer });
+    }
+}
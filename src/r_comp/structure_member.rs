@@ -0,0 +1,120 @@
+use crate::r_code::image::{get_string_size, get_words, read_string, write_string, ImageError};
+use super::class::ReturnType;
+
+/**
+ * ReadId identifies the function that the compiler uses to read a member. (In the C++ version it
+ * is stored in place of a pointer to the read function.)
+ */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ReadId {
+    Any = 0,
+    Number = 1,
+    Timestamp = 2,
+    Duration = 3,
+    Boolean = 4,
+    String = 5,
+    Node = 6,
+    Device = 7,
+    Function = 8,
+    Expression = 9,
+    Set = 10,
+    Class = 11,
+}
+
+impl ReadId {
+    pub fn from_u32(value: u32) -> Option<Self> {
+        match value {
+            0 => Some(ReadId::Any),
+            1 => Some(ReadId::Number),
+            2 => Some(ReadId::Timestamp),
+            3 => Some(ReadId::Duration),
+            4 => Some(ReadId::Boolean),
+            5 => Some(ReadId::String),
+            6 => Some(ReadId::Node),
+            7 => Some(ReadId::Device),
+            8 => Some(ReadId::Function),
+            9 => Some(ReadId::Expression),
+            10 => Some(ReadId::Set),
+            11 => Some(ReadId::Class),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Iteration {
+    IExpression = 0,
+    ISet = 1,
+    // For sets in the form {:class1 :class2 ...}.
+    IDClass = 2,
+}
+
+impl Iteration {
+    pub fn from_u32(value: u32) -> Option<Self> {
+        match value {
+            0 => Some(Iteration::IExpression),
+            1 => Some(Iteration::ISet),
+            2 => Some(Iteration::IDClass),
+            _ => None,
+        }
+    }
+}
+
+/**
+ * A StructureMember describes one member of a class: how to read it, its type, its class if it
+ * is an expression or set of a given class, and its name.
+ */
+#[derive(Clone, Debug)]
+pub struct StructureMember {
+    pub read_id_: ReadId,
+    pub type_: ReturnType,
+    // The class name if the member is an expression or set of this class, otherwise empty.
+    pub class_: String,
+    pub iteration_: Iteration,
+    pub name_: String,
+}
+
+impl StructureMember {
+    /**
+     * Read the words: read ID, type, class, iteration, name. The strings are stored as in
+     * write_string.
+     * \return The StructureMember and the number of words read.
+     */
+    pub fn read(data: &[u32]) -> Result<(Self, usize), ImageError> {
+        let words = get_words(data, 0, 2, "structure member")?;
+        let read_id = ReadId::from_u32(words[0]).ok_or_else(|| ImageError::Invalid(
+            format!("Unknown structure member read ID {}", words[0])))?;
+        let type_ = ReturnType::from_u32(words[1]).ok_or_else(|| ImageError::Invalid(
+            format!("Unknown structure member type {}", words[1])).offset_by(1))?;
+        let mut offset = 2;
+
+        let (class, size) = read_string(data, offset)?;
+        offset += size;
+        let iteration_word = get_words(data, offset, 1, "structure member iteration")?[0];
+        let iteration = Iteration::from_u32(iteration_word).ok_or_else(|| ImageError::Invalid(
+            format!("Unknown structure member iteration {}", iteration_word)).offset_by(offset))?;
+        offset += 1;
+        let (name, size) = read_string(data, offset)?;
+        offset += size;
+
+        Ok((StructureMember { read_id_: read_id, type_, class_: class, iteration_: iteration,
+                              name_: name }, offset))
+    }
+
+    /**
+     * Write this StructureMember in the format read by read.
+     * \param data The destination, which must have at least get_size() words.
+     */
+    pub fn write(&self, data: &mut [u32]) {
+        data[0] = self.read_id_ as u32;
+        data[1] = self.type_ as u32;
+        write_string(&mut data[2..], &self.class_);
+        let offset = 2 + get_string_size(&self.class_);
+        data[offset] = self.iteration_ as u32;
+        write_string(&mut data[offset + 1..], &self.name_);
+    }
+
+    pub fn get_size(&self) -> usize {
+        3 + get_string_size(&self.class_) + get_string_size(&self.name_)
+    }
+}
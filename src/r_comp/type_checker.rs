@@ -0,0 +1,635 @@
+use std::collections::HashMap;
+use std::fmt;
+use crate::r_code::atom;
+use crate::r_code::atom::Atom;
+use crate::r_code::{Code, View};
+use super::ast::{Expression, ExpressionKind, SourceObject};
+use super::class::{Class, ReturnType};
+use super::compiler::get_view_class_name;
+use super::diagnostic::Diagnostic;
+use super::lexer::Span;
+use super::segments::{DefinitionSegment, Image};
+use super::structure_member::StructureMember;
+
+// The maximum depth of nested structures in compiled code, in case of an I_PTR cycle.
+const MAX_DEPTH: usize = 64;
+
+/**
+ * A ValueType is the type of a member or of a value. The type of an object, or of a member which
+ * is an object, is Class with the class name. Any matches every type.
+ */
+#[derive(Clone, Debug, PartialEq)]
+pub struct ValueType {
+    pub type_: ReturnType,
+    // The class name if type_ is Class and the class is known, otherwise empty.
+    pub class_: String,
+}
+
+impl ValueType {
+    pub fn new(return_type: ReturnType) -> Self {
+        ValueType { type_: return_type, class_: String::new() }
+    }
+
+    pub fn any() -> Self {
+        Self::new(ReturnType::Any)
+    }
+
+    pub fn of_class(class_name: &str) -> Self {
+        ValueType { type_: ReturnType::Class, class_: class_name.to_string() }
+    }
+
+    /**
+     * Return the declared type of the class member.
+     */
+    pub fn from_member(member: &StructureMember) -> Self {
+        ValueType { type_: member.type_, class_: member.class_.clone() }
+    }
+
+    pub fn is_any(&self) -> bool {
+        self.type_ == ReturnType::Any
+    }
+
+    /**
+     * Return true if a value of the given type can be a member of this type. A timestamp can be
+     * a member of type us, which older definitions use for timestamps. An object of unknown class
+     * can be a member of any class type.
+     */
+    pub fn accepts(&self, given: &ValueType) -> bool {
+        match (self.type_, given.type_) {
+            (ReturnType::Any, _) | (_, ReturnType::Any) => true,
+            (ReturnType::Duration, ReturnType::Timestamp) => true,
+            (ReturnType::Class, ReturnType::Class) =>
+                self.class_.is_empty() || given.class_.is_empty() || self.class_ == given.class_,
+            (expected, given) => expected == given,
+        }
+    }
+}
+
+impl fmt::Display for ValueType {
+    /**
+     * Write the type as it is written in a definition, like nb for :nb or the class name.
+     */
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.type_ {
+            ReturnType::Class if !self.class_.is_empty() => write!(f, "{}", self.class_),
+            _ => write!(f, "{}", self.type_.get_name()),
+        }
+    }
+}
+
+/**
+ * Return the type of an undefined value like |nb, or Any if it is not a known type.
+ */
+fn get_undefined_type(type_name: &str) -> ValueType {
+    ValueType::new(match type_name {
+        "nb" => ReturnType::Number,
+        "bl" => ReturnType::Boolean,
+        "st" => ReturnType::String,
+        "ts" => ReturnType::Timestamp,
+        "us" => ReturnType::Duration,
+        "nid" => ReturnType::NodeId,
+        "did" => ReturnType::DeviceId,
+        "fid" => ReturnType::FunctionId,
+        _ => ReturnType::Any,
+    })
+}
+
+/**
+ * Return "member after" for a named member, or "member 2" for an unnamed one like an operand.
+ */
+fn describe_member(definition: &StructureMember, position: usize) -> String {
+    if definition.name_.is_empty() { format!("member {}", position) }
+    else { format!("member {}", definition.name_) }
+}
+
+/**
+ * A MemberPlace is the class member where an expression is written, for checking the
+ * expression's type and for the messages.
+ */
+struct MemberPlace<'a> {
+    class_: &'a Class,
+    definition_: &'a StructureMember,
+    // The position of the member in the structure, starting from 1.
+    position_: usize,
+}
+
+impl<'a> MemberPlace<'a> {
+    fn get_expected(&self) -> ValueType {
+        ValueType::from_member(self.definition_)
+    }
+
+    /**
+     * Return an error that the value, which is described like "the value has type nb", doesn't
+     * have the type of this member.
+     */
+    fn get_mismatch(&self, value_description: &str, span: &Span) -> Diagnostic {
+        Diagnostic::error(format!(
+            "The {} of {} has type {}, but {}", describe_member(self.definition_, self.position_),
+            self.class_.str_opcode_, self.get_expected(), value_description), span)
+            .with_note(format!("{} is defined as {}", self.class_.str_opcode_,
+                               self.class_.get_definition()))
+    }
+}
+
+/**
+ * SourceChecker holds the types of the variables and labels of the source object being checked.
+ * A variable gets the type of the first member where it is written which has a type, and each
+ * other use must agree with it, so that a variable bound in a pattern has the same type in the
+ * guards and productions.
+ */
+struct SourceChecker<'a> {
+    checker_: &'a TypeChecker<'a>,
+    // The labels of the variables in the object.
+    variable_names_: Vec<String>,
+    // The type of each variable which has one, with the span where it got the type.
+    variables_: HashMap<String, (ValueType, Span)>,
+    // The type of each labeled expression.
+    labels_: HashMap<String, ValueType>,
+    diagnostics_: Vec<Diagnostic>,
+}
+
+impl<'a> SourceChecker<'a> {
+    fn new(checker: &'a TypeChecker<'a>) -> Self {
+        SourceChecker {
+            checker_: checker, variable_names_: vec![], variables_: HashMap::new(),
+            labels_: HashMap::new(), diagnostics_: vec![],
+        }
+    }
+
+    /**
+     * Check the members of the structure against the members of its class.
+     */
+    fn check_members(&mut self, class: &Class, members: &[Expression], span: &Span) {
+        if class.things_to_read_.len() != members.len() {
+            self.diagnostics_.push(Diagnostic::error(format!(
+                "The class {} has {} members, but {} are given", class.str_opcode_,
+                class.things_to_read_.len(), members.len()), span)
+                .with_note(format!("{} is defined as {}", class.str_opcode_,
+                                   class.get_definition())));
+            for member in members {
+                self.check_expression(member, None);
+            }
+            return;
+        }
+
+        for (i, (definition, member)) in class.things_to_read_.iter().zip(members).enumerate() {
+            let place = MemberPlace { class_: class, definition_: definition, position_: i + 1 };
+            let given = self.check_expression(member, Some(&place));
+            if !place.get_expected().accepts(&given) {
+                self.diagnostics_.push(
+                    place.get_mismatch(&format!("the value has type {}", given), &member.span_));
+            }
+        }
+    }
+
+    /**
+     * Check the expression and return its type. The caller checks the type against the member.
+     * \param expression The expression.
+     * \param place The class member where the expression is written, or None for a set element
+     * or an object.
+     * \return The type of the expression, or Any for a variable, which is checked here.
+     */
+    fn check_expression(&mut self, expression: &Expression, place: Option<&MemberPlace>)
+      -> ValueType {
+        if let (true, Some(label)) = (expression.is_variable(), &expression.label_) {
+            self.check_variable(label, place, &expression.span_);
+            return ValueType::any();
+        }
+
+        let definitions = self.checker_.definition_segment_;
+        let given = match &expression.kind_ {
+            ExpressionKind::Structure { head_, members_, .. } => {
+                match definitions.get_class(head_) {
+                    Some(class) => {
+                        self.check_members(class, members_, &expression.span_);
+                        match class.atom_.getDescriptor() {
+                            atom::OPERATOR => ValueType::new(class.type_),
+                            atom::DEVICE_FUNCTION => ValueType::any(),
+                            _ => ValueType::of_class(head_),
+                        }
+                    },
+                    None => {
+                        // The compiler reports the unknown class.
+                        for member in members_ {
+                            self.check_expression(member, None);
+                        }
+                        ValueType::any()
+                    },
+                }
+            },
+            ExpressionKind::Set(elements) => {
+                for element in elements {
+                    self.check_expression(element, None);
+                }
+                ValueType::new(ReturnType::Set)
+            },
+            ExpressionKind::Number(_) => ValueType::new(ReturnType::Number),
+            ExpressionKind::Boolean(_) => ValueType::new(ReturnType::Boolean),
+            ExpressionKind::String(_) => ValueType::new(ReturnType::String),
+            ExpressionKind::Timestamp(_) => ValueType::new(ReturnType::Timestamp),
+            ExpressionKind::Duration(_) => ValueType::new(ReturnType::Duration),
+            ExpressionKind::Undefined(type_name) => get_undefined_type(type_name),
+            ExpressionKind::Symbol(name) => {
+                if self.variable_names_.contains(name) {
+                    self.check_variable(name, place, &expression.span_);
+                    return ValueType::any();
+                }
+                match self.labels_.get(name) {
+                    Some(label_type) => label_type.clone(),
+                    None => self.checker_.get_name_type(name),
+                }
+            },
+            ExpressionKind::Nil | ExpressionKind::Wildcard | ExpressionKind::TailWildcard |
+            ExpressionKind::Type(_) => ValueType::any(),
+        };
+
+        if let Some(label) = &expression.label_ {
+            // A later use of the label has the type of the expression, or of the member.
+            let label_type = match place {
+                Some(place) if given.is_any() => place.get_expected(),
+                _ => given.clone(),
+            };
+            self.labels_.insert(label.clone(), label_type);
+        }
+        given
+    }
+
+    /**
+     * Give the variable the type of the member if it has no type yet, otherwise check that its
+     * type agrees with the member.
+     */
+    fn check_variable(&mut self, name: &str, place: Option<&MemberPlace>, span: &Span) {
+        let place = match place {
+            Some(place) if !place.get_expected().is_any() => place,
+            _ => return,
+        };
+        match self.variables_.get(name) {
+            Some((variable_type, first_span)) => {
+                if !place.get_expected().accepts(variable_type) {
+                    let diagnostic = place.get_mismatch(
+                        &format!("the variable {} has type {}", name, variable_type), span)
+                        .with_secondary(first_span, format!(
+                            "{} has type {} from this use", name, variable_type));
+                    self.diagnostics_.push(diagnostic);
+                }
+            },
+            None => {
+                self.variables_.insert(name.to_string(), (place.get_expected(), span.clone()));
+            },
+        }
+    }
+
+    /**
+     * Collect the labels of the variables in the expression, as the compiler does.
+     */
+    fn collect_variables(&mut self, expression: &Expression) {
+        if let (true, Some(label)) = (expression.is_variable(), &expression.label_) {
+            if !self.variable_names_.contains(label) {
+                self.variable_names_.push(label.clone());
+            }
+        }
+        for member in expression.get_members() {
+            self.collect_variables(member);
+        }
+    }
+}
+
+/**
+ * CodeChecker holds the compiled code being checked, with the types of its variables. This is
+ * like SourceChecker, but the types come from the atom descriptors and a problem is described
+ * with its code index.
+ */
+struct CodeChecker<'a> {
+    checker_: &'a TypeChecker<'a>,
+    code_: Vec<Atom>,
+    // The class name of each reference, if its head atom is a known class.
+    reference_classes_: Vec<Option<String>>,
+    // The type of each VL_PTR variable which has one, with the code index where it got the type.
+    variables_: HashMap<u16, (ValueType, usize)>,
+    location_: &'a str,
+    problems_: Vec<String>,
+}
+
+impl<'a> CodeChecker<'a> {
+    /**
+     * Return the type of the value at the index, following an I_PTR or CODE_VL_PTR.
+     */
+    fn get_value_type(&self, index: usize, depth: usize) -> ValueType {
+        let a = match self.code_.get(index) {
+            Some(a) => *a,
+            None => return ValueType::any(),
+        };
+        if a.isFloat() {
+            return ValueType::new(ReturnType::Number);
+        }
+        match a.getDescriptor() {
+            atom::BOOLEAN_ => ValueType::new(ReturnType::Boolean),
+            atom::NODE => ValueType::new(ReturnType::NodeId),
+            atom::DEVICE => ValueType::new(ReturnType::DeviceId),
+            atom::DEVICE_FUNCTION => ValueType::new(ReturnType::FunctionId),
+            atom::STRING => ValueType::new(ReturnType::String),
+            atom::TIMESTAMP => ValueType::new(ReturnType::Timestamp),
+            atom::DURATION => ValueType::new(ReturnType::Duration),
+            atom::SET | atom::S_SET | atom::MKS | atom::VWS => ValueType::new(ReturnType::Set),
+            atom::I_PTR | atom::CODE_VL_PTR if depth < MAX_DEPTH =>
+                self.get_value_type(a.asIndex() as usize, depth + 1),
+            atom::VL_PTR => self.variables_.get(&a.asIndex())
+                .map_or_else(ValueType::any, |(variable_type, _)| variable_type.clone()),
+            atom::R_PTR => match self.reference_classes_.get(a.asIndex() as usize) {
+                Some(Some(class_name)) => ValueType::of_class(class_name),
+                _ => ValueType::any(),
+            },
+            atom::OPERATOR => self.checker_.get_head_class(a)
+                .map_or_else(ValueType::any, |class| ValueType::new(class.type_)),
+            _ if a.isStructural() => self.checker_.get_head_class(a)
+                .map_or_else(ValueType::any, |class| ValueType::of_class(&class.str_opcode_)),
+            _ => ValueType::any(),
+        }
+    }
+
+    /**
+     * If the atom at the index is an I_PTR to a structure or set, check it.
+     */
+    fn check_pointer(&mut self, index: usize, depth: usize) {
+        let a = self.code_[index];
+        if a.getDescriptor() != atom::I_PTR {
+            return;
+        }
+        let target = a.asIndex() as usize;
+        match self.code_.get(target).map(Atom::getDescriptor) {
+            Some(atom::STRING) | Some(atom::TIMESTAMP) | Some(atom::DURATION) | Some(atom::C_PTR) |
+            None => {},
+            Some(descriptor) if descriptor > atom::C_PTR =>
+                self.check_structure(target, depth + 1),
+            _ => {},
+        }
+    }
+
+    /**
+     * Check the members of the structure at the index against the members of its class.
+     */
+    fn check_structure(&mut self, index: usize, depth: usize) {
+        if depth > MAX_DEPTH {
+            self.problems_.push(format!(
+                "{}: The structure at code({}) is nested too deeply", self.location_, index));
+            return;
+        }
+        let head = self.code_[index];
+        let arity = head.getAtomCount() as usize;
+        if index + arity >= self.code_.len() {
+            // validate reports the arity past the end of the code.
+            return;
+        }
+        let descriptor = head.getDescriptor();
+        let class = match self.checker_.get_head_class(head) {
+            // A structured set, like the code of a view, has the members of its class.
+            Some(class) if descriptor != atom::SET => class,
+            _ => {
+                for i in index + 1..=index + arity {
+                    self.check_pointer(i, depth);
+                }
+                return;
+            },
+        };
+        if class.things_to_read_.len() != arity {
+            self.problems_.push(format!(
+                "{}: The class {} at code({}) has {} members, but the arity is {}",
+                self.location_, class.str_opcode_, index, class.things_to_read_.len(), arity));
+            return;
+        }
+
+        for (position, definition) in class.things_to_read_.iter().enumerate() {
+            let member_index = index + 1 + position;
+            let expected = ValueType::from_member(definition);
+            let a = self.code_[member_index];
+            if a.getDescriptor() == atom::VL_PTR {
+                if !expected.is_any() {
+                    self.check_variable(a.asIndex(), class, definition, position + 1, member_index);
+                }
+                continue;
+            }
+            let given = self.get_value_type(member_index, depth);
+            if !expected.accepts(&given) {
+                self.problems_.push(format!(
+                    "{}: At code({}), the {} of {} has type {}, but the value has type {}",
+                    self.location_, member_index, describe_member(definition, position + 1),
+                    class.str_opcode_, expected, given));
+            }
+            self.check_pointer(member_index, depth);
+        }
+    }
+
+    /**
+     * Give the variable the type of the member if it has no type yet, otherwise check that its
+     * type agrees with the member.
+     */
+    fn check_variable(&mut self, variable: u16, class: &Class, definition: &StructureMember,
+                      position: usize, member_index: usize) {
+        let expected = ValueType::from_member(definition);
+        match self.variables_.get(&variable) {
+            Some((variable_type, first_index)) => {
+                if !expected.accepts(variable_type) {
+                    self.problems_.push(format!(
+                        "{}: At code({}), the {} of {} has type {}, but the variable v{} has type \
+                         {} from code({})", self.location_, member_index,
+                        describe_member(definition, position), class.str_opcode_, expected,
+                        variable, variable_type, first_index));
+                }
+            },
+            None => {
+                self.variables_.insert(variable, (expected, member_index));
+            },
+        }
+    }
+}
+
+/**
+ * TypeChecker checks that the members of objects have the types in the definitions of their
+ * classes and operators, like :nb, :ts, :us, :bl, :st, :did or a class name. It checks parsed
+ * source with spans, or compiled code by its atom descriptors. A variable takes the type of the
+ * first member with a type where it is written, and each other use of the variable must agree,
+ * as in a program whose guards and productions use the variables of its patterns.
+ */
+pub struct TypeChecker<'a> {
+    definition_segment_: &'a DefinitionSegment,
+    // The class name of each named object.
+    object_classes_: HashMap<String, String>,
+}
+
+impl<'a> TypeChecker<'a> {
+    /**
+     * Create a TypeChecker for the classes and operators in the definition segment.
+     */
+    pub fn new(definition_segment: &'a DefinitionSegment) -> Self {
+        TypeChecker { definition_segment_: definition_segment, object_classes_: HashMap::new() }
+    }
+
+    /**
+     * Set the class of a named object, so that a member which names it has the class type.
+     */
+    pub fn add_object_class(&mut self, name: &str, class_name: &str) {
+        self.object_classes_.insert(name.to_string(), class_name.to_string());
+    }
+
+    /**
+     * Return the class or operator of the head atom, or None if it is not in the definition
+     * segment.
+     */
+    fn get_head_class(&self, head: Atom) -> Option<&'a Class> {
+        let opcode = head.asOpcode() as usize;
+        let definitions = self.definition_segment_;
+        if head.getDescriptor() == atom::OPERATOR {
+            definitions.operator_names_.get(opcode).and_then(|name| definitions.get_class(name))
+        }
+        else {
+            definitions.classes_by_opcodes_.get(opcode)
+        }
+    }
+
+    /**
+     * Return the type of a name which is not a variable or label: a named object, a device
+     * function or a keyword.
+     */
+    fn get_name_type(&self, name: &str) -> ValueType {
+        if let Some(class_name) = self.object_classes_.get(name) {
+            return ValueType::of_class(class_name);
+        }
+        if self.definition_segment_.function_names_.iter().any(|function| function == name) {
+            return ValueType::new(ReturnType::FunctionId);
+        }
+        match name {
+            "now" => ValueType::new(ReturnType::Timestamp),
+            "mks" | "vws" => ValueType::new(ReturnType::Set),
+            _ => ValueType::any(),
+        }
+    }
+
+    /**
+     * Check the objects, which can name each other in any order.
+     * \param objects The parsed objects.
+     * \return A Diagnostic for each member whose type doesn't agree with its definition.
+     */
+    pub fn check_objects(&mut self, objects: &[SourceObject]) -> Vec<Diagnostic> {
+        for object in objects {
+            if let (Some(name), Some(head)) = (object.get_label(), object.expression_.get_head()) {
+                self.add_object_class(name, head);
+            }
+        }
+        objects.iter().flat_map(|object| self.check_object(object)).collect()
+    }
+
+    /**
+     * Check the object and its views. The classes of the objects it names must already be added
+     * with add_object_class.
+     * \param object The parsed object.
+     * \return A Diagnostic for each member whose type doesn't agree with its definition.
+     */
+    pub fn check_object(&self, object: &SourceObject) -> Vec<Diagnostic> {
+        let mut checker = SourceChecker::new(self);
+        checker.collect_variables(&object.expression_);
+        checker.check_expression(&object.expression_, None);
+
+        for view in &object.views_ {
+            let members = view.get_members();
+            let class = get_view_class_name(members.len())
+                .and_then(|class_name| self.definition_segment_.get_class(class_name));
+            // The compiler reports a view with the wrong number of members.
+            if let Some(class) = class {
+                let mut view_checker = SourceChecker::new(self);
+                view_checker.check_members(class, members, &view.span_);
+                checker.diagnostics_.append(&mut view_checker.diagnostics_);
+            }
+        }
+        checker.diagnostics_
+    }
+
+    /**
+     * Check the compiled code of the object and of its views.
+     * \param object The object.
+     * \param location The description of the object at the start of each problem, like
+     * "Object 3 (OID 12)".
+     * \return A description of each member whose type doesn't agree with its definition.
+     */
+    pub fn check_code(&self, object: &dyn Code, location: &str) -> Vec<String> {
+        let code: Vec<Atom> = (0..object.code_size()).map(|i| object.code(i)).collect();
+        let reference_classes = (0..object.references_size()).map(|i| {
+            let reference = object.get_reference(i);
+            let reference = reference.borrow();
+            self.get_reference_class(if reference.code_size() > 0 { Some(reference.code(0)) }
+                                     else { None })
+        }).collect();
+        let mut problems = self.check_atoms(code, reference_classes, location);
+
+        for (i, view) in object.views().iter().enumerate() {
+            problems.append(&mut self.check_view(view, &format!("{} view {}", location, i)));
+        }
+        problems
+    }
+
+    fn check_view(&self, view: &View, location: &str) -> Vec<String> {
+        let code: Vec<Atom> = (0..view.code_size()).map(|i| view.code(i)).collect();
+        let reference_classes = (0..2).map(|i| {
+            let head = view.get_reference(i).and_then(|reference| {
+                let reference = reference.borrow();
+                if reference.code_size() > 0 { Some(reference.code(0)) } else { None }
+            });
+            self.get_reference_class(head)
+        }).collect();
+        self.check_atoms(code, reference_classes, location)
+    }
+
+    /**
+     * Return the class name of a referenced object with the head atom, if it is known.
+     */
+    fn get_reference_class(&self, head: Option<Atom>) -> Option<String> {
+        head.and_then(|head| self.get_head_class(head)).map(|class| class.str_opcode_.clone())
+    }
+
+    fn check_atoms(&self, code: Vec<Atom>, reference_classes: Vec<Option<String>>,
+                   location: &str) -> Vec<String> {
+        if code.is_empty() || !code[0].isStructural() {
+            // validate reports code which is not a structure.
+            return vec![];
+        }
+        let mut checker = CodeChecker {
+            checker_: self, code_: code, reference_classes_: reference_classes,
+            variables_: HashMap::new(), location_: location, problems_: vec![],
+        };
+        checker.check_structure(0, 0);
+        checker.problems_
+    }
+}
+
+impl Image {
+    /**
+     * Check that the members of every object and view in the image have the types in the
+     * definitions of their classes and operators.
+     * \return A description of each problem found, or an empty Vec if the types agree.
+     */
+    pub fn check_types(&self) -> Vec<String> {
+        let type_checker = TypeChecker::new(&self.definition_segment_);
+        let objects = &self.code_segment_.objects_;
+        let get_head = |reference: usize| {
+            objects.get(reference).and_then(|object| object.code_.first().copied())
+        };
+        let mut problems = vec![];
+        for (i, object) in objects.iter().enumerate() {
+            let location = format!("Object {} (OID {})", i, object.oid_);
+            let reference_classes: Vec<Option<String>> = object.references_.iter()
+                .map(|reference| type_checker.get_reference_class(get_head(*reference as usize)))
+                .collect();
+            problems.append(&mut type_checker.check_atoms(
+                object.code_.clone(), reference_classes, &location));
+
+            for (j, view) in object.views_.iter().enumerate() {
+                // A view references the host and origin by the indexes in its own references.
+                let view_classes = view.references_.iter().map(|reference| {
+                    type_checker.get_reference_class(get_head(*reference as usize))
+                }).collect();
+                problems.append(&mut type_checker.check_atoms(
+                    view.code_.clone(), view_classes, &format!("{} view {}", location, j)));
+            }
+        }
+        problems
+    }
+}
@@ -0,0 +1,162 @@
+use crate::r_code::atom;
+use crate::r_code::atom::Atom;
+use crate::r_code::view::VIEW_HOST;
+use super::segments::{Image, PtrEntry};
+
+/**
+ * Check the pointer atoms in the code: I_PTR indexes must be in the code and R_PTR indexes must
+ * be in the references.
+ */
+fn validate_code(
+  code: &[Atom], references_size: usize, location: &str, problems: &mut Vec<String>) {
+    if code.is_empty() {
+        problems.push(format!("{}: The code is empty", location));
+        return;
+    }
+    if !code[0].isStructural() {
+        problems.push(format!("{}: code(0) is not a structural atom", location));
+    }
+    else if code[0].getAtomCount() as usize >= code.len() {
+        problems.push(format!(
+            "{}: The arity {} of code(0) is larger than the code size {}", location,
+            code[0].getAtomCount(), code.len()));
+    }
+
+    let mut data_to_skip = 0;
+    for (i, a) in code.iter().enumerate() {
+        if data_to_skip > 0 {
+            // Skip the data words of a timestamp, duration or string which can look like atoms.
+            data_to_skip -= 1;
+            continue;
+        }
+        match a.getDescriptor() {
+            atom::TIMESTAMP | atom::DURATION => data_to_skip = 2,
+            atom::STRING => data_to_skip = a.getAtomCount() as usize,
+            atom::I_PTR if a.asIndex() as usize >= code.len() => problems.push(format!(
+                "{}: The I_PTR at code({}) has index {} past the code size {}", location, i,
+                a.asIndex(), code.len())),
+            atom::R_PTR if a.asIndex() as usize >= references_size => problems.push(format!(
+                "{}: The R_PTR at code({}) has index {} past the references size {}", location,
+                i, a.asIndex(), references_size)),
+            _ => {}
+        }
+    }
+}
+
+impl Image {
+    /**
+     * Check the structure of every object, view and reference in the image, and the consistency
+     * of the relocation segment.
+     * \return A description of each problem found, or an empty Vec if the image is valid.
+     */
+    pub fn validate(&self) -> Vec<String> {
+        let mut problems = vec![];
+        let objects = &self.code_segment_.objects_;
+        let opcode_count = self.definition_segment_.classes_by_opcodes_.len();
+
+        for (i, object) in objects.iter().enumerate() {
+            let location = format!("Object {} (OID {})", i, object.oid_);
+            validate_code(&object.code_, object.references_.len(), &location, &mut problems);
+            if let Some(head) = object.code_.first() {
+                if head.isStructural() && head.asOpcode() as usize >= opcode_count &&
+                   opcode_count > 0 {
+                    problems.push(format!(
+                        "{}: The opcode {} is not in the class table", location,
+                        head.asOpcode()));
+                }
+            }
+
+            for reference in &object.references_ {
+                if *reference as usize >= objects.len() {
+                    problems.push(format!(
+                        "{}: The reference index {} is not an object in the image", location,
+                        reference));
+                }
+            }
+
+            for marker_index in &object.markers_ {
+                match objects.get(*marker_index as usize) {
+                    None => problems.push(format!(
+                        "{}: The marker index {} is not an object in the image", location,
+                        marker_index)),
+                    Some(marker) => {
+                        if marker.code_.first().map(|a| a.getDescriptor()) != Some(atom::MARKER) {
+                            problems.push(format!(
+                                "{}: The marker at index {} is not a MARKER object", location,
+                                marker_index));
+                        }
+                        if !marker.references_.iter().any(|r| *r as usize == i) {
+                            problems.push(format!(
+                                "{}: The marker at index {} does not reference this object",
+                                location, marker_index));
+                        }
+                    }
+                }
+            }
+
+            for (v, view) in object.views_.iter().enumerate() {
+                let view_location = format!("{} view {}", location, v);
+                validate_code(&view.code_, view.references_.len(), &view_location, &mut problems);
+                if view.code_.get(VIEW_HOST as usize).map(|a| a.getDescriptor()) !=
+                   Some(atom::R_PTR) {
+                    problems.push(format!("{}: The host is not an R_PTR", view_location));
+                }
+                if view.references_.len() > 2 {
+                    problems.push(format!(
+                        "{}: There are {} references, but a view only has a host and an origin",
+                        view_location, view.references_.len()));
+                }
+                for reference in &view.references_ {
+                    if *reference as usize >= objects.len() {
+                        problems.push(format!(
+                            "{}: The reference index {} is not an object in the image",
+                            view_location, reference));
+                    }
+                }
+            }
+        }
+
+        let entries = &self.relocation_segment_.entries_;
+        if !entries.is_empty() && entries.len() != objects.len() {
+            problems.push(format!(
+                "The relocation segment has {} entries but there are {} objects", entries.len(),
+                objects.len()));
+        }
+        for (target, entry) in entries.iter().enumerate() {
+            for ptr_entry in &entry.ptr_entries_ {
+                if !self.ptr_entry_points_to(ptr_entry, target) {
+                    problems.push(format!(
+                        "Relocation entry {}: The pointer ({}, {}, {}) is not an R_PTR to it",
+                        target, ptr_entry.object_, ptr_entry.view_ as i32, ptr_entry.pointer_));
+                }
+            }
+        }
+
+        problems
+    }
+
+    /**
+     * Return true if the atom at the ptr_entry is an R_PTR to the object at target.
+     */
+    fn ptr_entry_points_to(&self, ptr_entry: &PtrEntry, target: usize) -> bool {
+        let object = match self.code_segment_.objects_.get(ptr_entry.object_ as usize) {
+            Some(object) => object,
+            None => return false,
+        };
+        let (a, reference) = if ptr_entry.view_ == PtrEntry::NO_VIEW {
+            let a = object.code_.get(ptr_entry.pointer_ as usize);
+            (a, a.and_then(|a| object.references_.get(a.asIndex() as usize))
+                 .map(|r| *r as u32))
+        }
+        else {
+            let view = match object.views_.get(ptr_entry.view_ as usize) {
+                Some(view) => view,
+                None => return false,
+            };
+            let a = view.code_.get(ptr_entry.pointer_ as usize);
+            (a, a.and_then(|a| view.references_.get(a.asIndex() as usize)).copied())
+        };
+
+        a.map(|a| a.getDescriptor()) == Some(atom::R_PTR) && reference == Some(target as u32)
+    }
+}
@@ -0,0 +1,133 @@
+//! Run the aera-image tool on tests/fixtures/legacy.image, which has the objects self, stdin and
+//! robot (see tests/legacy_image.rs), and on the image of golden.replicode, and check the output
+//! and exit status of each command.
+
+use std::path::{Path, PathBuf};
+use std::process::{self, Command, Output};
+use aera::r_comp::{compile_file, Image};
+
+const TOOL: &str = env!("CARGO_BIN_EXE_aera-image");
+const LEGACY: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/legacy.image");
+const GOLDEN: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/golden.replicode");
+
+/**
+ * Return a path in the temporary directory which is unique to this test process.
+ */
+fn temp_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("aera-image-{}-{}", process::id(), name))
+}
+
+/**
+ * Compile golden.replicode and write its image to a temporary file.
+ * \return The path of the image file.
+ */
+fn write_golden_image(name: &str) -> PathBuf {
+    let path = temp_path(name);
+    compile_file(Path::new(GOLDEN)).ok().unwrap().serialize().write_file(&path).unwrap();
+    path
+}
+
+fn run(args: &[&str]) -> Output {
+    Command::new(TOOL).args(args).output().unwrap()
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8(output.stdout.clone()).unwrap()
+}
+
+fn stderr(output: &Output) -> String {
+    String::from_utf8(output.stderr.clone()).unwrap()
+}
+
+#[test]
+fn show_info() {
+    let output = run(&["info", LEGACY]);
+    assert!(output.status.success());
+    let out = stdout(&output);
+    assert!(out.starts_with("Format: legacy (no header)\nTimestamp: 1700000000000000us\n"), "{}",
+            out);
+    assert!(out.contains("Objects: 3\n  ent: 2\n  ont: 1\n"), "{}", out);
+    assert!(out.ends_with("Opcodes:\n  0\tent\n  1\tont\n"), "{}", out);
+}
+
+#[test]
+fn dump_objects() {
+    let output = run(&["dump", LEGACY, "--class", "ont"]);
+    assert!(output.status.success());
+    let out = stdout(&output);
+    assert_eq!(out.matches("--------").count(), 1);
+    assert!(out.contains("OID: 2"), "{}", out);
+
+    let output = run(&["dump", LEGACY, "--oid", "1"]);
+    assert!(output.status.success());
+    let out = stdout(&output);
+    assert_eq!(out.matches("--------").count(), 1);
+    assert!(out.contains("OID: 1"), "{}", out);
+
+    let output = run(&["dump", LEGACY, "--oid", "x"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(stderr(&output).starts_with("Invalid OID: x\nUsage: aera-image"));
+}
+
+#[test]
+fn validate_and_find_references() {
+    let output = run(&["validate", LEGACY]);
+    assert!(output.status.success());
+    assert_eq!(stdout(&output), "OK: 3 objects\n");
+
+    // In golden.replicode, f and the mdl reference hand.
+    let golden = write_golden_image("refs.image");
+    let output = run(&["refs", golden.to_str().unwrap(), "2"]);
+    assert!(output.status.success());
+    assert_eq!(stdout(&output),
+               "Objects referencing 2 (hand):\n  3 (f) fact reference 0\n  4 mdl reference 0\n");
+
+    let output = run(&["refs", LEGACY, "9"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(stderr(&output), "No object with OID 9\n");
+    std::fs::remove_file(golden).unwrap();
+}
+
+#[test]
+fn migrate_and_link() {
+    let golden = write_golden_image("golden.image");
+    let golden = golden.to_str().unwrap();
+    let migrated = temp_path("migrated.image");
+    let linked = temp_path("linked.image");
+
+    // self is in both images, so it is linked once.
+    let output = run(&["link", linked.to_str().unwrap(), LEGACY, golden]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(stdout(&output), format!("Linked 8 objects into {}\n", linked.display()));
+    let output = run(&["validate", linked.to_str().unwrap()]);
+    assert_eq!(stdout(&output), "OK: 8 objects\n");
+
+    // The legacy image doesn't have the classes of golden.replicode, so nothing is written.
+    let output = run(&["migrate", golden, LEGACY, migrated.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stderr(&output).contains("class fact used by OID 3, 4"), "{}", stderr(&output));
+    assert!(!migrated.exists());
+    // The linked image has the classes ent and ont first, so the other opcodes change.
+    let output = run(&["migrate", golden, linked.to_str().unwrap(), migrated.to_str().unwrap()]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let image = Image::read_file(&migrated).unwrap();
+    assert_eq!(image.definition_segment_.classes_by_opcodes_[1].str_opcode_, "ont");
+    let output = run(&["validate", migrated.to_str().unwrap()]);
+    assert_eq!(stdout(&output), "OK: 6 objects\n");
+
+    for path in &[Path::new(golden), &migrated, &linked] {
+        std::fs::remove_file(path).unwrap();
+    }
+}
+
+#[test]
+fn report_usage_errors() {
+    let output = run(&["bogus", LEGACY]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(stderr(&output).starts_with("Unknown command: bogus\nUsage: aera-image"));
+    let output = run(&["info"]);
+    assert_eq!(output.status.code(), Some(2));
+    let output = run(&["info", "missing.image"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stderr(&output).starts_with("missing.image: "));
+}
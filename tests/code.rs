@@ -0,0 +1,146 @@
+//! Check the module functions of r_code::code on LocalObjects: invalidation, which cascades to the
+//! markers and calls the invalidation observers once per object, and the modification of members
+//! of objects and views, which clamps the values and calls the modification observers.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+use aera::r_code::atom::{self, Atom};
+use aera::r_code::code;
+use aera::r_code::view::{VIEW_ACT, VIEW_IJT, VIEW_RES, VIEW_SLN};
+use aera::r_code::{Code, LocalObject, ObjectNames, SysView, View};
+
+const MK_VAL: u16 = 8;
+
+fn new_object(head: Atom, oid: u32) -> Rc<RefCell<dyn Code>> {
+    let object: Rc<RefCell<dyn Code>> = Rc::new(RefCell::new(LocalObject::default()));
+    object.borrow_mut().set_code(0, head);
+    object.borrow_mut().set_code(1, Atom::Float(0.5));
+    object.borrow_mut().set_oid(oid);
+    object
+}
+
+/**
+ * Return a marker of class mk.val which references the object.
+ */
+fn new_marker(object: &Rc<RefCell<dyn Code>>, oid: u32) -> Rc<RefCell<dyn Code>> {
+    let marker = new_object(Atom::Marker(MK_VAL, 2), oid);
+    marker.borrow_mut().set_code(1, Atom::RPointer(0));
+    marker.borrow_mut().set_code(2, Atom::Float(1.0));
+    code::set_reference(&marker, 0, object);
+    marker
+}
+
+fn new_view(group: &Rc<RefCell<dyn Code>>) -> View {
+    let mut view = View::from_sys_view(&SysView {
+        code_: vec![Atom::SSet(0, 6), Atom::Float(0.0), Atom::Nil(), Atom::Float(0.5),
+                    Atom::Float(1.0), Atom::RPointer(0), Atom::Nil()],
+        references_: vec![] });
+    view.set_reference(0, group);
+    view
+}
+
+/**
+ * Return a program view in the group, which has the act member.
+ */
+fn new_program_view(group: &Rc<RefCell<dyn Code>>) -> View {
+    let mut view = View::from_sys_view(&SysView {
+        code_: vec![Atom::SSet(11, 7), Atom::Float(0.0), Atom::Nil(), Atom::Float(0.5),
+                    Atom::Float(1.0), Atom::RPointer(0), Atom::Nil(), Atom::Float(0.5)],
+        references_: vec![] });
+    view.set_reference(0, group);
+    view
+}
+
+#[test]
+fn invalidate_with_markers() {
+    let invalidated = Rc::new(RefCell::new(vec![]));
+    let observed = Rc::clone(&invalidated);
+    code::add_invalidation_observer(Rc::new(move |object: &Rc<RefCell<dyn Code>>| {
+        observed.borrow_mut().push(object.borrow().get_oid());
+    }));
+
+    let group = new_object(Atom::Group(1, 1), 0);
+    let fact = new_object(Atom::Object(2, 1), 1);
+    let marker = new_marker(&fact, 2);
+    // A marker of the marker is invalidated through the cascade too.
+    let marker_of_marker = new_marker(&marker, 3);
+    fact.borrow_mut().add_view(new_view(&group));
+    assert_eq!(code::markers_of(&fact, MK_VAL).len(), 1);
+
+    assert!(!code::invalidate(&fact));
+    assert!(fact.borrow().is_invalidated());
+    assert!(marker.borrow().is_invalidated());
+    assert!(marker_of_marker.borrow().is_invalidated());
+    assert!(!group.borrow().is_invalidated());
+    assert!(fact.borrow().views().is_empty());
+    // The invalidated marker is removed from the markers of the fact.
+    assert!(code::markers_of(&fact, MK_VAL).is_empty());
+    // The markers are invalidated first, and each object is observed once.
+    assert_eq!(*invalidated.borrow(), vec![3, 2, 1]);
+
+    // Invalidating again does nothing.
+    assert!(code::invalidate(&fact));
+    assert!(code::invalidate(&marker));
+    assert_eq!(invalidated.borrow().len(), 3);
+    let opcode_names = vec![(2, "fact".to_string())].into_iter().collect::<HashMap<_, _>>();
+    atom::set_opcode_names(&opcode_names);
+    let mut out = String::new();
+    code::trace_out(&*fact.borrow(), &mut out, &ObjectNames::new());
+    assert!(out.trim_end().ends_with(" (invalidated)"), "{}", out);
+    code::clear_invalidation_observers();
+}
+
+#[test]
+fn modify_members() {
+    type Modification = (u32, Option<u32>, u16, f32);
+    let modifications: Rc<RefCell<Vec<Modification>>> = Rc::new(RefCell::new(vec![]));
+    let observed = Rc::clone(&modifications);
+    code::add_modification_observer(Rc::new(move |object: &Rc<RefCell<dyn Code>>,
+      group: Option<&Rc<RefCell<dyn Code>>>, member_index: u16, value: f32| {
+        observed.borrow_mut().push((
+          object.borrow().get_oid(), group.map(|group| group.borrow().get_oid()), member_index,
+          value));
+    }));
+
+    // Only the last member, the propagation of saliency threshold, can be modified.
+    let fact = new_object(Atom::Object(2, 2), 1);
+    fact.borrow_mut().set_code(2, Atom::Float(0.5));
+    assert_eq!(code::mod_member(&fact, 2, 0.25), Some(0.75));
+    assert_eq!(code::mod_member(&fact, 2, 0.5), Some(1.0));
+    assert_eq!(code::set_member(&fact, 2, -2.0), Some(0.0));
+    assert_eq!(fact.borrow().code(2).asFloat(), 0.0);
+    assert_eq!(code::mod_member(&fact, 1, 0.1), None);
+    assert_eq!(code::set_member(&fact, 3, 0.1), None);
+    assert_eq!(fact.borrow().code(1).asFloat(), 0.5);
+    // The last member must be a float.
+    let marker = new_object(Atom::Marker(MK_VAL, 1), 2);
+    marker.borrow_mut().set_code(1, Atom::Nil());
+    assert_eq!(code::set_member(&marker, 1, 0.5), None);
+
+    let group = new_object(Atom::Group(1, 1), 0);
+    let program_group = new_object(Atom::Group(1, 1), 3);
+    fact.borrow_mut().add_view(new_view(&group));
+    fact.borrow_mut().add_view(new_program_view(&program_group));
+    assert_eq!(code::mod_view_member(&fact, &group, VIEW_SLN, 0.75), Some(1.0));
+    assert_eq!(code::set_view_member(&fact, &group, VIEW_SLN, -0.5), Some(0.0));
+    // The resilience is not clamped to 1.
+    assert_eq!(code::mod_view_member(&fact, &group, VIEW_RES, 5.0), Some(6.0));
+    assert_eq!(code::set_view_member(&fact, &group, VIEW_RES, -1.0), Some(0.0));
+    assert_eq!(code::set_view_member(&fact, &group, VIEW_IJT, 1.0), None);
+    // Only a program or model view has the act member.
+    assert_eq!(code::set_view_member(&fact, &group, VIEW_ACT, 0.5), None);
+    assert_eq!(code::mod_view_member(&fact, &program_group, VIEW_ACT, 0.75), Some(1.0));
+    assert_eq!(code::set_view_member(&fact, &program_group, VIEW_ACT, 2.0), Some(1.0));
+    // The fact has no view in the group of the marker.
+    assert_eq!(code::set_view_member(&fact, &marker, VIEW_SLN, 0.5), None);
+    assert_eq!(fact.borrow().get_view(&group).unwrap().code(VIEW_SLN).asFloat(), 0.0);
+
+    // The observers are only called for the members which are changed.
+    assert_eq!(*modifications.borrow(), vec![
+      (1, None, 2, 0.75), (1, None, 2, 1.0), (1, None, 2, 0.0),
+      (1, Some(0), VIEW_SLN, 1.0), (1, Some(0), VIEW_SLN, 0.0),
+      (1, Some(0), VIEW_RES, 6.0), (1, Some(0), VIEW_RES, 0.0),
+      (1, Some(3), VIEW_ACT, 1.0), (1, Some(3), VIEW_ACT, 1.0)]);
+    code::clear_modification_observers();
+}
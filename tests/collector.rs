@@ -0,0 +1,83 @@
+//! Check that the Collector frees the objects which can't be reached from the roots, including
+//! objects in a reference cycle, and keeps the objects reachable through references and the
+//! hosts and origins of views.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+use aera::r_code::atom::Atom;
+use aera::r_code::code::object_address;
+use aera::r_code::{Code, Collector, LocalObject, SysView, View};
+
+fn new_object(oid: u32) -> Rc<RefCell<dyn Code>> {
+    let object: Rc<RefCell<dyn Code>> = Rc::new(RefCell::new(LocalObject::default()));
+    object.borrow_mut().set_code(0, Atom::Object(0, 1));
+    object.borrow_mut().set_code(1, Atom::Float(1.0));
+    object.borrow_mut().set_oid(oid);
+    object
+}
+
+fn add_reference(object: &Rc<RefCell<dyn Code>>, reference: &Rc<RefCell<dyn Code>>) {
+    let i = object.borrow().references_size();
+    object.borrow_mut().set_reference(i, reference);
+}
+
+#[test]
+fn collect_cycles() {
+    let mut collector = Collector::new();
+    let root = new_object(0);
+    let kept = new_object(1);
+    let a = new_object(2);
+    let b = new_object(3);
+    add_reference(&root, &kept);
+    // a and b reference each other, so their Rc counts never drop to zero by themselves.
+    add_reference(&a, &b);
+    add_reference(&b, &a);
+    for object in &[&root, &kept, &a, &b] {
+        collector.register(object);
+    }
+    let weak_a = Rc::downgrade(&a);
+    let weak_b = Rc::downgrade(&b);
+    drop((a, b));
+    assert!(weak_a.upgrade().is_some());
+    assert_eq!(collector.live_count(), 4);
+
+    let report = collector.collect(&[Rc::clone(&root)]);
+    assert_eq!(report.live_count_, 4);
+    assert_eq!(report.reachable_count_, 2);
+    let mut freed_oids = report.freed_oids_.clone();
+    freed_oids.sort_unstable();
+    assert_eq!(freed_oids, vec![2, 3]);
+    assert_eq!(report.freed_count(), 2);
+    assert!(weak_a.upgrade().is_none());
+    assert!(weak_b.upgrade().is_none());
+    assert_eq!(collector.live_count(), 2);
+    assert_eq!(kept.borrow().references_size(), 0);
+    assert_eq!(root.borrow().references_size(), 1);
+
+    // A second collection finds nothing more to free.
+    let report = collector.collect(&[root]);
+    assert_eq!(report.freed_count(), 0);
+    assert_eq!(report.reachable_count_, 2);
+}
+
+#[test]
+fn mark_through_views() {
+    let group = new_object(0);
+    let origin = new_object(1);
+    let fact = new_object(2);
+    let unreachable = new_object(3);
+    let mut view = View::from_sys_view(&SysView {
+        code_: vec![Atom::SSet(0, 6), Atom::Float(0.0), Atom::Nil(), Atom::Float(1.0),
+                    Atom::Float(1.0), Atom::RPointer(0), Atom::RPointer(1)],
+        references_: vec![] });
+    view.set_reference(0, &group);
+    view.set_reference(1, &origin);
+    fact.borrow_mut().add_view(view);
+
+    let reachable = Collector::mark(&[Rc::clone(&fact)]);
+    assert_eq!(reachable.len(), 3);
+    for object in &[&fact, &group, &origin] {
+        assert!(reachable.contains(&object_address(object)));
+    }
+    assert!(!reachable.contains(&object_address(&unreachable)));
+}
@@ -0,0 +1,63 @@
+//! Check that generic code doesn't change the code of a CompactObject, and that its references
+//! keep the size given by new or load.
+
+use std::rc::Rc;
+use std::cell::RefCell;
+use aera::r_code::atom::Atom;
+use aera::r_code::code;
+use aera::r_code::{Code, CompactObject, LocalObject, SysObject};
+
+fn new_local(code: &[Atom]) -> Rc<RefCell<dyn Code>> {
+    let object: Rc<RefCell<dyn Code>> = Rc::new(RefCell::new(LocalObject::default()));
+    for (i, a) in code.iter().enumerate() {
+        object.borrow_mut().set_code(i as u16, *a);
+    }
+    object
+}
+
+#[test]
+fn compact_code_is_not_modified() {
+    let code = vec![Atom::Object(0, 1), Atom::Float(0.5)];
+    let compact: Rc<RefCell<dyn Code>> =
+        Rc::new(RefCell::new(CompactObject::new(1, code.into(), vec![])));
+    assert_eq!(code::mod_member(&compact, 1, 0.25), None);
+    assert_eq!(code::set_member(&compact, 1, 0.25), None);
+    assert!(!compact.borrow_mut().try_set_code(1, Atom::Float(1.0)));
+    assert_eq!(compact.borrow().code(1).asFloat(), 0.5);
+
+    // The same members of a LocalObject are modified.
+    let local = new_local(&[Atom::Object(0, 1), Atom::Float(0.5)]);
+    assert_eq!(code::mod_member(&local, 1, 0.25), Some(0.75));
+    assert_eq!(code::set_member(&local, 1, 0.25), Some(0.25));
+    assert!(local.borrow_mut().try_set_code(1, Atom::Float(1.0)));
+    assert_eq!(local.borrow().code(1).asFloat(), 1.0);
+}
+
+#[test]
+fn compact_references_are_fixed() {
+    let a = new_local(&[]);
+    let b = new_local(&[]);
+    let mut compact = CompactObject::new(1, vec![Atom::Float(1.0)].into(), vec![Rc::clone(&a)]);
+    compact.set_reference(0, &b);
+    assert_eq!(compact.references_size(), 1);
+    assert!(code::same_object(&compact.get_reference(0), &b));
+
+    // load makes as many unresolved references as the source has.
+    let source = SysObject { oid_: 2, code_: vec![Atom::Float(1.0)], references_: vec![0, 0],
+                             ..SysObject::default() };
+    compact.load(&source);
+    assert_eq!(compact.get_oid(), 2);
+    assert_eq!(compact.references_size(), 2);
+    assert!(compact.try_get_reference(1).is_none());
+    compact.set_reference(1, &a);
+    assert!(code::same_object(&compact.try_get_reference(1).unwrap(), &a));
+    assert!(compact.try_get_reference(2).is_none());
+}
+
+#[test]
+#[should_panic(expected = "Can't set reference 1 of a compact object with 1 references")]
+fn compact_references_do_not_grow() {
+    let a = new_local(&[]);
+    let mut compact = CompactObject::new(1, vec![Atom::Float(1.0)].into(), vec![Rc::clone(&a)]);
+    compact.set_reference(1, &a);
+}
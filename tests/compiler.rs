@@ -0,0 +1,41 @@
+//! Check that the named objects and the next OID of a Compiler are kept across calls to compile,
+//! and restored when a call fails.
+
+use std::path::Path;
+use std::rc::Rc;
+use aera::r_comp::{parse, Compiler, Preprocessor};
+
+const FIXTURES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures");
+
+fn load_std() -> Preprocessor {
+    let mut preprocessor = Preprocessor::new();
+    preprocessor.process_text("test.replicode", "!load std.replicode\n", Path::new(FIXTURES))
+        .unwrap();
+    preprocessor
+}
+
+#[test]
+fn restore_state_on_error() {
+    let preprocessor = load_std();
+    let mut compiler = Compiler::new(preprocessor.get_definition_segment());
+    let first = parse("first.replicode", "self:(ent 1) |[]").unwrap();
+    let compiled = compiler.compile(&first).unwrap();
+    assert_eq!(compiled[0].borrow().get_oid(), 0);
+
+    // The second object references an unknown name, so hand and the OIDs 1 and 2 are released.
+    let failed = parse(
+      "failed.replicode", "hand:(ent 1) |[]\n(mk.val hnd self 1 1) |[]").unwrap();
+    let diagnostics = compiler.compile(&failed).err().unwrap();
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(diagnostics[0].message_, "Unknown name hnd");
+    assert!(compiler.get_named_object("hand").is_none());
+    assert!(compiler.get_named_object("self").is_some());
+
+    // hand can be compiled again and gets the next OID after self.
+    let second = parse(
+      "second.replicode", "hand:(ent 1) |[]\n(mk.val hand self 1 1) |[]").unwrap();
+    let compiled = compiler.compile(&second).unwrap();
+    assert_eq!(compiled[0].borrow().get_oid(), 1);
+    assert_eq!(compiled[1].borrow().get_oid(), 2);
+    assert!(Rc::ptr_eq(compiler.get_named_object("hand").unwrap(), &compiled[0]));
+}
@@ -0,0 +1,182 @@
+//! Compile tests/fixtures/golden.replicode and compare the code, references and views of every
+//! object in the image with the arrays which the C++ compiler writes for the same source.
+//!
+//! The arrays were derived by hand from the layout of the C++ compiler (there is no C++ build to
+//! run), atom by atom: the head atom with the arity, one atom per member, and a member which
+//! doesn't fit in one atom written after the end of the code so far, depth first, with an I_PTR to
+//! it. The head atom of a view is a structured set of its view class. The opcodes are those of
+//! tests/fixtures/std.replicode: ent 0, grp 1, fact 2, mdl 7, mk.val 8, cmd 9, view 10,
+//! pgm_view 11 and grp_view 12. The references are indexes of objects in the image.
+//!
+//! These arrays are not verified against the C++ compiler. They were not written by it, and
+//! std.replicode is a trimmed excerpt, so the opcodes differ from those of the full
+//! std.replicode. When a C++ build is available, the goldens should be regenerated from the C++
+//! compiler on the full std.replicode and its test programs.
+
+use std::path::Path;
+use aera::r_code::atom::Atom;
+use aera::r_comp::{compile_file, Image};
+
+const GOLDEN: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/golden.replicode");
+
+// Floats are the bits of the f32 shifted right by one.
+const F0: u32 = 0x00000000;
+const F05: u32 = 0x1F800000;
+const F1: u32 = 0x1FC00000;
+const F2: u32 = 0x20000000;
+const NIL: u32 = 0x80000000;
+const TIMESTAMP: u32 = 0xC7000000;
+
+/**
+ * The expected code and references of an object and of each of its views.
+ */
+struct Golden {
+    name_: &'static str,
+    code_: Vec<u32>,
+    references_: Vec<u16>,
+    views_: Vec<(Vec<u32>, Vec<u32>)>,
+}
+
+fn golden_objects() -> Vec<Golden> {
+    vec![
+        Golden {
+            name_: "root",
+            code_: vec![
+                0xC8000105, // (grp, opcode 1, 5 members
+                F1, F05, F0, F0, F1],
+            references_: vec![],
+            views_: vec![(vec![
+                0xC2000C08, // [grp_view, opcode 12, 8 members
+                F0,         // SYNC_ONCE
+                0x84000009, // now: I_PTR to 9
+                F1, F1,
+                0x85000000, // host root: R_PTR 0
+                NIL,        // org
+                0x81000000, // false
+                F1,
+                TIMESTAMP, 0, 0], vec![0])],
+        },
+        Golden {
+            name_: "self",
+            code_: vec![0xC3000001, F1],
+            references_: vec![],
+            views_: vec![(vec![
+                0xC2000A06, // [view, opcode 10, 6 members
+                F0, 0x84000007, F1, F1, 0x85000000, NIL,
+                TIMESTAMP, 0, 0], vec![0])],
+        },
+        Golden { name_: "hand", code_: vec![0xC3000001, F1], references_: vec![], views_: vec![] },
+        Golden {
+            name_: "f",
+            code_: vec![
+                0xC3000205, // (fact
+                0x84000006, // I_PTR to (mk.val at 6
+                0x8400000B, // I_PTR to 10s:0ms:0us at 11
+                0x8400000E, // I_PTR to 10s:100ms:0us at 14
+                F1, F1,
+                0xC4000804, // (mk.val, opcode 8, 4 members
+                0x85000000, // hand: R_PTR 0
+                F1, F05, F1,
+                TIMESTAMP, 0, 10_000_000,
+                TIMESTAMP, 0, 10_100_000],
+            references_: vec![2],
+            views_: vec![(vec![
+                0xC2000A06, F0,
+                0x84000007, // I_PTR to 0s:100ms:0us at 7
+                F1, F1,
+                0x85000000, // host root: R_PTR 0
+                0x85000001, // org self: R_PTR 1
+                TIMESTAMP, 0, 100_000], vec![0, 1])],
+        },
+        Golden {
+            name_: "",
+            code_: vec![
+                0xCE00070A, // (mdl, opcode 7, 10 members
+                0x8400000B, // I_PTR to the objs at 11
+                0x84000024, // I_PTR to [] at 36
+                0x84000025, // I_PTR to [] at 37
+                0x84000026, // I_PTR to [root] at 38
+                F1, F2, F1, F0, F1, F1,
+                0xC1000002, // 11: [ with 2 elements
+                0x8400000E, // I_PTR to lhs:(fact at 14
+                0x84000019, // I_PTR to (fact at 25
+                0xC3000205, // 14: (fact
+                0x84000014, // I_PTR to (mk.val at 20
+                0x86000001, // t0: VL_PTR 1
+                0x86000002, // t1: VL_PTR 2
+                F1, F1,
+                0xC4000804, // 20: (mk.val
+                0x85000000, // hand: R_PTR 0
+                F1,
+                0x86000000, // v0: VL_PTR 0
+                F1,
+                0xC3000205, // 25: (fact
+                0x8400001F, // I_PTR to (mk.val at 31
+                0x86000001, 0x86000002, F1, F1,
+                0xC4000804, // 31: (mk.val
+                0x85000000, F2,
+                0x8EFFF00E, // lhs: CODE_VL_PTR to 14
+                F1,
+                0xC1000000, // 36: []
+                0xC1000000, // 37: []
+                0xC1000001, // 38: [ with 1 element
+                0x85000001], // root: R_PTR 1
+            references_: vec![2, 0],
+            views_: vec![],
+        },
+        Golden {
+            name_: "",
+            code_: vec![
+                0xC3000903, // (cmd, opcode 9, 3 members
+                0xA2000000, // speak: device function 0
+                0x84000004, // I_PTR to ["hi"] at 4
+                F1,
+                0xC1000001, // [ with 1 element
+                0x84000006, // I_PTR to "hi" at 6
+                0xC6000102, // string of 2 characters in 1 atom
+                0x00006968], // "hi", the first character in the low byte
+            references_: vec![],
+            views_: vec![(vec![
+                0xC2000B07, // [pgm_view, opcode 11, 7 members
+                F0, 0x84000008, F1, F1, 0x85000000, NIL,
+                F1,         // act
+                TIMESTAMP, 0, 0], vec![0])],
+        },
+    ]
+}
+
+fn to_words(code: &[Atom]) -> Vec<u32> {
+    code.iter().map(|a| a.atom_).collect()
+}
+
+fn compile_golden() -> Image {
+    match compile_file(Path::new(GOLDEN)) {
+        Ok(image) => image,
+        Err((diagnostics, sources)) => panic!("{}", diagnostics.iter()
+            .map(|diagnostic| diagnostic.render(&sources, false)).collect::<Vec<_>>().join("\n")),
+    }
+}
+
+#[test]
+fn compile_golden_program() {
+    let image = compile_golden();
+    let objects = &image.code_segment_.objects_;
+    let golden = golden_objects();
+    assert_eq!(objects.len(), golden.len());
+    for (i, (object, expected)) in objects.iter().zip(golden.iter()).enumerate() {
+        assert_eq!(object.oid_, i as u32);
+        if !expected.name_.is_empty() {
+            assert_eq!(image.object_names_.get_name(object.oid_), Some(expected.name_));
+        }
+        assert_eq!(to_words(&object.code_), expected.code_, "code of object {}", i);
+        assert_eq!(object.references_, expected.references_, "references of object {}", i);
+        assert_eq!(object.views_.len(), expected.views_.len(), "views of object {}", i);
+        for (j, (view, (code, references))) in
+          object.views_.iter().zip(expected.views_.iter()).enumerate() {
+            assert_eq!(&to_words(&view.code_), code, "code of view {} of object {}", j, i);
+            assert_eq!(&view.references_, references,
+                       "references of view {} of object {}", j, i);
+        }
+    }
+    assert_eq!(image.validate(), Vec::<String>::new());
+}
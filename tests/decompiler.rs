@@ -0,0 +1,89 @@
+//! Check the source which the Decompiler writes for objects and views, and that compiling the
+//! decompiled source of an image gives the same objects.
+
+use std::path::Path;
+use aera::core::UTimestamp;
+use aera::r_code::atom::Atom;
+use aera::r_code::{SysObject, View};
+use aera::r_comp::segments::DefinitionSegment;
+use aera::r_comp::{compile_file, decompile_image, Compiler, Decompiler, Image, Preprocessor};
+
+const FIXTURES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures");
+const GOLDEN: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/golden.replicode");
+
+/**
+ * Compile the source, which uses the classes of tests/fixtures/std.replicode.
+ */
+fn compile_text(text: &str) -> Image {
+    let mut preprocessor = Preprocessor::new();
+    preprocessor.process_text(
+      "test.replicode", &format!("!load std.replicode\n{}", text), Path::new(FIXTURES)).unwrap();
+    let objects = preprocessor.parse_objects().unwrap();
+    match Compiler::new(preprocessor.get_definition_segment()).compile_image(&objects) {
+        Ok(image) => image,
+        Err(diagnostics) => panic!("{}\n{}", diagnostics[0].message_, text),
+    }
+}
+
+fn words(code: &[Atom]) -> Vec<u32> {
+    code.iter().map(|a| a.atom_).collect()
+}
+
+fn assert_same_objects(object: &SysObject, other: &SysObject) {
+    assert_eq!(object.oid_, other.oid_);
+    assert_eq!(words(&object.code_), words(&other.code_), "OID {}", object.oid_);
+    assert_eq!(object.references_, other.references_, "OID {}", object.oid_);
+    assert_eq!(object.markers_, other.markers_, "OID {}", object.oid_);
+    assert_eq!(object.views_.len(), other.views_.len(), "OID {}", object.oid_);
+    for (view, other_view) in object.views_.iter().zip(other.views_.iter()) {
+        assert_eq!(words(&view.code_), words(&other_view.code_), "OID {}", object.oid_);
+        assert_eq!(view.references_, other_view.references_, "OID {}", object.oid_);
+    }
+}
+
+/**
+ * Decompile the image, compile the source and check that the objects and names are the same, and
+ * that decompiling again gives the same source.
+ */
+fn assert_round_trip(image: &Image) {
+    let source = decompile_image(image, UTimestamp::default()).unwrap();
+    let compiled = compile_text(&source);
+    assert_eq!(compiled.get_object_count(), image.get_object_count(), "{}", source);
+    for (object, other) in image.code_segment_.objects_.iter()
+        .zip(compiled.code_segment_.objects_.iter()) {
+        assert_same_objects(object, other);
+    }
+    assert_eq!(compiled.object_names_.iter().collect::<Vec<_>>(),
+               image.object_names_.iter().collect::<Vec<_>>());
+    assert_eq!(decompile_image(&compiled, UTimestamp::default()).unwrap(), source);
+}
+
+#[test]
+fn decompile_invalid_view_reference() {
+    let definitions = DefinitionSegment::default();
+    let decompiler = Decompiler::new(&definitions, UTimestamp::default());
+    let mut view = View::default();
+    for (i, a) in [Atom::SSet(0, 6), Atom::Float(0.0), Atom::Nil(), Atom::Float(1.0),
+                   Atom::Float(1.0), Atom::RPointer(2), Atom::Nil()].iter().enumerate() {
+        view.set_code(i as u16, *a);
+    }
+    // A view only has the host and origin references, so R_PTR 2 is invalid.
+    assert_eq!(decompiler.decompile_view(&view), "[0 nil 1 1 <invalid reference 2> nil]");
+}
+
+#[test]
+fn round_trip_golden() {
+    assert_round_trip(&compile_file(Path::new(GOLDEN)).ok().unwrap());
+}
+
+#[test]
+fn round_trip_members() {
+    // Negative and fractional numbers, booleans, escaped and empty strings, durations, nested and
+    // empty sets, and a marker of a marker.
+    assert_round_trip(&compile_text("\
+e:(ent -0.25) |[]
+(cmd speak [\"say \\\"hi\\\"\" true 1.5e-3 [] [e [nil false]] 2s:500ms:0us] 1) |[]
+v:(mk.val e e -3 1) |[]
+(mk.val v e \"\" 0) |[]
+"));
+}
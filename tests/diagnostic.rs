@@ -0,0 +1,114 @@
+//! Check the text of Diagnostic::render for diagnostics of the compiler and for hand-made ones
+//! with a multi-line span, a secondary label and notes, and the names which find_similar_name
+//! suggests.
+
+use std::path::Path;
+use aera::r_comp::diagnostic::find_similar_name;
+use aera::r_comp::{parse, Compiler, Diagnostic, Preprocessor, Sources};
+
+const FIXTURES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures");
+
+/**
+ * Compile the program after loading std.replicode and return the rendered diagnostics.
+ */
+fn render_compile_errors(program: &str) -> Vec<String> {
+    let mut preprocessor = Preprocessor::new();
+    preprocessor.process_text("test.replicode", &format!("!load std.replicode\n{}", program),
+                              Path::new(FIXTURES)).unwrap();
+    let objects = preprocessor.parse_objects().unwrap();
+    let diagnostics = Compiler::new(preprocessor.get_definition_segment()).compile(&objects)
+        .err().unwrap();
+    diagnostics.iter().map(|diagnostic| diagnostic.render(preprocessor.get_sources(), false))
+        .collect()
+}
+
+#[test]
+fn render_did_you_mean() {
+    assert_eq!(render_compile_errors("self:(ent 1) |[]\n(mk.vall self 1 1 1) |[]"), vec![
+        "error: Unknown class mk.vall\n".to_string() +
+        " --> test.replicode:3:2\n" +
+        "  |\n" +
+        "3 | (mk.vall self 1 1 1) |[]\n" +
+        "  |  ^^^^^^^\n" +
+        "  = help: did you mean `mk.val`?\n"]);
+
+    assert_eq!(render_compile_errors("hand:(ent 1) |[]\n(mk.val hnd 1 1 1) |[]"), vec![
+        "error: Unknown name hnd\n".to_string() +
+        " --> test.replicode:3:9\n" +
+        "  |\n" +
+        "3 | (mk.val hnd 1 1 1) |[]\n" +
+        "  |         ^^^\n" +
+        "  = help: did you mean `hand`?\n"]);
+}
+
+#[test]
+fn render_multi_line_span() {
+    let text = "(ent 1) |[]\n; 2\n; 3\n; 4\n; 5\n; 6\n; 7\n; 8\n; 9\n\
+                (fact (mk.val self 1 1 1)\n      0s:0ms:0us 1s:0ms:0us 1 1) |[]\n";
+    let objects = parse("test.replicode", text).unwrap();
+    let mut sources = Sources::new();
+    sources.add("test.replicode", text);
+    let diagnostic = Diagnostic::error("The fact is wrong", &objects[1].expression_.span_)
+        .with_secondary(&objects[0].expression_.span_, "first defined here")
+        .with_note("a note")
+        .with_suggestion("a suggestion");
+    // A multi-line span is underlined to the end of its first line. The gutter is as wide as the
+    // widest line number.
+    assert_eq!(diagnostic.render(&sources, false),
+        "error: The fact is wrong\n".to_string() +
+        "  --> test.replicode:10:1\n" +
+        "   |\n" +
+        "10 | (fact (mk.val self 1 1 1)\n" +
+        "   | ^^^^^^^^^^^^^^^^^^^^^^^^^\n" +
+        "  ::: test.replicode:1:1\n" +
+        "   |\n" +
+        " 1 | (ent 1) |[]\n" +
+        "   | ------- first defined here\n" +
+        "   = note: a note\n" +
+        "   = help: a suggestion\n");
+
+    // A span whose file is not in the sources has no snippet.
+    assert_eq!(diagnostic.render(&Sources::new(), false),
+        "error: The fact is wrong\n".to_string() +
+        "  --> test.replicode:10:1\n" +
+        "  ::: test.replicode:1:1\n" +
+        "   = note: a note\n" +
+        "   = help: a suggestion\n");
+}
+
+#[test]
+fn render_colored() {
+    let text = "(ent 1) |[]";
+    let objects = parse("test.replicode", text).unwrap();
+    let mut sources = Sources::new();
+    sources.add("test.replicode", text);
+    let diagnostic = Diagnostic::warning("Unused", &objects[0].expression_.span_);
+    assert_eq!(diagnostic.render(&sources, true),
+        "\x1b[1;33mwarning\x1b[0m: \x1b[1mUnused\x1b[0m\n".to_string() +
+        " \x1b[1;34m-->\x1b[0m test.replicode:1:1\n" +
+        "  \x1b[1;34m|\x1b[0m\n" +
+        "\x1b[1;34m1\x1b[0m \x1b[1;34m|\x1b[0m (ent 1) |[]\n" +
+        "  \x1b[1;34m|\x1b[0m \x1b[1;33m^^^^^^^\x1b[0m\n");
+}
+
+#[test]
+fn similar_names() {
+    let classes = ["ent", "grp", "mk.val", "pgm_view", "grp_view"];
+    assert_eq!(find_similar_name("mk.vall", classes.iter().copied()), Some("mk.val"));
+    assert_eq!(find_similar_name("pgm_vew", classes.iter().copied()), Some("pgm_view"));
+    assert_eq!(find_similar_name("gpr", classes.iter().copied()), None);
+    // The name itself is not suggested.
+    assert_eq!(find_similar_name("ent", classes.iter().copied()), None);
+    assert_eq!(find_similar_name("ent", vec![]), None);
+
+    // A name can differ by one edit for every three characters, and at least one.
+    assert_eq!(find_similar_name("ab", vec!["ac"]), Some("ac"));
+    assert_eq!(find_similar_name("ab", vec!["cd"]), None);
+    assert_eq!(find_similar_name("abcdef", vec!["abcdxy"]), Some("abcdxy"));
+    assert_eq!(find_similar_name("abcdef", vec!["abcxyz"]), None);
+    // The nearest candidate wins, and the first one of equally near candidates.
+    assert_eq!(find_similar_name("abcdef", vec!["abcdxy", "abcdex"]), Some("abcdex"));
+    assert_eq!(find_similar_name("speed", vec!["sped", "spee"]), Some("sped"));
+    // The edits count characters, not bytes.
+    assert_eq!(find_similar_name("café", vec!["cafe"]), Some("cafe"));
+}
@@ -0,0 +1,225 @@
+//! Mutate valid images and check that reading them returns an error or an image, but never
+//! panics or allocates memory for a corrupt size. The mutations are generated from fixed seeds,
+//! so a failure can be reproduced from the seed in its message.
+
+use std::cell::RefCell;
+use std::panic;
+use std::rc::Rc;
+use aera::r_code::atom::Atom;
+use aera::r_code::image::{ImageError, IMAGE_MAGIC, LEGACY_FORMAT_VERSION};
+use aera::r_code::{code, Code, LocalObject, MappedImage, ObjectNames, SysView, View};
+use aera::r_comp::class::ReturnType;
+use aera::r_comp::structure_member::{Iteration, ReadId, StructureMember};
+use aera::r_comp::{Class, Image};
+
+const MUTANTS_PER_IMAGE: u64 = 2000;
+
+/**
+ * A xorshift generator, so that the corpus is the same on each run.
+ */
+struct Random(u64);
+
+impl Random {
+    fn new(seed: u64) -> Self {
+        Random(seed.wrapping_mul(0x9E3779B97F4A7C15) | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n.max(1) as u64) as usize
+    }
+}
+
+fn class(name: &str, a: Atom) -> Class {
+    Class {
+        atom_: a, str_opcode_: name.to_string(), type_: ReturnType::Any,
+        use_as_: Iteration::IExpression,
+        things_to_read_: vec![StructureMember {
+            read_id_: ReadId::Number, type_: ReturnType::Number, class_: String::new(),
+            iteration_: Iteration::IExpression, name_: "val".to_string() }],
+    }
+}
+
+fn new_object(head: Atom, oid: u32) -> Rc<RefCell<dyn Code>> {
+    let object: Rc<RefCell<dyn Code>> = Rc::new(RefCell::new(LocalObject::default()));
+    object.borrow_mut().set_code(0, head);
+    object.borrow_mut().set_oid(oid);
+    object
+}
+
+/**
+ * Return the words of a valid image with a group, a fact which references it and has a view in
+ * it, and a marker, in the given format version.
+ */
+fn valid_image(format_version: u32) -> Vec<u32> {
+    let mut image = Image::default();
+    for (opcode, name) in ["fact", "grp", "view", "mk"].iter().enumerate() {
+        let a = if *name == "grp" { Atom::Group(opcode as u16, 0) }
+                else { Atom::Object(opcode as u16, 1) };
+        image.definition_segment_.classes_by_opcodes_.push(class(name, a));
+        image.definition_segment_.classes_.push((name.to_string(), class(name, a)));
+        image.definition_segment_.class_names_.push(name.to_string());
+    }
+    image.definition_segment_.operator_names_.push("add".to_string());
+
+    let group = new_object(Atom::Group(1, 0), 7);
+    let fact = new_object(Atom::Object(0, 1), 3);
+    fact.borrow_mut().set_code(1, Atom::RPointer(0));
+    code::set_reference(&fact, 0, &group);
+    let marker = new_object(Atom::Marker(3, 1), 4);
+    marker.borrow_mut().set_code(1, Atom::RPointer(0));
+    code::set_reference(&marker, 0, &fact);
+    let sys_view = SysView {
+        code_: vec![
+            Atom::Object(2, 5), Atom::IPointer(6), Atom::Float(1.0), Atom::Float(1.0),
+            Atom::RPointer(0), Atom::Nil(), Atom::Timestamp(), Atom::new(0), Atom::new(5)],
+        references_: vec![] };
+    let mut view = View::from_sys_view(&sys_view);
+    view.set_reference(0, &group);
+    fact.borrow_mut().add_view(view);
+
+    let mut names = ObjectNames::new();
+    names.insert(7, "stdin");
+    image.add_objects(&[fact, marker], &names).unwrap();
+
+    let mut raw_image = image.serialize();
+    raw_image.set_format_version(format_version);
+    to_words(&raw_image)
+}
+
+fn to_words(image: &aera::r_code::Image) -> Vec<u32> {
+    let mut bytes = vec![];
+    image.write(&mut bytes).unwrap();
+    bytes.chunks(4).map(|word| u32::from_le_bytes([word[0], word[1], word[2], word[3]])).collect()
+}
+
+fn to_bytes(words: &[u32]) -> Vec<u8> {
+    words.iter().flat_map(|word| word.to_le_bytes()).collect()
+}
+
+/**
+ * Change the words by one random mutation. The result is in bytes so that it can also be cut
+ * in the middle of a word.
+ */
+fn mutate(words: &[u32], random: &mut Random) -> Vec<u8> {
+    const INTERESTING: [u32; 8] =
+      [0, 1, 0xFFFFFFFF, 0x7FFFFFFF, 0x80000000, 0x10000, 0x0FFFFFFF, IMAGE_MAGIC];
+    let mut words = words.to_vec();
+    let i = random.below(words.len());
+    match random.below(6) {
+        0 => words[i] ^= 1 << random.below(32),
+        1 => words[i] = INTERESTING[random.below(INTERESTING.len())],
+        2 => words[i] = words[i].wrapping_add(random.below(16) as u32).wrapping_sub(8),
+        3 => {
+            let mut bytes = to_bytes(&words);
+            bytes.truncate(random.below(bytes.len()));
+            return bytes;
+        },
+        4 => words.insert(i, random.next() as u32),
+        _ => { words.remove(i); },
+    }
+    to_bytes(&words)
+}
+
+/**
+ * Read the bytes in every way that an image can be used and return the number of readers which
+ * accepted it. Errors are expected, panics are not.
+ */
+#[allow(clippy::manual_is_multiple_of)]
+fn read_all(bytes: &[u8]) -> usize {
+    let mut accepted = 0;
+    if let Ok(raw_image) = aera::r_code::Image::read(&mut &bytes[..]) {
+        accepted += 1;
+        if let Ok(image) = Image::load(&raw_image) {
+            accepted += 1;
+            image.validate();
+            if let Ok(objects) = image.get_objects() {
+                for object in &objects {
+                    let object = object.borrow();
+                    let _ = (object.code_size(), object.markers().len(), object.views().len());
+                }
+            }
+        }
+    }
+
+    if bytes.len() % 4 == 0 {
+        let words = bytes.chunks(4)
+            .map(|word| u32::from_le_bytes([word[0], word[1], word[2], word[3]])).collect();
+        if let Ok(mapped_image) = MappedImage::from_vec(words) {
+            accepted += 1;
+            for index in 0..mapped_image.get_object_count() as usize {
+                if let Ok(object) = MappedImage::get_object(&mapped_image, index) {
+                    let object = object.borrow();
+                    for i in 0..object.code_size() {
+                        object.code(i);
+                    }
+                    for i in 0..object.references_size() {
+                        object.get_reference(i);
+                    }
+                    let _ = (object.markers().len(), object.views().len());
+                }
+            }
+        }
+    }
+    accepted
+}
+
+fn fuzz(format_version: u32, first_seed: u64) {
+    let words = valid_image(format_version);
+    assert_eq!(read_all(&to_bytes(&words)), 3, "The valid image must be accepted");
+
+    for seed in first_seed..first_seed + MUTANTS_PER_IMAGE {
+        let bytes = mutate(&words, &mut Random::new(seed));
+        if panic::catch_unwind(|| read_all(&bytes)).is_err() {
+            panic!("Reading the mutated image for seed {} panicked", seed);
+        }
+    }
+}
+
+#[test]
+fn mutated_images_with_header() {
+    fuzz(aera::r_code::image::FORMAT_VERSION, 0);
+}
+
+#[test]
+fn mutated_legacy_images() {
+    fuzz(LEGACY_FORMAT_VERSION, 1_000_000);
+}
+
+#[test]
+fn corrupt_size_does_not_allocate() {
+    // A legacy image whose definition segment claims 0xFFFFFFFF words but has none.
+    // A legacy image with a timestamp of 0 and a huge definition segment size.
+    let bytes = to_bytes(&[0, 0, 0xFFFFFFFF, 0, 0, 0]);
+    match aera::r_code::Image::read(&mut &bytes[..]) {
+        Err(error) => assert!(matches!(error.without_offset(), ImageError::SizeLimit { .. })),
+        Ok(_) => panic!("A corrupt size must be rejected"),
+    }
+    match aera::r_code::Image::read_with_limit(&mut &bytes[..], usize::MAX) {
+        Err(error) => {
+            assert!(matches!(error.without_offset(), ImageError::Truncated(_)));
+            assert_eq!(error.byte_offset(), Some(24));
+        },
+        Ok(_) => panic!("A truncated image must be rejected"),
+    }
+}
+
+#[test]
+fn errors_have_byte_offsets() {
+    let mut words = valid_image(LEGACY_FORMAT_VERSION);
+    // Give the first class of the definition segment an unknown type. The class count is at
+    // word 6 after the timestamp and the segment sizes, then the class atom, then the name "fact"
+    // as a length and one word.
+    words[6 + 1 + 1 + 2] = 1000;
+    let raw_image = aera::r_code::Image::read(&mut &to_bytes(&words)[..]).unwrap();
+    match Image::load(&raw_image) {
+        Err(error) => assert_eq!(error.byte_offset(), Some(4 * 10)),
+        Ok(_) => panic!("An unknown class type must be rejected"),
+    }
+}
@@ -0,0 +1,86 @@
+//! Check that Image::add_objects follows long chains of references without recursion, and that
+//! it returns an error and leaves the image unchanged if a reference index is too large. Check
+//! that Image::get_objects and Image::validate report a view with more than two references.
+
+use std::cell::RefCell;
+use std::path::Path;
+use std::rc::Rc;
+use aera::r_code::atom::Atom;
+use aera::r_code::image::ImageError;
+use aera::r_code::{Code, LocalObject, ObjectNames};
+use aera::r_comp::{compile_file, Image};
+
+const GOLDEN: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/golden.replicode");
+
+/**
+ * Return count objects where each object references the next one.
+ */
+fn new_chain(count: usize) -> Vec<Rc<RefCell<dyn Code>>> {
+    let objects: Vec<Rc<RefCell<dyn Code>>> = (0..count).map(|i| {
+        let mut object = LocalObject::default();
+        object.set_oid(i as u32);
+        object.set_code(0, Atom::Object(0, 1));
+        object.set_code(1, Atom::Float(1.0));
+        Rc::new(RefCell::new(object)) as Rc<RefCell<dyn Code>>
+    }).collect();
+    for pair in objects.windows(2) {
+        pair[0].borrow_mut().set_reference(0, &pair[1]);
+    }
+    objects
+}
+
+/**
+ * Break the chain so that dropping the objects doesn't recurse once per object.
+ */
+fn drop_chain(objects: Vec<Rc<RefCell<dyn Code>>>) {
+    for object in &objects {
+        object.borrow_mut().clear_references();
+    }
+}
+
+#[test]
+fn add_long_chain() {
+    let objects = new_chain(60_000);
+    let mut image = Image::default();
+    image.add_objects(&objects[..1], &ObjectNames::new()).unwrap();
+    let sys_objects = &image.code_segment_.objects_;
+    assert_eq!(sys_objects.len(), 60_000);
+    for (i, sys_object) in sys_objects.iter().enumerate() {
+        assert_eq!(sys_object.oid_, i as u32);
+    }
+    assert_eq!(sys_objects[0].references_, vec![1]);
+    assert!(sys_objects[59_999].references_.is_empty());
+    drop_chain(objects);
+}
+
+#[test]
+fn add_too_many_objects() {
+    // Object 65535 references object 65536, whose index doesn't fit in a reference.
+    let objects = new_chain(65_538);
+    let mut image = Image::default();
+    match image.add_objects(&objects[..1], &ObjectNames::new()) {
+        Err(ImageError::Invalid(message)) => assert_eq!(
+            message, "Object 65535 has reference index 65536 which is too large"),
+        _ => panic!("Expected ImageError::Invalid"),
+    }
+    assert!(image.code_segment_.objects_.is_empty());
+    assert!(image.relocation_segment_.entries_.is_empty());
+    drop_chain(objects);
+}
+
+#[test]
+fn get_view_with_extra_references() {
+    let mut image = compile_file(Path::new(GOLDEN)).ok().unwrap();
+    assert!(image.get_objects().is_ok());
+    // Object 1 is self, with a view hosted by root.
+    image.code_segment_.objects_[1].views_[0].references_ = vec![0, 1, 2];
+    match image.get_objects() {
+        Err(ImageError::Invalid(message)) => assert_eq!(
+            message, "Object 1 (OID 1) view 0 has 3 references, but a view only has a host and \
+                      an origin"),
+        _ => panic!("Expected ImageError::Invalid"),
+    }
+    assert_eq!(image.validate(), vec![
+        "Object 1 (OID 1) view 0: There are 3 references, but a view only has a host and an \
+         origin"]);
+}
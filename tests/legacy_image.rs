@@ -0,0 +1,76 @@
+//! Read a legacy image in the layout written by the C++ r_code::Image::Write: the 64-bit
+//! timestamp as two words (low word first), the definition, object map, code and relocation
+//! segment sizes, the data, then the size and words of the object names segment.
+//!
+//! The fixture tests/fixtures/legacy.image was written word by word in this layout, not by the
+//! serializer of this crate. Its definition segment has the classes ent (opcode 0) and ont
+//! (opcode 1), each (class psln_thr:nb), and the operator _now. Its code segment has the objects
+//! self and stdin of class ent and robot of class ont with OIDs 0 to 2 and no views. Its
+//! timestamp is 1700000000000000 microseconds.
+//!
+//! Compatibility with the C++ version is unverified: there is no C++ build to write an image, so
+//! this only checks that the reader follows the layout as read from the C++ source. A fixture
+//! written by the C++ compiler should replace or be added to this one.
+
+use aera::r_code::atom::{self, Atom};
+use aera::r_code::image::LEGACY_FORMAT_VERSION;
+use aera::r_code::MappedImage;
+use aera::r_comp::Image;
+
+const FIXTURE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/legacy.image");
+const TIMESTAMP: u64 = 1_700_000_000_000_000;
+// The OID, name and class of each object.
+const OBJECTS: [(u32, &str, &str); 3] =
+  [(0, "self", "ent"), (1, "stdin", "ent"), (2, "robot", "ont")];
+
+#[test]
+fn read_legacy_image() {
+    let raw_image = aera::r_code::Image::read_file(FIXTURE).unwrap();
+    assert_eq!(raw_image.format_version(), LEGACY_FORMAT_VERSION);
+    assert_eq!(raw_image.timestamp(), TIMESTAMP);
+    assert_eq!(raw_image.get_object_count(), 3);
+
+    let image = Image::load(&raw_image).unwrap();
+    assert_eq!(image.timestamp_.time_since_epoch().as_microseconds() as u64, TIMESTAMP);
+    let definitions = &image.definition_segment_;
+    assert_eq!(definitions.get_opcode("ent"), Some(0));
+    assert_eq!(definitions.get_opcode("ont"), Some(1));
+    assert_eq!(definitions.operator_names_, vec!["_now".to_string()]);
+    assert_eq!(image.get_object_count(), OBJECTS.len());
+    for (object, (oid, name, class)) in image.code_segment_.objects_.iter().zip(OBJECTS.iter()) {
+        assert_eq!(object.oid_, *oid);
+        assert_eq!(image.object_names_.get_name(*oid), Some(*name));
+        assert_eq!(object.code_[0].getDescriptor(), atom::OBJECT);
+        assert_eq!(Some(object.code_[0].asOpcode()), definitions.get_opcode(class));
+        assert_eq!(object.code_[1].atom_, Atom::Float(1.0).atom_);
+    }
+}
+
+#[test]
+fn write_legacy_image_unchanged() {
+    let bytes = std::fs::read(FIXTURE).unwrap();
+    let raw_image = aera::r_code::Image::read(&mut &bytes[..]).unwrap();
+    let mut written = vec![];
+    raw_image.write(&mut written).unwrap();
+    assert_eq!(written, bytes);
+
+    // Decoding and encoding the segments keeps the timestamp.
+    let mut serialized = Image::load(&raw_image).unwrap().serialize();
+    serialized.set_format_version(LEGACY_FORMAT_VERSION);
+    let mut written = vec![];
+    serialized.write(&mut written).unwrap();
+    assert_eq!(written, bytes);
+}
+
+#[test]
+fn map_legacy_image() {
+    let image = MappedImage::open(FIXTURE).unwrap();
+    assert_eq!(image.format_version(), LEGACY_FORMAT_VERSION);
+    assert_eq!(image.timestamp(), TIMESTAMP);
+    assert_eq!(image.get_object_count(), 3);
+    for (i, (oid, _, _)) in OBJECTS.iter().enumerate() {
+        let object = MappedImage::get_object(&image, i).unwrap();
+        assert_eq!(object.borrow().get_oid(), *oid);
+        assert_eq!(object.borrow().code(0).getDescriptor(), atom::OBJECT);
+    }
+}
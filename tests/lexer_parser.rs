@@ -0,0 +1,180 @@
+//! Check the tokens and spans of the lexer, the expressions of the parser and the errors of both
+//! for small pieces of Replicode source.
+
+use aera::r_comp::lexer::{Token, TokenKind};
+use aera::r_comp::parser::MAX_DEPTH;
+use aera::r_comp::{parse, ExpressionKind, Lexer, ParseError, SourceObject};
+
+fn tokenize(text: &str) -> Vec<Token> {
+    Lexer::new("test.replicode", text).tokenize().unwrap()
+}
+
+fn kinds(text: &str) -> Vec<TokenKind> {
+    tokenize(text).into_iter().map(|token| token.kind_).collect()
+}
+
+fn parse_one(text: &str) -> SourceObject {
+    let mut objects = parse("test.replicode", text).unwrap();
+    assert_eq!(objects.len(), 1);
+    objects.remove(0)
+}
+
+/**
+ * Return the error for the source, as "line:column: message".
+ */
+fn parse_error(text: &str) -> String {
+    let error: ParseError = parse("test.replicode", text).unwrap_err();
+    format!("{}:{}: {}", error.span_.line_, error.span_.column_, error.message_)
+}
+
+fn symbol(name: &str) -> TokenKind {
+    TokenKind::Symbol(name.to_string())
+}
+
+#[test]
+fn token_spans() {
+    let text = "; comment\n(mk.val  self\n   position 1.5) |[]";
+    let tokens = tokenize(text);
+    let spans: Vec<(&str, u32, u32)> = tokens.iter()
+        .map(|token| (&text[token.span_.start_..token.span_.end_], token.span_.line_,
+                      token.span_.column_)).collect();
+    assert_eq!(spans, vec![
+        ("(", 2, 1), ("mk.val", 2, 2), ("self", 2, 10), ("position", 3, 4), ("1.5", 3, 13),
+        (")", 3, 16), ("|[]", 3, 18)]);
+    assert_eq!(tokens.iter().map(|token| token.is_joined_).collect::<Vec<_>>(),
+               vec![false, true, false, false, false, true, false]);
+    assert_eq!(tokens.iter().map(|token| token.starts_line_).collect::<Vec<_>>(),
+               vec![true, false, false, true, false, false, false]);
+
+    // Columns count characters, not bytes.
+    let columns: Vec<u32> =
+      tokenize("(ent \"é\" x)\n\"ü\" y").iter().map(|token| token.span_.column_).collect();
+    assert_eq!(columns, vec![1, 2, 6, 10, 11, 1, 5]);
+
+    // The span of a structure goes from ( to ) and its line and column are those of (.
+    let object = parse_one(text);
+    assert_eq!(&text[object.expression_.span_.start_..object.expression_.span_.end_],
+               "(mk.val  self\n   position 1.5)");
+    assert_eq!((object.expression_.span_.line_, object.expression_.span_.column_), (2, 1));
+    assert_eq!(&text[object.span_.start_..object.span_.end_],
+               "(mk.val  self\n   position 1.5) |[]");
+}
+
+#[test]
+fn token_kinds() {
+    assert_eq!(kinds("p: :nb : :: |nb |[] !class 12 100ms 1s:200ms:0us \"a\\\"b\" -1 nil"), vec![
+        TokenKind::Label("p".to_string()), TokenKind::Type("nb".to_string()),
+        TokenKind::Wildcard, TokenKind::TailWildcard, TokenKind::Undefined("nb".to_string()),
+        TokenKind::EmptySet, TokenKind::Directive("class".to_string()), TokenKind::Number(12.0),
+        TokenKind::Duration(100_000), TokenKind::Timestamp(1_200_000),
+        TokenKind::String("a\"b".to_string()), TokenKind::Number(-1.0), symbol("nil")]);
+    assert_eq!(kinds(":~ :[]"),
+               vec![TokenKind::Type("~".to_string()), TokenKind::Type("[]".to_string())]);
+}
+
+#[test]
+fn indented_sets() {
+    let text = "(ent 1)\n[]\n   [SYNC_ONCE 0 1]\n   []\n      a\n      b\n   [c]\n(ent 2) |[]\n";
+    assert_eq!(kinds(text), vec![
+        TokenKind::LeftParen, symbol("ent"), TokenKind::Number(1.0), TokenKind::RightParen,
+        TokenKind::LeftBracket,
+        TokenKind::LeftBracket, symbol("SYNC_ONCE"), TokenKind::Number(0.0),
+        TokenKind::Number(1.0), TokenKind::RightBracket,
+        TokenKind::LeftBracket, symbol("a"), symbol("b"), TokenKind::RightBracket,
+        TokenKind::LeftBracket, symbol("c"), TokenKind::RightBracket,
+        TokenKind::RightBracket,
+        TokenKind::LeftParen, symbol("ent"), TokenKind::Number(2.0), TokenKind::RightParen,
+        TokenKind::EmptySet]);
+
+    let objects = parse("test.replicode", text).unwrap();
+    assert_eq!(objects.len(), 2);
+    assert_eq!(objects[0].views_.len(), 3);
+    assert_eq!(objects[0].views_[1].get_members().len(), 2);
+    assert!(objects[1].views_.is_empty());
+
+    // Sets which are still open at the end of the source are closed.
+    assert_eq!(parse_one("(ent 1)\n[]\n   []\n      a").views_.len(), 1);
+    // A comment after the trailing [] doesn't stop it from opening a set.
+    assert_eq!(parse_one("(ent 1)\n[] ; views\n   [a]\n   [b]").views_.len(), 2);
+}
+
+#[test]
+fn labels_and_variables() {
+    let object = parse_one("p:(pgm x: (ptn y:(mk.val : : v: :) |[]) [] 1)");
+    assert_eq!(object.get_label(), Some("p"));
+    let members = object.expression_.get_members();
+    // "x: " before white space is a variable.
+    assert!(members[0].is_variable());
+    assert_eq!(members[0].label_.as_deref(), Some("x"));
+    // "y:(" labels the structure.
+    let labeled = &members[1].get_members()[0];
+    assert_eq!(labeled.label_.as_deref(), Some("y"));
+    assert_eq!(labeled.get_head(), Some("mk.val"));
+    assert!(!labeled.is_variable());
+    assert!(labeled.get_members()[2].is_variable());
+    // A variable just before ) or ] is not a label of the ) or ].
+    let object = parse_one("(ent v:)");
+    assert!(object.expression_.get_members()[0].is_variable());
+    let object = parse_one("(ent [v:])");
+    assert!(object.expression_.get_members()[0].get_members()[0].is_variable());
+    assert_eq!(parse_error("(ent a:b:1)"), "1:8: An expression can't have two labels");
+}
+
+#[test]
+fn expression_kinds() {
+    let object = parse_one("(ent true false nil |[] [] \"s\" 2s |ts ::)");
+    let kinds: Vec<&ExpressionKind> =
+      object.expression_.get_members().iter().map(|member| &member.kind_).collect();
+    assert_eq!(kinds, vec![
+        &ExpressionKind::Boolean(true), &ExpressionKind::Boolean(false), &ExpressionKind::Nil,
+        &ExpressionKind::Set(vec![]), &ExpressionKind::Set(vec![]),
+        &ExpressionKind::String("s".to_string()), &ExpressionKind::Duration(2_000_000),
+        &ExpressionKind::Undefined("ts".to_string()), &ExpressionKind::TailWildcard]);
+}
+
+#[test]
+fn lexer_errors() {
+    assert_eq!(parse_error("(ent \"abc\n)"), "1:6: The string is not terminated");
+    assert_eq!(parse_error("(ent 1.2.3)"), "1:6: Invalid number 1.2.3");
+    assert_eq!(parse_error("(ent 1s:2x)"), "1:6: Invalid timestamp 1s:2x");
+    assert_eq!(parse_error("(ent 99999999999999999999s)"),
+               "1:6: Out of range time 99999999999999999999s");
+    assert_eq!(parse_error("(ent {a})"), "1:6: Unexpected character {");
+    assert_eq!(parse_error("(ent | 1)"), "1:6: Expected a type name or [] after |");
+}
+
+#[test]
+fn parser_errors() {
+    assert_eq!(parse_error("(ent 1"), "1:1: The ( is not closed");
+    assert_eq!(parse_error("(ent [1 2)"), "1:10: Unexpected )");
+    assert_eq!(parse_error("(ent\n  [1 2"), "2:3: The [ is not closed");
+    assert_eq!(parse_error("(1 2)"),
+               "1:2: Expected a class, operator or function name after ( but got 1");
+    assert_eq!(parse_error("(ent 1))"), "1:8: Unexpected )");
+    assert_eq!(parse_error("[a b]"),
+               "1:1: Expected an object like (class member...) at the top level");
+    assert_eq!(parse_error("(ent 1) [[a] b]"),
+               "1:14: Expected a view like [member...] in the set of views");
+    assert_eq!(parse_error("(ent !class)"),
+               "1:6: The directive !class must be handled by the preprocessor");
+    assert_eq!(parse_error("p:("), "1:4: Unexpected end of the source");
+}
+
+#[test]
+fn nesting_depth() {
+    let nested = |depth: usize| format!("{}{}", "(ent ".repeat(depth), ")".repeat(depth));
+    assert!(parse("test.replicode", &nested(MAX_DEPTH)).is_ok());
+    assert_eq!(parse_error(&nested(MAX_DEPTH + 1)),
+               format!("1:{}: The expression is nested more than {} deep", 5 * MAX_DEPTH + 1,
+                       MAX_DEPTH));
+
+    // Sets count toward the depth, and deep nesting doesn't overflow the stack.
+    let nested_sets = format!("(ent {}{})", "[".repeat(100_000), "]".repeat(100_000));
+    assert_eq!(parse_error(&nested_sets), format!(
+        "1:{}: The expression is nested more than {} deep", 5 + MAX_DEPTH, MAX_DEPTH));
+
+    // A chain of labels is an error at the second label, without parsing the rest of the chain.
+    let labels: String = (0..200_000).map(|i| format!("a{}:", i)).collect();
+    assert_eq!(parse_error(&format!("(fact {}1)", labels)),
+               "1:10: An expression can't have two labels");
+}
@@ -0,0 +1,111 @@
+//! Check that Image::merge and link unify the class tables of images compiled with different
+//! definitions, give the merged objects new OIDs when theirs are used, link named objects to the
+//! object with the same name, and leave the image unchanged on error.
+
+use std::path::Path;
+use aera::r_code::image::ImageError;
+use aera::r_comp::{link, Compiler, Image, Preprocessor};
+
+const FIXTURES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures");
+
+// The objects self (OID 0) and hand (OID 1), and a marker of hand (OID 2).
+const FIRST: &str = "\
+!load std.replicode
+self:(ent 1) |[]
+hand:(ent 1) |[]
+(mk.val hand self 1 1) |[]
+";
+// The class place comes before the std classes, so each std class has the next opcode. hand has
+// OID 0, kitchen OID 1 and the marker OID 2.
+const SECOND: &str = "\
+!class (place x:nb psln_thr:nb)
+!load std.replicode
+hand:(ent 1) |[]
+kitchen:(place 3 1) |[]
+(mk.val hand kitchen 1 1) |[]
+";
+
+fn compile_text(text: &str) -> Image {
+    let mut preprocessor = Preprocessor::new();
+    preprocessor.process_text("test.replicode", text, Path::new(FIXTURES)).unwrap();
+    let objects = preprocessor.parse_objects().unwrap();
+    Compiler::new(preprocessor.get_definition_segment()).compile_image(&objects).ok().unwrap()
+}
+
+fn get_opcode(image: &Image, class_name: &str) -> u16 {
+    image.definition_segment_.classes_by_opcodes_.iter()
+        .position(|class| class.str_opcode_ == class_name).unwrap() as u16
+}
+
+/**
+ * Return the bytes of the serialized image, to check that it is not changed.
+ */
+fn image_bytes(image: &mut Image) -> Vec<u8> {
+    let mut bytes = vec![];
+    image.serialize().write(&mut bytes).unwrap();
+    bytes
+}
+
+#[test]
+fn merge_different_classes() {
+    let mut image = compile_text(FIRST);
+    let second = compile_text(SECOND);
+    assert_eq!(get_opcode(&second, "place"), 0);
+    assert_eq!(get_opcode(&second, "mk.val"), get_opcode(&image, "mk.val") + 1);
+    let class_count = image.definition_segment_.classes_by_opcodes_.len();
+
+    image.merge(&second).unwrap();
+    assert!(image.validate().is_empty(), "{:?}", image.validate());
+    // place is appended, and the std classes keep the opcodes of the first image.
+    assert_eq!(image.definition_segment_.classes_by_opcodes_.len(), class_count + 1);
+    assert_eq!(get_opcode(&image, "place") as usize, class_count);
+    let objects = &image.code_segment_.objects_;
+    // hand is linked, so only kitchen and the marker are added, with the next unused OIDs.
+    assert_eq!(objects.iter().map(|object| object.oid_).collect::<Vec<_>>(), vec![0, 1, 2, 3, 4]);
+    assert_eq!(image.object_names_.iter().collect::<Vec<_>>(),
+               vec![(0, "self"), (1, "hand"), (3, "kitchen")]);
+    assert_eq!(objects[3].code_[0].asOpcode() as usize, class_count);
+    assert_eq!(objects[4].code_[0].asOpcode(), get_opcode(&image, "mk.val"));
+    // The marker of the second image references hand of the first image and kitchen.
+    assert_eq!(objects[4].references_, vec![1, 3]);
+    assert_eq!(objects[1].markers_, vec![2, 4]);
+    assert_eq!(objects[3].markers_, vec![4]);
+}
+
+#[test]
+fn link_images() {
+    let images = [compile_text(FIRST), compile_text(SECOND), compile_text(FIRST)];
+    let linked = link(&images).unwrap();
+    // The named objects of the third image are linked, and its marker gets OID 5.
+    assert_eq!(linked.get_object_count(), 6);
+    assert_eq!(linked.code_segment_.objects_[5].oid_, 5);
+    assert_eq!(linked.code_segment_.objects_[5].references_, vec![1, 0]);
+    assert_eq!(linked.code_segment_.objects_[1].markers_, vec![2, 4, 5]);
+    assert!(linked.validate().is_empty());
+}
+
+#[test]
+fn merge_unchanged_on_error() {
+    let mut image = compile_text(FIRST);
+    let before = image_bytes(&mut image);
+
+    // ent has a different definition.
+    let other = compile_text("!class (ent x:nb psln_thr:nb)\nrobot:(ent 2 1) |[]\n");
+    match image.merge(&other) {
+        Err(ImageError::Invalid(message)) =>
+            assert_eq!(message, "The class ent has a different definition in each image"),
+        _ => panic!("Expected ImageError::Invalid"),
+    }
+    assert_eq!(image_bytes(&mut image), before);
+
+    // hand is a grp in the other image. The class place would be appended before the error.
+    let other = compile_text(
+      "!class (place x:nb psln_thr:nb)\n!load std.replicode\nhand:(grp 1 0.5 0 0 1) |[]\n");
+    match image.merge(&other) {
+        Err(ImageError::Invalid(message)) =>
+            assert_eq!(message, "The object named hand has a different class in each image"),
+        _ => panic!("Expected ImageError::Invalid"),
+    }
+    assert_eq!(image_bytes(&mut image), before);
+    assert!(link(&[compile_text(FIRST), other]).is_err());
+}
@@ -0,0 +1,81 @@
+//! Check that MappedImage::get_object only checks the object which it gets, and that a
+//! MappedObject returns the error of a referenced object which is not valid from load_reference,
+//! or resolves it to an invalidated object through the Code trait, instead of panicking.
+
+use std::path::Path;
+use aera::r_code::code::same_object;
+use aera::r_code::image::ImageError;
+use aera::r_code::{Code, MappedImage};
+use aera::r_comp::compile_file;
+
+const GOLDEN: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/golden.replicode");
+// The indexes of objects in golden.replicode. The fact f references hand and has a view with
+// the host root and the origin self.
+const ROOT: usize = 0;
+const HAND: usize = 2;
+const FACT: usize = 3;
+
+/**
+ * Return the words of the image of golden.replicode, with a header, where the code of hand is
+ * changed so that it doesn't match its checksum.
+ */
+fn corrupt_image() -> Vec<u32> {
+    let raw_image = compile_file(Path::new(GOLDEN)).ok().unwrap().serialize();
+    let mut bytes = vec![];
+    raw_image.write(&mut bytes).unwrap();
+    let mut words: Vec<u32> = bytes.chunks(4)
+        .map(|word| u32::from_le_bytes([word[0], word[1], word[2], word[3]])).collect();
+    // The object starts with its OID and 4 sizes. code(1) of (ent 1) is the 1.
+    let hand_offset = raw_image.get_map_segment()[HAND] as usize;
+    words[raw_image.get_segment_offset(0) + hand_offset + 6] ^= 1;
+    words
+}
+
+fn is_checksum_mismatch(error: &ImageError, index: usize) -> bool {
+    match error.without_offset() {
+        ImageError::ChecksumMismatch { object_index, oid, .. } =>
+            *object_index == Some(index) && *oid == Some(index as u32),
+        _ => false,
+    }
+}
+
+#[test]
+fn get_objects_lazily() {
+    let image = MappedImage::from_vec(corrupt_image()).unwrap();
+    // The fact is valid, even though hand which it references is not.
+    let fact = MappedImage::get_mapped_object(&image, FACT).unwrap();
+    let error = MappedImage::get_object(&image, HAND).err().unwrap();
+    assert!(is_checksum_mismatch(&error, HAND), "{}", error);
+
+    let fact = fact.borrow();
+    let error = fact.load_reference(0).err().unwrap();
+    assert!(is_checksum_mismatch(&error, HAND), "{}", error);
+    assert!(fact.load_markers().unwrap().is_empty());
+    let views = fact.load_views().unwrap();
+    assert_eq!(views.len(), 1);
+    let root = MappedImage::get_object(&image, ROOT).unwrap();
+    assert!(same_object(&views[0].get_host().unwrap(), &root));
+}
+
+#[test]
+fn resolve_invalid_reference() {
+    let image = MappedImage::from_vec(corrupt_image()).unwrap();
+    let fact = MappedImage::get_mapped_object(&image, FACT).unwrap();
+    assert!(fact.borrow().try_get_reference(0).is_none());
+
+    // The Code trait can't return the error, so hand is an invalidated object with no code.
+    let hand = fact.borrow().get_reference(0);
+    assert!(same_object(&hand, &fact.borrow().get_reference(0)));
+    assert!(hand.borrow().is_invalidated());
+    assert_eq!(hand.borrow().get_oid(), HAND as u32);
+    assert_eq!(hand.borrow().code_size(), 0);
+    assert_eq!(hand.borrow().references_size(), 0);
+    // get_object still returns the error.
+    assert!(MappedImage::get_object(&image, HAND).is_err());
+
+    // Modifying the fact copies it with the invalidated reference.
+    let cfd = fact.borrow().code(4);
+    fact.borrow_mut().set_code(4, cfd);
+    assert!(fact.borrow().is_materialized());
+    assert!(same_object(&fact.borrow().get_reference(0), &hand));
+}
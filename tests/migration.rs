@@ -0,0 +1,123 @@
+//! Check that Image::migrate remaps the opcodes of classes, operators and device functions when
+//! definitions are inserted before the std definitions, and leaves the image unchanged if a
+//! definition is missing. Check the OpcodeMap of two definition segments.
+
+use std::path::Path;
+use aera::core::UTimestamp;
+use aera::r_code::atom::Atom;
+use aera::r_code::image::ImageError;
+use aera::r_comp::segments::DefinitionSegment;
+use aera::r_comp::{compile_file, decompile_image, Image, OpcodeMap, Preprocessor};
+
+const FIXTURES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures");
+const GOLDEN: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/golden.replicode");
+
+// A class, an operator and a device function before the std definitions, so that each std
+// definition has the next opcode.
+const INSERTED: &str = "\
+!class (place x:nb psln_thr:nb)
+!op (_later):ts
+!dfn (move :st)
+!load std.replicode
+";
+
+fn load_definitions(text: &str) -> DefinitionSegment {
+    let mut preprocessor = Preprocessor::new();
+    preprocessor.process_text("test.replicode", text, Path::new(FIXTURES)).unwrap();
+    preprocessor.get_definition_segment().clone()
+}
+
+fn get_opcode(definition_segment: &DefinitionSegment, class_name: &str) -> u16 {
+    definition_segment.classes_by_opcodes_.iter()
+        .position(|class| class.str_opcode_ == class_name).unwrap() as u16
+}
+
+/**
+ * Return the bytes of the serialized image, to check that it is not changed.
+ */
+fn image_bytes(image: &mut Image) -> Vec<u8> {
+    let mut bytes = vec![];
+    image.serialize().write(&mut bytes).unwrap();
+    bytes
+}
+
+#[test]
+fn migrate_after_inserting_definitions() {
+    let mut image = compile_file(Path::new(GOLDEN)).ok().unwrap();
+    let source = decompile_image(&image, UTimestamp::default()).unwrap();
+    let definitions = load_definitions(INSERTED);
+
+    image.migrate(&definitions).unwrap();
+    assert!(image.validate().is_empty(), "{:?}", image.validate());
+    assert_eq!(get_opcode(&image.definition_segment_, "place"), 0);
+    let objects = &image.code_segment_.objects_;
+    // root is a grp with a grp_view, and hand is an ent.
+    assert_eq!(objects[0].code_[0].asOpcode(), get_opcode(&definitions, "grp"));
+    assert_eq!(objects[0].views_[0].code_[0].asOpcode(), get_opcode(&definitions, "grp_view"));
+    assert_eq!(objects[2].code_[0].asOpcode(), get_opcode(&definitions, "ent"));
+    // The cmd has the device function speak, which is now after move.
+    assert_eq!(objects[5].code_[1].atom_, Atom::DeviceFunction(1).atom_);
+    // The names of the classes, operators and device functions are the same, and the data words
+    // of timestamps and strings are not remapped.
+    assert_eq!(decompile_image(&image, UTimestamp::default()).unwrap(), source);
+}
+
+#[test]
+fn migrate_unchanged_on_error() {
+    let mut image = compile_file(Path::new(GOLDEN)).ok().unwrap();
+    let before = image_bytes(&mut image);
+    // std.replicode without mk.val and speak.
+    let std = std::fs::read_to_string(Path::new(FIXTURES).join("std.replicode")).unwrap();
+    let definitions = load_definitions(&std.lines()
+        .filter(|line| !line.starts_with("!class (mk.val") && !line.starts_with("!dfn"))
+        .collect::<Vec<_>>().join("\n"));
+
+    match image.migrate(&definitions) {
+        Err(ImageError::MissingDefinitions(descriptions)) => assert_eq!(descriptions, vec![
+          "class mk.val used by OID 3, 4".to_string(),
+          "device function speak used by OID 5".to_string()]),
+        _ => panic!("Expected ImageError::MissingDefinitions"),
+    }
+    assert_eq!(image_bytes(&mut image), before);
+}
+
+#[test]
+fn map_opcodes() {
+    let from = load_definitions("!load std.replicode\n");
+    let to = load_definitions(INSERTED);
+    assert!(OpcodeMap::new(&from, &from).is_identity());
+    let opcode_map = OpcodeMap::new(&from, &to);
+    assert!(!opcode_map.is_identity());
+
+    let fact = get_opcode(&from, "fact");
+    let new_fact = get_opcode(&to, "fact");
+    assert_eq!(new_fact, fact + 1);
+    let remap = |a: Atom| opcode_map.remap_atom(a).ok().unwrap().atom_;
+    assert_eq!(remap(Atom::Object(fact, 5)), Atom::Object(new_fact, 5).atom_);
+    assert_eq!(remap(Atom::SSet(fact, 2)), Atom::SSet(new_fact, 2).atom_);
+    assert_eq!(remap(Atom::Operator(0, 0)), Atom::Operator(1, 0).atom_);
+    assert_eq!(remap(Atom::DeviceFunction(0)), Atom::DeviceFunction(1).atom_);
+    assert_eq!(remap(Atom::CodeVLPointer_cast_opcode(3, fact)),
+               Atom::CodeVLPointer_cast_opcode(3, new_fact).atom_);
+    // Atoms without an opcode are unchanged.
+    for a in [Atom::Wildcard(), Atom::CodeVLPointer(3), Atom::UndefinedDeviceFunction(),
+              Atom::Float(2.0), Atom::RPointer(1)] {
+        assert_eq!(remap(a), a.atom_);
+    }
+
+    // The opcodes which are not in the old definition segment are reported.
+    let missing = OpcodeMap::new(&to, &from);
+    match missing.remap_atom(Atom::Object(0, 2)) {
+        Err(ImageError::Invalid(message)) =>
+            assert_eq!(message, "The class place is not in the new definition segment"),
+        _ => panic!("Expected ImageError::Invalid"),
+    }
+    // The word after the timestamp looks like an object of class place, but it is data.
+    let code = [Atom::Object(0, 1), Atom::Timestamp(), Atom::Object(0, 1), Atom::new(0),
+                Atom::Operator(0, 0), Atom::DeviceFunction(0)];
+    assert_eq!(missing.get_missing(&code),
+               vec!["class place", "operator _later", "device function move"]);
+    assert_eq!(missing.get_missing(&code[1..4]), Vec::<String>::new());
+    assert_eq!(opcode_map.get_missing(&[Atom::Object(100, 1)]),
+               vec!["class opcode 100 (not in the old definition segment)"]);
+}
@@ -0,0 +1,82 @@
+//! Check the lookup of ObjectNames both ways, that Image::add_objects adds the names which it is
+//! given and reports a name used by another OID, and that code::trace_out prints the names.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+use aera::r_code::atom::{self, Atom};
+use aera::r_code::code;
+use aera::r_code::image::ImageError;
+use aera::r_code::{Code, LocalObject, ObjectNames};
+use aera::r_comp::Image;
+
+fn new_object(oid: u32) -> Rc<RefCell<dyn Code>> {
+    let object: Rc<RefCell<dyn Code>> = Rc::new(RefCell::new(LocalObject::default()));
+    object.borrow_mut().set_code(0, Atom::Object(0, 1));
+    object.borrow_mut().set_code(1, Atom::Float(1.0));
+    object.borrow_mut().set_oid(oid);
+    object
+}
+
+#[test]
+fn look_up_names() {
+    let mut names = ObjectNames::new();
+    assert!(names.insert(1, "self"));
+    assert!(names.insert(2, "hand"));
+    assert_eq!(names.get_name(2), Some("hand"));
+    assert_eq!(names.get_oid("self"), Some(1));
+    // Another OID can't have the name, but the same OID can get it again.
+    assert!(!names.insert(3, "hand"));
+    assert!(names.insert(2, "hand"));
+    // A new name replaces the previous name of the OID.
+    assert!(names.insert(2, "arm"));
+    assert_eq!(names.get_oid("hand"), None);
+    assert_eq!(names.iter().collect::<Vec<_>>(), vec![(1, "self"), (2, "arm")]);
+    assert_eq!(names.remove(1), Some("self".to_string()));
+    assert_eq!(names.get_oid("self"), None);
+
+    let mut data = vec![0; names.get_size()];
+    names.write(&mut data);
+    let mut read_names = ObjectNames::new();
+    assert_eq!(read_names.read(&data).unwrap(), data.len());
+    assert_eq!(read_names.iter().collect::<Vec<_>>(), vec![(2, "arm")]);
+}
+
+#[test]
+fn add_named_objects() {
+    let hand = new_object(7);
+    let fact = new_object(8);
+    fact.borrow_mut().set_code(1, Atom::RPointer(0));
+    fact.borrow_mut().set_reference(0, &hand);
+    let mut names = ObjectNames::new();
+    names.insert(7, "hand");
+    // A name of an object which is not added is left out.
+    names.insert(9, "other");
+
+    let mut image = Image::default();
+    image.add_objects(&[Rc::clone(&fact)], &names).unwrap();
+    assert_eq!(image.object_names_.iter().collect::<Vec<_>>(), vec![(7, "hand")]);
+
+    // Adding another object with the name hand fails and doesn't change the image.
+    let other = new_object(10);
+    let mut other_names = ObjectNames::new();
+    other_names.insert(10, "hand");
+    match image.add_objects(&[other], &other_names) {
+        Err(ImageError::Invalid(message)) => assert_eq!(
+            message, "The name hand of OID 10 is already used by OID 7"),
+        _ => panic!("Expected ImageError::Invalid"),
+    }
+    assert_eq!(image.code_segment_.objects_.len(), 2);
+    assert_eq!(image.object_names_.len(), 1);
+
+    // Atom::trace prints the class names.
+    atom::set_opcode_names(&vec![(0, "ent".to_string())].into_iter().collect::<HashMap<_, _>>());
+    let mut out = String::new();
+    code::trace_out(&*fact.borrow(), &mut out, &image.object_names_);
+    let line = out.lines().find(|line| line.contains("-> 7")).unwrap();
+    assert!(line.ends_with(" (hand)"), "{}", line);
+    // Without the names, only the OID is printed.
+    let mut out = String::new();
+    code::trace_out(&*fact.borrow(), &mut out, &ObjectNames::new());
+    assert!(!out.contains("(hand)"));
+}
@@ -0,0 +1,75 @@
+//! Check that the ObjectRegistry gives each object a unique OID, resolves OIDs to live objects
+//! and stops allocating before UNDEFINED_OID.
+
+use std::rc::Rc;
+use std::cell::RefCell;
+use aera::r_code::code::same_object;
+use aera::r_code::object_registry::UNDEFINED_OID;
+use aera::r_code::{Code, LocalObject, ObjectRegistry};
+
+fn new_object(oid: u32) -> Rc<RefCell<dyn Code>> {
+    let object: Rc<RefCell<dyn Code>> = Rc::new(RefCell::new(LocalObject::default()));
+    object.borrow_mut().set_oid(oid);
+    object
+}
+
+#[test]
+fn register_objects() {
+    let mut registry = ObjectRegistry::new();
+    let a = new_object(0);
+    let b = new_object(0);
+    assert_eq!(registry.register(&a), Some(0));
+    assert_eq!(registry.register(&b), Some(1));
+    assert_eq!(b.borrow().get_oid(), 1);
+    assert!(same_object(&registry.get(1).unwrap(), &b));
+    assert!(registry.get(2).is_none());
+
+    // An object loaded with an OID is registered with it, and allocation continues after it.
+    let loaded = new_object(10);
+    assert!(registry.register_with_oid(&loaded));
+    assert!(registry.register_with_oid(&loaded));
+    assert!(!registry.register_with_oid(&new_object(10)));
+    let c = new_object(0);
+    assert_eq!(registry.register(&c), Some(11));
+
+    // A dropped object is no longer resolved, and its OID can be registered again.
+    drop(b);
+    assert!(registry.get(1).is_none());
+    assert_eq!(registry.purge(), 1);
+    assert!(registry.register_with_oid(&new_object(1)));
+    assert!(registry.unregister(1));
+    assert!(!registry.unregister(1));
+}
+
+#[test]
+fn reserve_allocated_oids() {
+    let mut registry = ObjectRegistry::new();
+    let oid = registry.allocate_oid().unwrap();
+    assert_eq!(registry.allocate_oid(), Some(oid + 1));
+
+    // Another object can't be registered with an allocated OID before its object is.
+    let other = new_object(oid);
+    assert!(!registry.register_with_oid(&other));
+    let object = new_object(oid);
+    assert!(registry.register_allocated(&object));
+    assert!(!registry.register_allocated(&other));
+    assert!(!registry.register_allocated(&new_object(100)));
+    assert!(same_object(&registry.get(oid).unwrap(), &object));
+
+    // Releasing an allocated OID lets register_with_oid use it.
+    assert!(registry.unregister(oid + 1));
+    assert!(registry.register_with_oid(&new_object(oid + 1)));
+}
+
+#[test]
+fn stop_before_undefined_oid() {
+    let mut registry = ObjectRegistry::new();
+    assert!(!registry.register_with_oid(&new_object(UNDEFINED_OID)));
+    assert!(!registry.register_with_oid(&new_object(UNDEFINED_OID - 1)));
+    let last = new_object(UNDEFINED_OID - 2);
+    assert!(registry.register_with_oid(&last));
+    assert_eq!(registry.allocate_oid(), Some(UNDEFINED_OID - 1));
+    assert_eq!(registry.allocate_oid(), None);
+    assert_eq!(registry.register(&new_object(0)), None);
+    assert_eq!(registry.allocate_oid(), None);
+}
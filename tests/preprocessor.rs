@@ -0,0 +1,56 @@
+//! Check the classes which the preprocessor defines from tests/fixtures/std.replicode.
+
+use std::path::Path;
+use aera::r_code::atom;
+use aera::r_comp::segments::DefinitionSegment;
+use aera::r_comp::{Compiler, Preprocessor};
+
+const FIXTURES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures");
+
+fn load_std(program: &str) -> Preprocessor {
+    let mut preprocessor = Preprocessor::new();
+    preprocessor.process_text("test.replicode", &format!("!load std.replicode\n{}", program),
+                              Path::new(FIXTURES)).unwrap();
+    preprocessor
+}
+
+/**
+ * Check the head atom of the class and that it is in the sys classes or the classes.
+ */
+fn check_class(definitions: &DefinitionSegment, name: &str, descriptor: u8, arity: u8) {
+    let class = definitions.get_class(name).unwrap();
+    assert_eq!(class.atom_.getDescriptor(), descriptor, "{}", name);
+    assert_eq!(Some(class.atom_.asOpcode()), definitions.get_opcode(name), "{}", name);
+    assert_eq!(class.atom_.getAtomCount(), arity, "{}", name);
+    let is_sys_class = definitions.sys_classes_.iter().any(|(class_name, _)| class_name == name);
+    let is_class = definitions.classes_.iter().any(|(class_name, _)| class_name == name);
+    assert_eq!((is_sys_class, is_class), (descriptor != atom::OBJECT, descriptor == atom::OBJECT),
+               "{}", name);
+    assert!(definitions.class_names_.iter().any(|class_name| class_name == name), "{}", name);
+}
+
+#[test]
+fn system_class_atoms() {
+    let preprocessor = load_std("");
+    let definitions = preprocessor.get_definition_segment();
+    check_class(definitions, "mdl", atom::MODEL, 10);
+    check_class(definitions, "cst", atom::COMPOSITE_STATE, 6);
+    check_class(definitions, "ipgm", atom::INSTANTIATED_PROGRAM, 6);
+    check_class(definitions, "icpp_pgm", atom::INSTANTIATED_CPP_PROGRAM, 6);
+    check_class(definitions, "grp", atom::GROUP, 5);
+    check_class(definitions, "mk.val", atom::MARKER, 4);
+    check_class(definitions, "ent", atom::OBJECT, 1);
+    check_class(definitions, "pgm", atom::OBJECT, 5);
+}
+
+#[test]
+fn compile_system_classes() {
+    let preprocessor = load_std(
+      "(mdl [] [] [] [] 1 2 1 0 1 1) |[]\n(cst [] [] [] [] 1 1) |[]\n(ent 1) |[]");
+    let definitions = preprocessor.get_definition_segment();
+    let objects = preprocessor.parse_objects().unwrap();
+    let compiled = Compiler::new(definitions).compile(&objects).unwrap();
+    let heads: Vec<u8> =
+      compiled.iter().map(|object| object.borrow().code(0).getDescriptor()).collect();
+    assert_eq!(heads, vec![atom::MODEL, atom::COMPOSITE_STATE, atom::OBJECT]);
+}
@@ -0,0 +1,103 @@
+//! Check the parsing of durations with UDuration::from_str and of timestamps with
+//! UTimestamp::parse_relative, in the forms printed by Utils::to_string_us and
+//! Utils::to_string_s_ms_us.
+
+use aera::core::u_duration::{microseconds, seconds, ParseTimeError};
+use aera::core::{UDuration, UTimestamp};
+use aera::r_code::Utils;
+
+fn parse(text: &str) -> Result<i64, ParseTimeError> {
+    text.parse::<UDuration>().map(|duration| duration.as_microseconds())
+}
+
+fn invalid(text: &str) -> Result<i64, ParseTimeError> {
+    Err(ParseTimeError::Invalid(text.to_string()))
+}
+
+fn overflow(text: &str) -> Result<i64, ParseTimeError> {
+    Err(ParseTimeError::Overflow(text.to_string()))
+}
+
+#[test]
+fn parse_durations() {
+    assert_eq!(parse("150us"), Ok(150));
+    assert_eq!(parse("20ms"), Ok(20_000));
+    assert_eq!(parse("3s"), Ok(3_000_000));
+    assert_eq!(parse("0us"), Ok(0));
+    // Combinations with decreasing units, which can skip a unit.
+    assert_eq!(parse("1s:200ms:0us"), Ok(1_200_000));
+    assert_eq!(parse("1s:20ms"), Ok(1_020_000));
+    assert_eq!(parse("2ms:5us"), Ok(2_005));
+    assert_eq!(parse("1s:999us"), Ok(1_000_999));
+    // The first part can be 1000 or more.
+    assert_eq!(parse("1500ms:10us"), Ok(1_500_010));
+}
+
+#[test]
+fn parse_negative_durations() {
+    assert_eq!(parse("-150us"), Ok(-150));
+    assert_eq!(parse("-1s:200ms:0us"), Ok(-1_200_000));
+    assert_eq!(parse("-0s:0ms:5us"), Ok(-5));
+    assert_eq!(parse("-0us"), Ok(0));
+    assert_eq!(parse("-9223372036854775808us"), Ok(i64::MIN));
+}
+
+#[test]
+fn parse_overflowing_durations() {
+    assert_eq!(parse("9223372036854775807us"), Ok(i64::MAX));
+    assert_eq!(parse("9223372036854775808us"), overflow("9223372036854775808us"));
+    assert_eq!(parse("-9223372036854775809us"), overflow("-9223372036854775809us"));
+    assert_eq!(parse("9223372036855s"), overflow("9223372036855s"));
+    assert_eq!(parse("9223372036854s:775ms:808us"), overflow("9223372036854s:775ms:808us"));
+    // More digits than an i128 is an overflow, not an invalid form.
+    let digits = format!("{}s", "9".repeat(40));
+    assert_eq!(parse(&digits), overflow(&digits));
+}
+
+#[test]
+fn parse_malformed_durations() {
+    for text in &["", "-", "s", "10", "10 s", " 10s", "10sec", "1.5s", "+1s", "--1s", "1s:",
+                  ":1s", "1s::2ms", "1ms:2s", "1s:1s", "1s:1000ms", "1s:-2ms", "1h", "1S"] {
+        assert_eq!(parse(text), invalid(text));
+    }
+    assert_eq!(ParseTimeError::Invalid("1h".to_string()).to_string(), "Invalid time \"1h\"");
+    assert_eq!(ParseTimeError::Overflow("1s".to_string()).to_string(),
+               "The time \"1s\" is out of range");
+}
+
+#[test]
+fn parse_printed_durations() {
+    for &us in &[0, 1, 999, 1_000, 1_001, 20_000, 3_000_000, 1_200_000, -150, -1_000_999,
+                 i64::MAX, i64::MIN + 1] {
+        let duration = microseconds(us);
+        assert_eq!(Utils::to_string_us(duration).parse::<UDuration>(), Ok(duration));
+        let text = Utils::to_string_s_ms_us(UTimestamp::from_duration(duration),
+                                            UTimestamp::default());
+        assert_eq!(text.parse::<UDuration>(), Ok(duration), "{}", text);
+    }
+}
+
+#[test]
+fn parse_relative_timestamps() {
+    let time_reference = UTimestamp::from_duration(seconds(100));
+    let parse_relative = |text: &str| UTimestamp::parse_relative(text, time_reference)
+        .map(|timestamp| timestamp.time_since_epoch().as_microseconds());
+    assert_eq!(parse_relative("0s:0ms:0us"), Ok(100_000_000));
+    assert_eq!(parse_relative("1s:200ms:0us"), Ok(101_200_000));
+    assert_eq!(parse_relative("-1s:200ms:0us"), Ok(98_800_000));
+    assert_eq!(parse_relative("-100s"), Ok(0));
+    assert_eq!(parse_relative("250us"), Ok(100_000_250));
+    assert_eq!(parse_relative("1s:2s"), invalid("1s:2s"));
+    assert_eq!(parse_relative("now"), invalid("now"));
+    // The duration fits in an i64 but the timestamp doesn't.
+    assert_eq!(parse_relative("9223372036854775807us"), overflow("9223372036854775807us"));
+    assert_eq!(UTimestamp::parse_relative(
+        "-1us", UTimestamp::from_duration(microseconds(i64::MIN))),
+        overflow("-1us").map(|_| UTimestamp::default()));
+
+    // A timestamp printed relative to the time reference parses back to the same timestamp.
+    let timestamp = UTimestamp::from_duration(microseconds(12_345_678));
+    let text = Utils::to_string_s_ms_us(timestamp, time_reference);
+    assert_eq!(text, "-87s:654ms:322us");
+    assert_eq!(UTimestamp::parse_relative(&text, time_reference), Ok(timestamp));
+}
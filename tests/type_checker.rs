@@ -0,0 +1,112 @@
+//! Check that the TypeChecker accepts members which have the types of their class definitions in
+//! tests/fixtures/std.replicode and rejects members which don't, in parsed source and in compiled
+//! code, including variables and the members of views.
+
+use std::path::Path;
+use aera::r_code::atom::Atom;
+use aera::r_comp::{Compiler, Image, Preprocessor, TypeChecker};
+
+const FIXTURES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures");
+
+const OBJECTS: &str = "root:(grp 1 0.5 0 0 1) |[]\nhand:(ent 1) |[]\n";
+
+fn load_std(program: &str) -> Preprocessor {
+    let mut preprocessor = Preprocessor::new();
+    preprocessor.process_text("test.replicode",
+                              &format!("!load std.replicode\n{}{}", OBJECTS, program),
+                              Path::new(FIXTURES)).unwrap();
+    preprocessor
+}
+
+/**
+ * Check the types of the source objects and return the message of each diagnostic with its
+ * line, column and the messages of its secondary labels.
+ */
+fn check_source(program: &str) -> Vec<String> {
+    let preprocessor = load_std(program);
+    let objects = preprocessor.parse_objects().unwrap();
+    TypeChecker::new(preprocessor.get_definition_segment()).check_objects(&objects).iter()
+        .map(|diagnostic| {
+            let mut message = format!("{}:{}: {}", diagnostic.span_.line_, diagnostic.span_.column_,
+                                      diagnostic.message_);
+            for label in &diagnostic.secondary_ {
+                message += &format!(" ({}:{}: {})", label.span_.line_, label.span_.column_,
+                                    label.message_);
+            }
+            message
+        }).collect()
+}
+
+/**
+ * Compile the program, which must have no type errors, into an image. The objects of OBJECTS
+ * are objects 0 and 1 of the image.
+ */
+fn compile(program: &str) -> Image {
+    let preprocessor = load_std(program);
+    let objects = preprocessor.parse_objects().unwrap();
+    let image = Compiler::new(preprocessor.get_definition_segment()).compile_image(&objects)
+        .unwrap();
+    assert_eq!(image.check_types(), Vec::<String>::new());
+    image
+}
+
+#[test]
+fn check_source_members() {
+    // A timestamp is accepted for after:ts, and a group for the host:grp of a view.
+    assert_eq!(check_source(
+      "(fact (mk.val hand 1 0.5 1) 0s:0ms:0us 1s:0ms:0us 1 1) [[SYNC_ONCE now 1 1 root nil]]"),
+      Vec::<String>::new());
+
+    // A number is rejected for after:ts, and an ent for the host:grp of a view.
+    assert_eq!(check_source(
+      "(fact (mk.val hand 1 0.5 1) 1 1s:0ms:0us 1 1) [[SYNC_ONCE now 1 1 hand nil]]"), vec![
+        "4:29: The member after of fact has type ts, but the value has type nb",
+        "4:67: The member host of view has type grp, but the value has type ent"]);
+}
+
+#[test]
+fn check_source_variables() {
+    // The variable t: gets type ts from after:ts, and before:ts accepts it.
+    assert_eq!(check_source(
+      "(mdl [(fact (mk.val hand 1 v: 1) t: t 1 1)] [] [] [] 1 1 1 0 1 1) |[]"),
+      Vec::<String>::new());
+
+    // cfd:nb rejects it.
+    assert_eq!(check_source(
+      "(mdl [(fact (mk.val hand 1 v: 1) t: t t 1)] [] [] [] 1 1 1 0 1 1) |[]"), vec![
+        "4:39: The member cfd of fact has type nb, but the variable t has type ts \
+         (4:34: t has type ts from this use)"]);
+}
+
+#[test]
+fn check_compiled_members() {
+    let mut image = compile(
+      "(fact (mk.val hand 1 0.5 1) 0s:0ms:0us 1s:0ms:0us 1 1) [[SYNC_ONCE now 1 1 root nil]]");
+    let fact = &mut image.code_segment_.objects_[2];
+    // after:ts is an I_PTR to a timestamp. Replace it with a number.
+    assert_eq!(fact.code_[2].atom_, Atom::IPointer(11).atom_);
+    fact.code_[2] = Atom::Float(1.0);
+    // Make the view host the object hand instead of the group root.
+    assert_eq!(fact.views_[0].references_, vec![0]);
+    fact.views_[0].references_ = vec![1];
+    assert_eq!(image.check_types(), vec![
+        "Object 2 (OID 2): At code(2), the member after of fact has type ts, but the value has \
+         type nb",
+        "Object 2 (OID 2) view 0: At code(5), the member host of view has type grp, but the value \
+         has type ent"]);
+}
+
+#[test]
+fn check_compiled_variables() {
+    let mut image = compile(
+      "(mdl [(fact (mk.val hand 1 v: 1) t: t 1 1)] [] [] [] 1 1 1 0 1 1) |[]");
+    let model = &mut image.code_segment_.objects_[2];
+    // The fact is at 13, with after and before at 15 and 16, and cfd at 17. v is VL_PTR 0 and t
+    // is VL_PTR 1.
+    assert_eq!(model.code_[15].atom_, Atom::VLPointer(1).atom_);
+    assert_eq!(model.code_[16].atom_, Atom::VLPointer(1).atom_);
+    model.code_[17] = Atom::VLPointer(1);
+    assert_eq!(image.check_types(), vec![
+        "Object 2 (OID 2): At code(17), the member cfd of fact has type nb, but the variable v1 \
+         has type ts from code(15)"]);
+}